@@ -0,0 +1,304 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+use crate::board::{Board, ChessMove};
+use crate::json::{self, Value};
+use crate::logging;
+use crate::monitor::score_json;
+use crate::search::{Searcher, SearchInfo, SearchLimits};
+use crate::table::Bound;
+use crate::uci;
+
+// Analysis over HTTP, for tools that would rather not drive the engine over UCI. Two endpoints,
+// both taking and giving back JSON:
+//
+//   POST /analyse {"fen": "...", "depth": 8}             or "movetime" in ms instead of depth,
+//                                                         and "multipv" for more than one line
+//     -> {"fen":"...","bestmove":"e2e4","depth":8,"nodes":1234,"time_ms":56,
+//         "lines":[{"multipv":1,"score":{"cp":31},"pv":["e2e4","e7e5"]}]}
+//
+//   POST /legal-moves {"fen": "..."}
+//     -> {"fen":"...","moves":["a2a3","a2a4",...]}
+//
+// The lines are from the deepest iteration that finished. "bestmove" is what the engine would
+// play, which can come from part way through the next one when a movetime runs out.
+//
+// Anything wrong comes back as {"error":"..."} with a 4xx status. Requests are answered one at a
+// time, on one searcher, and every connection is closed after its response.
+
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:8081";
+
+// request bodies are a fen and a couple of numbers, anything much bigger is a mistake
+const MAX_BODY: usize = 64 * 1024;
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+impl Response {
+    fn ok(body: String) -> Response {
+        Response { status: 200, body }
+    }
+
+    fn error(status: u16, message: &str) -> Response {
+        Response { status, body: format!(r#"{{"error":{}}}"#, json::string(message)) }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            413 => "Payload Too Large",
+            422 => "Unprocessable Entity",
+            _ => "Internal Server Error",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Api {
+    searcher: Searcher,
+}
+
+impl Api {
+    pub fn new(searcher: Searcher) -> Api {
+        Api { searcher }
+    }
+
+    /// Answers connections on `address` until the process is killed
+    pub fn serve(&mut self, address: &str) -> std::result::Result<(), String> {
+        let listener = TcpListener::bind(address).map_err(|err| format!("can't listen on {}: {}", address, err))?;
+        let local = listener.local_addr().map_err(|err| err.to_string())?;
+        logging::info(format!("analysis api listening on {}", local).as_str());
+
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(err) = self.answer(stream) {
+                        logging::warn(format!("api connection failed: {}", err).as_str());
+                    }
+                },
+                Err(err) => logging::warn(format!("api accept failed: {}", err).as_str()),
+            }
+        }
+        Ok(())
+    }
+
+    fn answer(&mut self, mut stream: TcpStream) -> std::io::Result<()> {
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let response = match read_request(&mut stream) {
+            Ok((method, path, body)) => {
+                logging::debug(format!("api < {} {} {}", method, path, body).as_str());
+                self.handle(&method, &path, &body)
+            },
+            Err(response) => response,
+        };
+        logging::debug(format!("api > {} {}", response.status, response.body).as_str());
+
+        write!(
+            stream,
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            response.status, response.reason(), response.body.len(), response.body,
+        )?;
+        stream.flush()
+    }
+
+    /// Answers one request, without any of the HTTP around it
+    pub fn handle(&mut self, method: &str, path: &str, body: &str) -> Response {
+        let endpoint = match path {
+            "/analyse" | "/analyze" => Api::analyse,
+            "/legal-moves" => Api::legal_moves,
+            _ => return Response::error(404, format!("no such endpoint: {}", path).as_str()),
+        };
+        if method != "POST" {
+            return Response::error(405, "only POST is supported");
+        }
+
+        let request = match json::parse(body) {
+            Ok(request @ Value::Object(_)) => request,
+            Ok(_) => return Response::error(400, "the body should be a json object"),
+            Err(err) => return Response::error(400, err.as_str()),
+        };
+        endpoint(self, &request)
+    }
+
+    fn analyse(&mut self, request: &Value) -> Response {
+        let board = match request_board(request) {
+            Ok(board) => board,
+            Err(response) => return response,
+        };
+
+        let mut limits = SearchLimits::default();
+        match number_field(request, "depth") {
+            Ok(depth) => limits.depth = depth.map(|depth| depth.clamp(1, u32::MAX as u64) as u32),
+            Err(response) => return response,
+        }
+        match number_field(request, "movetime") {
+            Ok(movetime) => limits.movetime = movetime.map(Duration::from_millis),
+            Err(response) => return response,
+        }
+        match number_field(request, "multipv") {
+            Ok(lines) => self.searcher.set_multi_pv(lines.unwrap_or(1) as usize),
+            Err(response) => return response,
+        }
+
+        // only the lines from the deepest finished iteration are worth giving back
+        let mut lines: Vec<SearchInfo> = Vec::new();
+        let mut last: Option<SearchInfo> = None;
+        let res = self.searcher.find_best_move(board.clone(), &limits, |info| {
+            if info.bound == Bound::Exact {
+                if lines.first().map(|first| first.depth != info.depth).unwrap_or(false) {
+                    lines.clear();
+                }
+                lines.push(info.clone());
+            }
+            last = Some(info.clone());
+        });
+
+        let best = match res {
+            Ok(best) => best,
+            Err(err) => return Response::error(422, err.as_str()),
+        };
+
+        let depth = lines.first().map(|info| info.depth).unwrap_or(0);
+        let (nodes, millis) = last.map(|info| (info.nodes, info.time.as_millis())).unwrap_or((0, 0));
+        let lines: Vec<String> = lines.iter()
+            .enumerate()
+            .map(|(i, info)| format!(
+                r#"{{"multipv":{},"score":{},"pv":{}}}"#,
+                info.multipv.unwrap_or(i + 1), score_json(info.score), moves_json(&info.pv),
+            ))
+            .collect();
+
+        Response::ok(format!(
+            r#"{{"fen":{},"bestmove":{},"depth":{},"nodes":{},"time_ms":{},"lines":[{}]}}"#,
+            json::string(board.to_fen().as_str()), json::string(best.to_long_algebraic_notation().as_str()),
+            depth, nodes, millis, lines.join(","),
+        ))
+    }
+
+    fn legal_moves(&mut self, request: &Value) -> Response {
+        match request_board(request) {
+            Ok(mut board) => {
+                let moves = board.get_legal_moves();
+                Response::ok(format!(r#"{{"fen":{},"moves":{}}}"#, json::string(board.to_fen().as_str()), moves_json(&moves)))
+            },
+            Err(response) => response,
+        }
+    }
+}
+
+fn moves_json(moves: &[ChessMove]) -> String {
+    let moves: Vec<String> = moves.iter().map(|cm| json::string(cm.to_long_algebraic_notation().as_str())).collect();
+    format!("[{}]", moves.join(","))
+}
+
+fn request_board(request: &Value) -> std::result::Result<Board, Response> {
+    let fen = request.get("fen")
+        .and_then(Value::as_str)
+        .ok_or_else(|| Response::error(400, "fen is missing"))?;
+    uci::position_board(Some(fen), &[]).map_err(|err| Response::error(422, err.as_str()))
+}
+
+/// An optional whole number field
+fn number_field(request: &Value, name: &str) -> std::result::Result<Option<u64>, Response> {
+    match request.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => value.as_u64()
+            .map(Some)
+            .ok_or_else(|| Response::error(400, format!("{} should be a whole number", name).as_str())),
+    }
+}
+
+/// Reads the method, path and body of one request. Only as much HTTP as curl and friends need.
+fn read_request(stream: &mut TcpStream) -> std::result::Result<(String, String, String), Response> {
+    let bad = |message: &str| Response::error(400, message);
+    let mut reader = BufReader::new(stream);
+
+    let mut line = String::new();
+    reader.read_line(&mut line).map_err(|_| bad("couldn't read the request"))?;
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    if parts.len() != 3 {
+        return Err(bad("malformed request line"))
+    }
+    let (method, path) = (parts[0].to_string(), parts[1].to_string());
+
+    let mut length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).map_err(|_| bad("couldn't read the headers"))? == 0 {
+            return Err(bad("connection closed in the headers"))
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().map_err(|_| bad("bad content-length"))?;
+            }
+        }
+    }
+
+    if length > MAX_BODY {
+        return Err(Response::error(413, "request body too large"))
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).map_err(|_| bad("body shorter than content-length"))?;
+    let body = String::from_utf8(body).map_err(|_| bad("body isn't utf-8"))?;
+    Ok((method, path, body))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::api::Api;
+    use crate::json::{self, Value};
+
+    #[test]
+    fn test_legal_moves() {
+        let mut api = Api::default();
+        let response = api.handle("POST", "/legal-moves", r#"{"fen": "4k3/8/8/8/8/8/8/4K2R w K - 0 1"}"#);
+        assert_eq!(response.status, 200);
+        let moves = json::parse(&response.body).unwrap();
+        match moves.get("moves") {
+            Some(Value::Array(moves)) => {
+                assert_eq!(moves.len(), 15);
+                assert!(moves.contains(&Value::String(String::from("e1g1"))));
+            },
+            other => panic!("no moves in {:?}", other),
+        }
+
+        assert_eq!(api.handle("POST", "/legal-moves", r#"{"fen": "nonsense"}"#).status, 422);
+        assert_eq!(api.handle("POST", "/legal-moves", "{").status, 400);
+        assert_eq!(api.handle("POST", "/legal-moves", &"[".repeat(60_000)).status, 400);
+        assert_eq!(api.handle("GET", "/legal-moves", "").status, 405);
+        assert_eq!(api.handle("POST", "/elsewhere", "{}").status, 404);
+    }
+
+    #[test]
+    fn test_analyse() {
+        let mut api = Api::default();
+        let response = api.handle("POST", "/analyse", r#"{"fen": "6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", "depth": 3, "multipv": 2}"#);
+        assert_eq!(response.status, 200, "{}", response.body);
+
+        let analysis = json::parse(&response.body).unwrap();
+        assert_eq!(analysis.get("bestmove").and_then(Value::as_str), Some("a1a8"));
+        assert_eq!(analysis.get("depth").and_then(Value::as_u64), Some(3));
+        match analysis.get("lines") {
+            Some(Value::Array(lines)) => {
+                assert_eq!(lines.len(), 2);
+                assert_eq!(lines[0].get("score").and_then(|score| score.get("mate")).and_then(Value::as_u64), Some(1));
+            },
+            other => panic!("no lines in {:?}", other),
+        }
+
+        assert_eq!(api.handle("POST", "/analyse", r#"{"fen": "6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", "depth": -1}"#).status, 400);
+    }
+}
@@ -0,0 +1,86 @@
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::process;
+
+use chess::datagen::{Generator, Settings};
+
+// Plays fast self-play games and writes out their quiet positions for tuning and training:
+//
+//   datagen [--games N] [--nodes N] [--seed N] [--random-plies N] [--hash MB]
+//           [--text FILE] [--binary FILE]
+//
+// The same seed and settings always give the same positions, so to use more cores run one of
+// these per core with a different --seed each. Files are appended to, so runs can be stopped
+// and picked up again with another seed. Without --text or --binary, the text goes to stdout.
+
+const USAGE: &str = "usage: datagen [--games N] [--nodes N] [--seed N] [--random-plies N] [--hash MB] [--text FILE] [--binary FILE]";
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1)
+}
+
+fn next(args: &mut impl Iterator<Item = String>) -> String {
+    args.next().unwrap_or_else(|| fail(USAGE))
+}
+
+fn next_number(args: &mut impl Iterator<Item = String>) -> u64 {
+    next(args).parse::<u64>().unwrap_or_else(|_| fail(USAGE))
+}
+
+fn open(path: &str) -> BufWriter<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+        .map(BufWriter::new)
+        .unwrap_or_else(|err| fail(format!("can't open {}: {}", path, err).as_str()))
+}
+
+fn main() {
+    let mut settings = Settings::default();
+    let mut games = 100;
+    let mut seed = 0;
+    let mut hash = None;
+    let mut text_path = None;
+    let mut binary_path = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--games" => games = next_number(&mut args),
+            "--nodes" => settings.nodes = next_number(&mut args).max(1),
+            "--seed" => seed = next_number(&mut args),
+            "--random-plies" => settings.random_plies = next_number(&mut args) as usize,
+            "--hash" => hash = Some(next_number(&mut args) as usize),
+            "--text" => text_path = Some(next(&mut args)),
+            "--binary" => binary_path = Some(next(&mut args)),
+            _ => fail(USAGE),
+        }
+    }
+
+    let mut text: Box<dyn Write> = match (&text_path, &binary_path) {
+        (Some(path), _) => Box::new(open(path)),
+        (None, Some(_)) => Box::new(std::io::sink()),
+        (None, None) => Box::new(std::io::stdout()),
+    };
+    let mut binary = binary_path.as_deref().map(open);
+
+    let mut generator = Generator::new(settings, seed);
+    if let Some(megabytes) = hash {
+        generator.searcher().set_hash_size(megabytes);
+    }
+
+    let mut positions = 0;
+    for game in 0..games {
+        let records = generator.play_game();
+        for record in &records {
+            let written = writeln!(text, "{}", record.to_text())
+                .and_then(|_| binary.as_mut().map_or(Ok(()), |file| file.write_all(&record.to_bytes())));
+            written.unwrap_or_else(|err| fail(format!("can't write: {}", err).as_str()));
+        }
+        text.flush().and_then(|_| binary.as_mut().map_or(Ok(()), |file| file.flush()))
+            .unwrap_or_else(|err| fail(format!("can't write: {}", err).as_str()));
+
+        positions += records.len();
+        eprintln!("game {}/{}: {} positions, {} in all", game + 1, games, records.len(), positions);
+    }
+}
@@ -0,0 +1,109 @@
+use std::env;
+use std::process;
+use std::time::Duration;
+
+use chess::epd;
+use chess::pgn;
+use chess::search::{Searcher, SearchLimits};
+
+// Runs EPD test suites (WAC, STS, ECM and the like) through the search:
+//
+//   epd [--depth N | --movetime MS] [--threads N] [--hash MB] [--verbose] suite.epd [more.epd ...]
+//
+// A position is solved when the move found is one of its `bm` moves and none of its `am` ones.
+// Suites with STS style `c0` points also get a score, out of the most the positions could give.
+// The table is cleared before every position so they don't help each other out.
+
+const USAGE: &str = "usage: epd [--depth N | --movetime MS] [--threads N] [--hash MB] [--verbose] suite.epd [more.epd ...]";
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1)
+}
+
+fn next_number(args: &mut impl Iterator<Item = String>) -> u64 {
+    args.next()
+        .and_then(|n| n.parse::<u64>().ok())
+        .unwrap_or_else(|| fail(USAGE))
+}
+
+fn main() {
+    let mut limits = SearchLimits { movetime: Some(Duration::from_secs(1)), ..Default::default() };
+    let mut searcher = Searcher::new();
+    let mut verbose = false;
+    let mut paths = Vec::new();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--depth" => {
+                limits.depth = Some(next_number(&mut args) as u32);
+                limits.movetime = None;
+            },
+            "--movetime" => {
+                limits.movetime = Some(Duration::from_millis(next_number(&mut args)));
+                limits.depth = None;
+            },
+            "--threads" => searcher.set_threads(next_number(&mut args) as usize),
+            "--hash" => searcher.set_hash_size(next_number(&mut args) as usize),
+            "--verbose" => verbose = true,
+            _ if arg.starts_with("--") => fail(USAGE),
+            _ => paths.push(arg),
+        }
+    }
+    if paths.is_empty() {
+        fail(USAGE);
+    }
+
+    for path in &paths {
+        let positions = epd::load(path).unwrap_or_else(|err| fail(err.as_str()));
+        let (mut solved, mut points, mut max_points, mut scored) = (0, 0, 0, 0);
+
+        for (i, position) in positions.iter().enumerate() {
+            let id = if position.id().is_empty() { format!("#{}", i + 1) } else { position.id().to_string() };
+
+            searcher.clear();
+            let found = searcher.find_best_move(position.board.clone(), &limits, |info| {
+                if verbose {
+                    println!("  {}", info.to_uci_string());
+                }
+            });
+            let found = match found {
+                Ok(found) => found,
+                Err(err) => {
+                    println!("{}: {}", id, err);
+                    continue
+                },
+            };
+            let san = pgn::to_san(&position.board, &found);
+
+            let ok = position.solved_by(&found).unwrap_or_else(|err| {
+                println!("{}: bad bm/am: {}", id, err);
+                false
+            });
+            if ok {
+                solved += 1;
+            }
+
+            let mut line = format!("{}: {} {}", id, san, if ok { "ok" } else { "wrong" });
+            if let Some(bm) = position.operation("bm") {
+                line.push_str(format!(" (bm {})", bm).as_str());
+            }
+            if let Some(am) = position.operation("am") {
+                line.push_str(format!(" (am {})", am).as_str());
+            }
+            if let Some((got, max)) = position.points_for(&found) {
+                points += got;
+                max_points += max;
+                scored += 1;
+                line.push_str(format!(" {}/{} points", got, max).as_str());
+            }
+            println!("{}", line);
+        }
+
+        println!("{}: solved {}/{}", path, solved, positions.len());
+        if scored > 0 {
+            println!("{}: score {}/{} over {} positions ({:.1}%)", path, points, max_points, scored, 100.0 * points as f64 / max_points as f64);
+        }
+    }
+}
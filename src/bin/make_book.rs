@@ -0,0 +1,73 @@
+use std::env;
+use std::fs;
+use std::process;
+
+use chess::book::BookBuilder;
+use chess::pgn;
+
+// Builds a Polyglot book out of PGN files:
+//
+//   make_book [--plies N] out.bin games.pgn [more.pgn ...]
+//
+// Only the first N plies of every game go in (default 20). Games without a result, and games
+// with moves we can't follow, are skipped.
+
+const USAGE: &str = "usage: make_book [--plies N] out.bin games.pgn [more.pgn ...]";
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1)
+}
+
+fn main() {
+    let mut max_ply = 20;
+    let mut paths = Vec::new();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--plies" {
+            max_ply = args.next()
+                .and_then(|n| n.parse::<usize>().ok())
+                .unwrap_or_else(|| fail(USAGE));
+        } else {
+            paths.push(arg);
+        }
+    }
+
+    if paths.len() < 2 {
+        fail(USAGE);
+    }
+    let out = paths.remove(0);
+
+    let mut builder = BookBuilder::new();
+    let mut used = 0;
+    let mut skipped = 0;
+
+    for path in &paths {
+        let text = fs::read_to_string(path).unwrap_or_else(|err| fail(format!("can't read {}: {}", path, err).as_str()));
+
+        for game in pgn::parse_games(&text) {
+            let result = match game.result {
+                Some(result) => result,
+                None => {
+                    skipped += 1;
+                    continue
+                }
+            };
+            let played = game.start().and_then(|start| game.chess_moves().map(|moves| (start, moves)));
+            match played {
+                Ok((start, moves)) => {
+                    builder.add_game(&start, &moves, result, max_ply);
+                    used += 1;
+                },
+                Err(err) => {
+                    eprintln!("skipping game in {}: {}", path, err);
+                    skipped += 1;
+                },
+            }
+        }
+    }
+
+    builder.write(&out).unwrap_or_else(|err| fail(err.as_str()));
+    println!("{} games used, {} skipped, {} distinct book moves seen, written to {}", used, skipped, builder.len(), out);
+}
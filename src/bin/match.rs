@@ -0,0 +1,72 @@
+use std::env;
+use std::process;
+
+use chess::board::GameResult;
+use chess::tournament::{Match, MatchSettings};
+
+// Plays two UCI engines against each other and reports how the first one did:
+//
+//   match --engine1 CMD --engine2 CMD [--games N] [--tc 10+0.1] [--openings FILE [--plies N]]
+//         [--pgn FILE] [--option NAME=VALUE ...] [--resign CP MOVES] [--max-plies N]
+//
+// Every opening is played twice with the colours swapped, so the games come in pairs. Openings
+// are FEN/EPD lines, or a .pgn file cut to --plies moves (default 8). Engines are restarted
+// for a game if the last one killed them.
+//
+// Fixed length matches are fine for a rough idea; to decide whether a change is an improvement,
+// sprt stops as soon as the games say one way or the other.
+
+const USAGE: &str = "usage: match --engine1 CMD --engine2 CMD [--games N] [--tc 10+0.1] [--openings FILE [--plies N]] \
+    [--pgn FILE] [--option NAME=VALUE ...] [--resign CP MOVES] [--max-plies N]";
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1)
+}
+
+fn result_string(result: GameResult) -> &'static str {
+    match result {
+        GameResult::WhiteWin => "1-0",
+        GameResult::BlackWin => "0-1",
+        GameResult::Draw => "1/2-1/2",
+    }
+}
+
+fn format_elo(elo: f64) -> String {
+    // adding zero turns -0.0 into 0.0
+    if elo.is_finite() { format!("{:.1}", elo + 0.0) } else { String::from("inf") }
+}
+
+fn main() {
+    let mut settings = MatchSettings::default();
+    let mut games = 10;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match settings.parse_arg(&arg, &mut args) {
+            Ok(true) => continue,
+            Ok(false) => (),
+            Err(err) => fail(format!("{}\n{}", err, USAGE).as_str()),
+        }
+        match arg.as_str() {
+            "--games" => games = args.next().and_then(|n| n.parse::<usize>().ok()).unwrap_or_else(|| fail(USAGE)),
+            _ => fail(USAGE),
+        }
+    }
+
+    let mut games_match = Match::new(settings, "match").unwrap_or_else(|err| fail(format!("{}\n{}", err, USAGE).as_str()));
+
+    for game in 0..games {
+        let (record, first_white) = games_match.play_next().unwrap_or_else(|err| fail(err.as_str()));
+        let score = games_match.score;
+        let (first, second) = if first_white { (&record.white, &record.black) } else { (&record.black, &record.white) };
+        println!("game {}/{}: {} vs {} {} ({})", game + 1, games, record.white, record.black, result_string(record.result), record.reason);
+        println!(
+            "score of {} vs {}: {} - {} - {} [{:.3}] {}",
+            first, second, score.wins, score.losses, score.draws, score.ratio(), score.games(),
+        );
+    }
+
+    let score = games_match.score;
+    println!("elo difference: {} +/- {}, draw ratio {:.1}%", format_elo(score.elo()), format_elo(score.elo_error()), score.draw_ratio() * 100.0);
+}
@@ -0,0 +1,39 @@
+use std::env;
+use std::process;
+
+use chess::api::{self, Api};
+use chess::search::Searcher;
+
+// Runs the HTTP analysis API (see api.rs):
+//
+//   serve [--threads N] [--hash MB] [address]
+//
+// The address defaults to 127.0.0.1:8081. There's no authentication of any kind, so think twice
+// before giving it anything other than a loopback address.
+
+const USAGE: &str = "usage: serve [--threads N] [--hash MB] [address]";
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1)
+}
+
+fn main() {
+    let mut searcher = Searcher::new();
+    let mut address = String::from(api::DEFAULT_ADDRESS);
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut number = || args.next()
+            .and_then(|n| n.parse::<usize>().ok())
+            .unwrap_or_else(|| fail(USAGE));
+        match arg.as_str() {
+            "--threads" => searcher.set_threads(number()),
+            "--hash" => searcher.set_hash_size(number()),
+            _ if arg.starts_with("--") => fail(USAGE),
+            _ => address = arg,
+        }
+    }
+
+    Api::new(searcher).serve(&address).unwrap_or_else(|err| fail(err.as_str()));
+}
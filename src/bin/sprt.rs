@@ -0,0 +1,94 @@
+use std::env;
+use std::process;
+
+use chess::stats::{Hypothesis, Pentanomial, Sprt};
+use chess::tournament::{Match, MatchSettings};
+
+// Sequential probability ratio test between two UCI builds:
+//
+//   sprt --engine1 NEW --engine2 BASE [--elo0 0] [--elo1 5] [--alpha 0.05] [--beta 0.05]
+//        [--tc 10+0.1] [--openings FILE [--plies N]] [--pgn FILE] [--option NAME=VALUE ...]
+//        [--resign CP MOVES] [--max-plies N] [--max-pairs N]
+//
+// Plays game pairs (each opening once with each colour) until the LLR crosses a bound: H1
+// means engine1 is at least elo1 stronger, H0 that it's no better than elo0. The LLR is printed
+// after every pair. With --max-pairs the test can also end without a decision.
+
+const USAGE: &str = "usage: sprt --engine1 NEW --engine2 BASE [--elo0 0] [--elo1 5] [--alpha 0.05] [--beta 0.05] \
+    [--tc 10+0.1] [--openings FILE [--plies N]] [--pgn FILE] [--option NAME=VALUE ...] [--resign CP MOVES] \
+    [--max-plies N] [--max-pairs N]";
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1)
+}
+
+fn float(arg: Option<String>) -> f64 {
+    arg.and_then(|arg| arg.parse::<f64>().ok()).filter(|f| f.is_finite()).unwrap_or_else(|| fail(USAGE))
+}
+
+fn main() {
+    let mut settings = MatchSettings::default();
+    let mut sprt = Sprt::default();
+    let mut max_pairs = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match settings.parse_arg(&arg, &mut args) {
+            Ok(true) => continue,
+            Ok(false) => (),
+            Err(err) => fail(format!("{}\n{}", err, USAGE).as_str()),
+        }
+        match arg.as_str() {
+            "--elo0" => sprt.elo0 = float(args.next()),
+            "--elo1" => sprt.elo1 = float(args.next()),
+            "--alpha" => sprt.alpha = float(args.next()),
+            "--beta" => sprt.beta = float(args.next()),
+            "--max-pairs" => max_pairs = Some(args.next().and_then(|n| n.parse::<usize>().ok()).unwrap_or_else(|| fail(USAGE))),
+            _ => fail(USAGE),
+        }
+    }
+    let probability = |p: f64| p > 0.0 && p < 1.0;
+    if sprt.elo1 <= sprt.elo0 || !probability(sprt.alpha) || !probability(sprt.beta) {
+        fail("elo1 has to be above elo0, and alpha and beta between 0 and 1");
+    }
+
+    let mut games = Match::new(settings, "sprt").unwrap_or_else(|err| fail(format!("{}\n{}", err, USAGE).as_str()));
+
+    let (lower, upper) = sprt.bounds();
+    println!("sprt elo0 {} elo1 {} alpha {} beta {}, bounds ({:.2}, {:.2})", sprt.elo0, sprt.elo1, sprt.alpha, sprt.beta, lower, upper);
+
+    let mut pairs = Pentanomial::default();
+
+    let decision = loop {
+        if max_pairs.is_some_and(|max| pairs.pairs() as usize >= max) {
+            break None
+        }
+
+        // the match plays each opening twice, first engine white first
+        let mut points = 0.0;
+        for _ in 0..2 {
+            let (record, first_white) = games.play_next().unwrap_or_else(|err| fail(err.as_str()));
+            points += record.points(first_white);
+        }
+        pairs.add(points);
+
+        let score = games.score;
+        println!(
+            "pairs {}: {} - {} - {}, ptnml {:?}, llr {:.2} ({:.2}, {:.2})",
+            pairs.pairs(), score.wins, score.losses, score.draws, pairs.counts, sprt.llr(&pairs), lower, upper,
+        );
+
+        if let Some(decision) = sprt.decision(&pairs) {
+            break Some(decision)
+        }
+    };
+
+    match decision {
+        Some(Hypothesis::H1) => println!("H1 accepted: engine1 is stronger (elo1 {})", sprt.elo1),
+        Some(Hypothesis::H0) => println!("H0 accepted: engine1 is no stronger (elo0 {})", sprt.elo0),
+        None => println!("no decision after {} pairs", pairs.pairs()),
+    }
+    let elo = games.score.elo() + 0.0;
+    println!("elo difference: {:.1} +/- {:.1}", elo, games.score.elo_error());
+}
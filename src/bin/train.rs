@@ -0,0 +1,113 @@
+use std::env;
+use std::fs;
+use std::process;
+use std::time::Instant;
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+use chess::trainer::{self, Settings, Trainer};
+
+// Trains a network for UseNNUE from datagen output:
+//
+//   train [--hidden N] [--epochs N] [--batch N] [--lr X] [--lambda X] [--threads N] [--seed N]
+//         [--validation FILE | --split X] [--checkpoint FILE] [--resume FILE] [--out FILE]
+//         data [more data ...]
+//
+// Data files are datagen's, binary if they end in .bin and FEN;score;result lines otherwise.
+// Validation positions come from --validation, or else the --split fraction (default 0.05) of
+// the data is held back for it. After every epoch the network is written to --out (default
+// net.nnue) for EvalFile, and the whole training state to --checkpoint if there is one, which
+// --resume carries on from. --hidden and --seed only matter for a fresh start.
+
+const USAGE: &str = "usage: train [--hidden N] [--epochs N] [--batch N] [--lr X] [--lambda X] [--threads N] [--seed N] \
+    [--validation FILE | --split X] [--checkpoint FILE] [--resume FILE] [--out FILE] data [more data ...]";
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1)
+}
+
+fn next(args: &mut impl Iterator<Item = String>) -> String {
+    args.next().unwrap_or_else(|| fail(USAGE))
+}
+
+fn next_number<T: std::str::FromStr>(args: &mut impl Iterator<Item = String>) -> T {
+    next(args).parse::<T>().unwrap_or_else(|_| fail(USAGE))
+}
+
+fn main() {
+    let mut settings = Settings::default();
+    let mut hidden = 128;
+    let mut epochs = 10;
+    let mut seed = 0;
+    let mut validation_path = None;
+    let mut split = 0.05;
+    let mut checkpoint = None;
+    let mut resume = None;
+    let mut out = String::from("net.nnue");
+    let mut paths = Vec::new();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--hidden" => hidden = next_number(&mut args),
+            "--epochs" => epochs = next_number(&mut args),
+            "--batch" => settings.batch_size = next_number::<usize>(&mut args).max(1),
+            "--lr" => settings.learning_rate = next_number(&mut args),
+            "--lambda" => settings.lambda = next_number::<f32>(&mut args).clamp(0.0, 1.0),
+            "--threads" => settings.threads = next_number::<usize>(&mut args).max(1),
+            "--seed" => seed = next_number(&mut args),
+            "--validation" => validation_path = Some(next(&mut args)),
+            "--split" => split = next_number::<f64>(&mut args).clamp(0.0, 0.5),
+            "--checkpoint" => checkpoint = Some(next(&mut args)),
+            "--resume" => resume = Some(next(&mut args)),
+            "--out" => out = next(&mut args),
+            _ if arg.starts_with("--") => fail(USAGE),
+            _ => paths.push(arg),
+        }
+    }
+    if paths.is_empty() {
+        fail(USAGE);
+    }
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut samples = Vec::new();
+    for path in &paths {
+        samples.extend(trainer::load(path).unwrap_or_else(|err| fail(err.as_str())));
+    }
+    let validation = match &validation_path {
+        Some(path) => trainer::load(path).unwrap_or_else(|err| fail(err.as_str())),
+        None => {
+            samples.shuffle(&mut rng);
+            let held = (samples.len() as f64 * split) as usize;
+            samples.split_off(samples.len() - held)
+        },
+    };
+    if samples.is_empty() {
+        fail("no positions to train on");
+    }
+    println!("{} training positions, {} validation positions", samples.len(), validation.len());
+
+    let mut trainer = match &resume {
+        Some(path) => Trainer::load_checkpoint(path).unwrap_or_else(|err| fail(err.as_str())),
+        None => Trainer::new(hidden, seed).unwrap_or_else(|err| fail(err.as_str())),
+    };
+    println!("hidden layer of {}, starting from epoch {}", trainer.hidden(), trainer.epoch() + 1);
+
+    for _ in 0..epochs {
+        let start = Instant::now();
+        let loss = trainer.train_epoch(&mut samples, &settings, &mut rng);
+        let validation_loss = trainer.loss(&validation, settings.lambda, settings.threads);
+        println!(
+            "epoch {}: loss {:.6}, validation loss {:.6}, {:.1}s",
+            trainer.epoch(), loss, validation_loss, start.elapsed().as_secs_f64(),
+        );
+
+        fs::write(&out, trainer.to_network().to_bytes()).unwrap_or_else(|err| fail(format!("can't write {}: {}", out, err).as_str()));
+        if let Some(path) = &checkpoint {
+            trainer.save_checkpoint(path).unwrap_or_else(|err| fail(err.as_str()));
+        }
+    }
+}
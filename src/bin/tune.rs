@@ -0,0 +1,72 @@
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::process;
+
+use chess::params::DEFAULT;
+use chess::tune::{self, Tuner};
+
+// Texel tunes the evaluation weights against a dataset of positions labelled with their game's
+// result:
+//
+//   tune [--threads N] [--step N] [--passes N] [--out FILE] dataset.txt
+//
+// One position per line, a FEN and then the result as [1.0], 1-0, c9 "1-0"; and so on. The
+// weights are written to --out (default tuned.rs) after every pass, laid out as the constants in
+// the eval modules, so the run can be stopped whenever and the file pasted over them.
+
+const USAGE: &str = "usage: tune [--threads N] [--step N] [--passes N] [--out FILE] dataset.txt";
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1)
+}
+
+fn next_number(args: &mut impl Iterator<Item = String>) -> usize {
+    args.next()
+        .and_then(|n| n.parse::<usize>().ok())
+        .unwrap_or_else(|| fail(USAGE))
+}
+
+fn main() {
+    let mut threads = 1;
+    let mut step = 1;
+    let mut passes = usize::MAX;
+    let mut out = String::from("tuned.rs");
+    let mut path = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--threads" => threads = next_number(&mut args).max(1),
+            "--step" => step = next_number(&mut args).max(1) as i32,
+            "--passes" => passes = next_number(&mut args),
+            "--out" => out = args.next().unwrap_or_else(|| fail(USAGE)),
+            _ if arg.starts_with("--") || path.is_some() => fail(USAGE),
+            _ => path = Some(arg),
+        }
+    }
+    let path = path.unwrap_or_else(|| fail(USAGE));
+
+    let samples = tune::load(&path).unwrap_or_else(|err| fail(err.as_str()));
+    if samples.is_empty() {
+        fail(format!("no usable positions in {}", path).as_str());
+    }
+    println!("{} positions", samples.len());
+
+    let mut tuner = Tuner::new(samples, DEFAULT.clone(), threads);
+    println!("K = {:.4}, error {:.6}", tuner.k(), tuner.error());
+
+    for pass in 1..=passes {
+        let changed = tuner.pass(step, |done, total| {
+            print!("\rpass {}: {}/{}", pass, done, total);
+            let _ = io::stdout().flush();
+        });
+        println!("\rpass {}: error {:.6}, {} weights changed", pass, tuner.error(), changed);
+
+        fs::write(&out, tuner.params().to_rust()).unwrap_or_else(|err| fail(format!("can't write {}: {}", out, err).as_str()));
+        if changed == 0 {
+            break
+        }
+    }
+}
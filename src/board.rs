@@ -1,21 +1,22 @@
 use std::hash::{Hash, Hasher};
-use std::array::IntoIter;
 use std::mem;
-use std::collections::hash_map::DefaultHasher;
+
+use crate::eval;
+use crate::zobrist;
 
 pub type BoardSlot = u8;
 
-const EMPTY: BoardSlot = 0;
-const PAWN: BoardSlot = 1;
-const ROOK: BoardSlot = 2;
-const KNIGHT: BoardSlot = 4;
-const BISHOP: BoardSlot = 8;
-const KING: BoardSlot = 16;
-const QUEEN: BoardSlot = 32;
-const UNIT_MASK: u8 = 127;
-const TEAM_MASK: u8 = 128;
-const TEAM_WHITE: u8 = 128;
-const TEAM_BLACK: u8 = 0;
+pub const EMPTY: BoardSlot = 0;
+pub const PAWN: BoardSlot = 1;
+pub const ROOK: BoardSlot = 2;
+pub const KNIGHT: BoardSlot = 4;
+pub const BISHOP: BoardSlot = 8;
+pub const KING: BoardSlot = 16;
+pub const QUEEN: BoardSlot = 32;
+pub const UNIT_MASK: u8 = 127;
+pub const TEAM_MASK: u8 = 128;
+pub const TEAM_WHITE: u8 = 128;
+pub const TEAM_BLACK: u8 = 0;
 
 fn char_for_slot(slot: BoardSlot) -> char {
     match slot & UNIT_MASK {
@@ -71,6 +72,10 @@ impl ChessMove {
         cmove
     }
 
+    pub fn new(from_ind: (i8, i8), to_ind: (i8, i8), promotion: BoardSlot) -> ChessMove {
+        ChessMove { from_ind, to_ind, promotion }
+    }
+
     pub fn from_delta(x: i8, y: i8, dx: i8, dy: i8) -> Option<ChessMove> {
         if ChessMove::within(x + dx) && ChessMove::within(y + dy) {
             Some(ChessMove{
@@ -83,13 +88,36 @@ impl ChessMove {
         }
     }
 
+    /// (column, rank) the moving piece starts on
+    pub fn from_ind(&self) -> (i8, i8) {
+        self.from_ind
+    }
+
+    /// (column, rank) the moving piece ends up on
+    pub fn to_ind(&self) -> (i8, i8) {
+        self.to_ind
+    }
+
+    /// piece a pawn is promoting to, or EMPTY
+    pub fn promotion(&self) -> BoardSlot {
+        self.promotion
+    }
+
     pub fn to_long_algebraic_notation(&self) -> String {
+        let promotion = match self.promotion & UNIT_MASK {
+            QUEEN => "q",
+            ROOK => "r",
+            BISHOP => "b",
+            KNIGHT => "n",
+            _ => "",
+        };
         format!(
-            "{}{}{}{}",
+            "{}{}{}{}{}",
             ChessMove::column_to_letter(self.from_ind.0),
             self.from_ind.1 + 1,
             ChessMove::column_to_letter(self.to_ind.0),
             self.to_ind.1 + 1,
+            promotion,
         ).to_string()
     }
 
@@ -127,10 +155,18 @@ impl ChessMove {
 
     #[inline(always)]
     fn within(x: i8) -> bool {
-        x >= 0 && x <= 7
+        (0..=7).contains(&x)
     }
 }
 
+/// How a game ended, from white's side
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameResult {
+    WhiteWin,
+    BlackWin,
+    Draw,
+}
+
 #[derive(Debug, Clone)]
 pub struct Board {
     board: [[BoardSlot; 8]; 8],
@@ -142,6 +178,9 @@ pub struct Board {
     white_qs_castle: bool,
     black_ks_castle: bool,
     black_qs_castle: bool,
+    en_passant: Option<(i8, i8)>,  // square a pawn that just moved two skipped over
+    pawn_key: u64,  // zobrist key over just the pawns, for the pawn hash table
+    key: u64,  // zobrist key of the whole position, see zobrist::key
     white_in_check: bool,
     black_in_check: bool,
     white_moves: Vec<ChessMove>,
@@ -177,6 +216,7 @@ impl Hash for Board {
         self.white_qs_castle.hash(state);
         self.black_ks_castle.hash(state);
         self.black_qs_castle.hash(state);
+        self.en_passant.hash(state);
     }
 }
 
@@ -187,6 +227,12 @@ impl PartialEq for Board {
 }
 impl Eq for Board {}
 
+impl Default for Board {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Board {
     pub fn new() -> Board {
         Board {
@@ -208,6 +254,9 @@ impl Board {
             white_qs_castle: false,
             black_ks_castle: false,
             black_qs_castle: false,
+            en_passant: None,
+            pawn_key: 0,
+            key: 0,
             white_in_check: false,
             black_in_check: false,
             white_moves: Vec::new(),
@@ -226,9 +275,9 @@ impl Board {
         let mut res = Board::new();
 
         // fen is given in reverse rank orders
-        for i in 0..8 {
+        for (i, rank) in ranks.iter().enumerate() {
             let mut slots: Vec<BoardSlot> = Vec::new();
-            for elem in ranks[i].chars() {
+            for elem in rank.chars() {
                 match elem.to_digit(10) {
                     Some(n) => slots.extend((0..n).map(|_| EMPTY)),
                     None => slots.push(slot_from_char(elem)),
@@ -239,9 +288,9 @@ impl Board {
                 return Err(String::from("illegal length of row"))
             }
 
-            for j in 0..8 {
+            for (j, slot) in slots.into_iter().enumerate() {
                 // we mirror the i index here because they arrive in reverse-rank order
-                res.board[j][7 - i] = slots[j]
+                res.board[j][7 - i] = slot
             }
         }
 
@@ -265,7 +314,10 @@ impl Board {
         }
 
         // parse en-passant
-        // TODO lol why
+        let ep: Vec<char> = fen[3].chars().collect();
+        if ep.len() == 2 && ('a'..='h').contains(&ep[0]) && ('1'..='8').contains(&ep[1]) {
+            res.en_passant = Some((ChessMove::letter_to_column(ep[0]), ChessMove::parse_rank(ep[1])));
+        }
 
         // parse half-move clock
         match fen[4].parse::<i32>().ok() {
@@ -279,23 +331,99 @@ impl Board {
             None => return Err(String::from("Couldn't parse half-move clock"))
         }
 
+        for x in 0..8 {
+            for y in 0..8 {
+                res.pawn_key ^= zobrist::pawn_square_key(res.board[x][y], x, y);
+            }
+        }
+        res.key = zobrist::key(&res);
+
         // compute moves
-        res.white_moves = res.compute_moves_for(TEAM_WHITE);
-        res.black_moves = res.compute_moves_for(TEAM_BLACK);
+        res.compute_moves_and_checks();
 
         Ok(res)
     }
 
+    /// The board as a FEN string, the reverse of `from_fen`
+    pub fn to_fen(&self) -> String {
+        let mut ranks = Vec::with_capacity(8);
+        for rank in (0..8).rev() {
+            let mut s = String::new();
+            let mut empty = 0;
+            for file in 0..8 {
+                let slot = self.board[file][rank];
+                if slot & UNIT_MASK == EMPTY {
+                    empty += 1;
+                    continue
+                }
+                if empty > 0 {
+                    s.push_str(empty.to_string().as_str());
+                    empty = 0;
+                }
+                s.push(char_for_slot(slot));
+            }
+            if empty > 0 {
+                s.push_str(empty.to_string().as_str());
+            }
+            ranks.push(s);
+        }
+
+        let mut castling = String::new();
+        for (right, c) in [(self.white_ks_castle, 'K'), (self.white_qs_castle, 'Q'), (self.black_ks_castle, 'k'), (self.black_qs_castle, 'q')] {
+            if right {
+                castling.push(c);
+            }
+        }
+        if castling.is_empty() {
+            castling.push('-');
+        }
+
+        let en_passant = match self.en_passant {
+            Some((file, rank)) => format!("{}{}", ChessMove::column_to_letter(file), rank + 1),
+            None => String::from("-"),
+        };
+
+        format!(
+            "{} {} {} {} {} {}",
+            ranks.join("/"),
+            if self.is_whites_move { "w" } else { "b" },
+            castling,
+            en_passant,
+            self.half_move_clock,
+            self.full_move_clock,
+        )
+    }
+
     pub fn is_whites_move(&self) -> bool {
-        return self.is_whites_move;
+        self.is_whites_move
     }
 
-    pub fn half_move_clock(&self) -> i32 { return self.half_move_clock; }
+    pub fn half_move_clock(&self) -> i32 { self.half_move_clock }
+
+    /// Zobrist key over just the pawns, kept up to date move by move
+    pub fn pawn_key(&self) -> u64 { self.pawn_key }
+
+    pub fn full_move_number(&self) -> i32 { self.full_move_clock }
+
+    /// Castling rights as (white king side, white queen side, black king side, black queen side)
+    pub fn castling_rights(&self) -> (bool, bool, bool, bool) {
+        (self.white_ks_castle, self.white_qs_castle, self.black_ks_castle, self.black_qs_castle)
+    }
+
+    /// The square a pawn skipped over by moving two on the last move, if it did.
+    pub fn en_passant(&self) -> Option<(i8, i8)> {
+        self.en_passant
+    }
+
+    /// True if either side still has any castling rights left.
+    pub fn can_castle(&self) -> bool {
+        self.white_ks_castle || self.white_qs_castle || self.black_ks_castle || self.black_qs_castle
+    }
 
     pub fn apply_move(&self, chess_move: &ChessMove) -> Board {
         let mut res = Board::new();
 
-        res.board = self.board.clone();
+        res.board = self.board;
         res.white_ks_castle = self.white_ks_castle;
         res.white_qs_castle = self.white_qs_castle;
         res.black_ks_castle = self.black_ks_castle;
@@ -304,66 +432,81 @@ impl Board {
         res.black_in_check = self.black_in_check;
 
         // moves are assumed to be at least sensical
-        let piece = res.board[chess_move.from_ind.0 as usize][chess_move.from_ind.1 as usize];
-        res.board[chess_move.from_ind.0 as usize][chess_move.from_ind.1 as usize] = EMPTY;
+        let (from_x, from_y) = (chess_move.from_ind.0 as usize, chess_move.from_ind.1 as usize);
+        let (to_x, to_y) = (chess_move.to_ind.0 as usize, chess_move.to_ind.1 as usize);
+        let piece = res.board[from_x][from_y];
+        let takes = self.board[to_x][to_y] & UNIT_MASK != EMPTY;
+        res.board[from_x][from_y] = EMPTY;
         if chess_move.promotion == EMPTY {
-            res.board[chess_move.to_ind.0 as usize][chess_move.to_ind.1 as usize] = piece;
+            res.board[to_x][to_y] = piece;
         } else {
             // promotion piece doesn't have team info
-            res.board[chess_move.to_ind.0 as usize][chess_move.to_ind.1 as usize] = (chess_move.promotion & UNIT_MASK) | (piece & TEAM_MASK);
+            res.board[to_x][to_y] = (chess_move.promotion & UNIT_MASK) | (piece & TEAM_MASK);
         }
 
-        // check castling moves
-        if chess_move.from_ind.0 == 4 && chess_move.from_ind.1 == 0 {
-            if chess_move.to_ind.0 == 6 && chess_move.to_ind.1 == 0 {
-                // white king side castle, move the rook
-                res.board[7][0] = EMPTY;
-                res.board[5][0] = TEAM_WHITE | ROOK;
-                res.white_ks_castle = false;
-                res.white_qs_castle = false;
-            }
-            if chess_move.to_ind.0 == 2 && chess_move.to_ind.1 == 0 {
-                // white king side castle, move the rook
-                res.board[0][0] = EMPTY;
-                res.board[3][0] = TEAM_WHITE | ROOK;
-                res.white_ks_castle = false;
-                res.white_qs_castle = false;
-            }
+        // a pawn taking en passant lands behind the pawn it takes
+        if piece & UNIT_MASK == PAWN && Some(chess_move.to_ind) == self.en_passant && from_x != to_x {
+            res.board[to_x][from_y] = EMPTY;
         }
-        if chess_move.from_ind.0 == 4 && chess_move.from_ind.1 == 7 {
-            if chess_move.to_ind.0 == 6 && chess_move.to_ind.1 == 7 {
-                // white king side castle, move the rook
-                res.board[7][7] = EMPTY;
-                res.board[5][7] = TEAM_WHITE | ROOK;
-                res.black_ks_castle = false;
-                res.black_qs_castle = false;
-            }
-            if chess_move.to_ind.0 == 2 && chess_move.to_ind.1 == 7 {
-                // white king side castle, move the rook
-                res.board[0][7] = EMPTY;
-                res.board[3][7] = TEAM_WHITE | ROOK;
-                res.black_ks_castle = false;
-                res.black_qs_castle = false;
+        if piece & UNIT_MASK == PAWN && (to_y as i8 - from_y as i8).abs() == 2 {
+            res.en_passant = Some((from_x as i8, ((from_y + to_y) / 2) as i8));
+        }
+
+        // only the squares the move touched can have gained or lost a pawn: where it went from
+        // and to, and where an en passant capture took from (castling just moves pieces that
+        // aren't pawns)
+        res.pawn_key = self.pawn_key;
+        let touched = if from_x != to_x && from_y != to_y { 3 } else { 2 };
+        for &(x, y) in [(from_x, from_y), (to_x, to_y), (to_x, from_y)].iter().take(touched) {
+            res.pawn_key ^= zobrist::pawn_square_key(self.board[x][y], x, y) ^ zobrist::pawn_square_key(res.board[x][y], x, y);
+        }
+
+        // castling moves the rook along with the king
+        let castles = piece & UNIT_MASK == KING && (to_x as i8 - from_x as i8).abs() == 2;
+        let (rook_from, rook_to) = if to_x == 6 { (7, 5) } else { (0, 3) };
+        if castles {
+            res.board[rook_to][from_y] = res.board[rook_from][from_y];
+            res.board[rook_from][from_y] = EMPTY;
+        }
+
+        // anything moving off or onto a king or rook's starting square loses that castling right
+        for &(x, y) in [chess_move.from_ind, chess_move.to_ind].iter() {
+            match (x, y) {
+                (4, 0) => { res.white_ks_castle = false; res.white_qs_castle = false; }
+                (7, 0) => res.white_ks_castle = false,
+                (0, 0) => res.white_qs_castle = false,
+                (4, 7) => { res.black_ks_castle = false; res.black_qs_castle = false; }
+                (7, 7) => res.black_ks_castle = false,
+                (0, 7) => res.black_qs_castle = false,
+                _ => {}
             }
         }
 
         // toggle move
         res.is_whites_move = !self.is_whites_move;
-        res.whos_turn = if self.is_whites_move { TEAM_WHITE } else { TEAM_BLACK };
+        res.whos_turn = if res.is_whites_move { TEAM_WHITE } else { TEAM_BLACK };
+
+        // the full key changes on the same squares as the pawn key plus the rook's when castling,
+        // and with the castling rights, en passant square and side to move
+        let mut key = self.key
+            ^ zobrist::castling_key(self.castling_rights()) ^ zobrist::castling_key(res.castling_rights())
+            ^ zobrist::en_passant_key(self.en_passant) ^ zobrist::en_passant_key(res.en_passant)
+            ^ zobrist::turn_key(self.is_whites_move) ^ zobrist::turn_key(res.is_whites_move);
+        let rook_squares = if castles { 2 } else { 0 };
+        let moved = [(from_x, from_y), (to_x, to_y), (to_x, from_y)];
+        let rook = [(rook_from, from_y), (rook_to, from_y)];
+        for &(x, y) in moved.iter().take(touched).chain(rook.iter().take(rook_squares)) {
+            key ^= zobrist::square_key(self.board[x][y], x, y) ^ zobrist::square_key(res.board[x][y], x, y);
+        }
+        res.key = key;
 
         // set move times
-        res.half_move_clock = self.half_move_clock + 1;
-        res.full_move_clock = (self.half_move_clock / 2) + 1;
+        // counts towards the fifty move rule, so pawn moves and captures start it again
+        res.half_move_clock = if piece & UNIT_MASK == PAWN || takes { 0 } else { self.half_move_clock + 1 };
+        res.full_move_clock = if self.is_whites_move { self.full_move_clock } else { self.full_move_clock + 1 };
 
         // check if white or black are in check
-        res.white_moves = res.compute_moves_for(TEAM_WHITE);
-        res.black_moves = res.compute_moves_for(TEAM_BLACK);
-
-        let white_king_ind = res.find_piece(TEAM_WHITE | KING).unwrap_or((-1, -1));
-        let black_king_ind = res.find_piece(TEAM_BLACK | KING).unwrap_or((-1, -1));
-
-        res.white_in_check = res.black_moves.iter().any(|cm| cm.to_ind == white_king_ind);
-        res.black_in_check = res.white_moves.iter().any(|cm| cm.to_ind == black_king_ind);
+        res.compute_moves_and_checks();
 
         res
     }
@@ -382,50 +525,99 @@ impl Board {
         }
     }
 
-    /// Get estimated score. Score is an float32, distributed around 0, advising whites advantage
-    pub fn score(&self) -> f32 {
-        let mut white_score = 0_f32;
-        let mut black_score = 0_f32;
+    /// Get every move for the player whos turn it is, without checking whether it leaves their
+    /// own king in check. Cheaper than `get_legal_moves` when the caller is going to apply the
+    /// move anyway and can check `moved_into_check` on the result.
+    pub fn get_pseudo_legal_moves(&self) -> Vec<ChessMove> {
+        if self.is_whites_move {
+            self.white_moves.clone()
+        } else {
+            self.black_moves.clone()
+        }
+    }
 
-        for i in 0..8 {
-            for j in 0..8 {
-                let piece = self.board[i][j];
-                let ps = match UNIT_MASK & piece {
-                    PAWN => 1_f32,
-                    ROOK => 3.5_f32,
-                    KNIGHT => 3_f32,
-                    BISHOP => 2.5_f32,
-                    QUEEN => 6_f32,
-                    _ => 0_f32,
-                };
-
-                if TEAM_MASK & piece == TEAM_WHITE {
-                    white_score += ps;
-                } else {
-                    black_score += ps;
+    /// Every pseudo-legal move one team has in this position, whether or not it's their turn.
+    /// These are worked out anyway when the board is made, so it's free to look at them (until
+    /// `get_legal_moves` takes them).
+    pub fn moves_for(&self, white: bool) -> &[ChessMove] {
+        if white {
+            &self.white_moves
+        } else {
+            &self.black_moves
+        }
+    }
+
+    /// True if the player whos turn it is is currently in check.
+    pub fn in_check(&self) -> bool {
+        if self.is_whites_move {
+            self.white_in_check
+        } else {
+            self.black_in_check
+        }
+    }
+
+    /// True if the player who just moved left their own king in check, meaning the move that
+    /// produced this board wasn't legal.
+    pub fn moved_into_check(&self) -> bool {
+        if self.is_whites_move {
+            self.black_in_check
+        } else {
+            self.white_in_check
+        }
+    }
+
+    /// The result if the rules end the game in this position: checkmate, stalemate, the fifty
+    /// move rule, or neither side having enough to mate. Repetitions need the moves that led
+    /// here, so they're left to whoever has those.
+    pub fn game_result(&self) -> Option<(GameResult, &'static str)> {
+        if self.clone().get_legal_moves().is_empty() {
+            return Some(if !self.in_check() {
+                (GameResult::Draw, "stalemate")
+            } else if self.is_whites_move {
+                (GameResult::BlackWin, "checkmate")
+            } else {
+                (GameResult::WhiteWin, "checkmate")
+            })
+        }
+        if self.half_move_clock >= 100 {
+            return Some((GameResult::Draw, "fifty move rule"))
+        }
+        if self.insufficient_material() {
+            return Some((GameResult::Draw, "insufficient material"))
+        }
+        None
+    }
+
+    /// Neither side can possibly mate: bare kings, or a king and one minor piece against a king
+    fn insufficient_material(&self) -> bool {
+        let mut minors = 0;
+        for x in 0..8 {
+            for y in 0..8 {
+                match self.piece_at((x, y)) & UNIT_MASK {
+                    EMPTY | KING => {},
+                    KNIGHT | BISHOP => minors += 1,
+                    _ => return false,
                 }
             }
         }
+        minors <= 1
+    }
 
-        return white_score - black_score
+    /// Get estimated score in centipawns, distributed around 0, positive advising whites advantage
+    pub fn score(&self) -> i32 {
+        eval::evaluate(self)
     }
 
-    pub fn id(& self) -> u64 {
-        let mut hasher = DefaultHasher::new();
-        self.board.hash(&mut hasher);
-        self.is_whites_move.hash(&mut hasher);
-        self.whos_turn.hash(&mut hasher);
-        self.white_ks_castle.hash(&mut hasher);
-        self.white_qs_castle.hash(&mut hasher);
-        self.black_ks_castle.hash(&mut hasher);
-        self.black_qs_castle.hash(&mut hasher);
-        hasher.finish()
+    /// Zobrist key of the position: the pieces, castling rights, en passant column and side to
+    /// move, but not the move clocks
+    pub fn id(&self) -> u64 {
+        self.key
     }
 
     pub fn as_debug_string(&self) -> String {
         let mut msg = String::with_capacity(64 + 8 + 17);
         msg.push_str("\n<Board>\n");
-        msg.push_str(format!("naive score / current turn / half turn clock: {} / {} / {}\n", self.score(), if self.is_whites_move { "white" } else { "black" }, self.half_move_clock).as_str());
+        msg.push_str(format!("score / current turn / half turn clock: {} / {} / {}\n", self.score(), if self.is_whites_move { "white" } else { "black" }, self.half_move_clock).as_str());
         msg.push_str(format!("white/black check:   {} / {}\n", self.white_in_check, self.black_in_check).as_str());
         msg.push_str(format!("white/black castle: {} / {}\n", self.white_qs_castle || self.white_ks_castle, self.black_qs_castle || self.black_ks_castle).as_str());
         for i in (0..8).rev() {
@@ -437,7 +629,7 @@ impl Board {
         }
         msg.push_str("  abcdefgh\n</Board>");
 
-        return msg
+        msg
     }
 
     #[cfg(test)]
@@ -450,14 +642,14 @@ impl Board {
             msg.push('/')
         };
 
-        return msg
+        msg
     }
 
     //
     // Util
     //
 
-    fn piece_at(&self, pos: (i8, i8)) -> u8 {
+    pub fn piece_at(&self, pos: (i8, i8)) -> u8 {
         self.board[pos.0 as usize][pos.1 as usize]
     }
 
@@ -465,6 +657,17 @@ impl Board {
         ((TEAM_MASK & piece) ^ team == 0) && (piece & UNIT_MASK > 0)
     }
 
+    fn compute_moves_and_checks(&mut self) {
+        self.white_moves = self.compute_moves_for(TEAM_WHITE);
+        self.black_moves = self.compute_moves_for(TEAM_BLACK);
+
+        let white_king_ind = self.find_piece(TEAM_WHITE | KING).unwrap_or((-1, -1));
+        let black_king_ind = self.find_piece(TEAM_BLACK | KING).unwrap_or((-1, -1));
+
+        self.white_in_check = self.black_moves.iter().any(|cm| cm.to_ind == white_king_ind);
+        self.black_in_check = self.white_moves.iter().any(|cm| cm.to_ind == black_king_ind);
+    }
+
     fn compute_moves_for(&mut self, team_mask: u8) -> Vec<ChessMove> {
         // almost always a team will have less than 50 possible moves; we allocate for 50 immediately
         let mut possible_moves: Vec<ChessMove> = Vec::with_capacity(50);
@@ -487,59 +690,38 @@ impl Board {
 
         match piece & UNIT_MASK {
             PAWN => {
-                // have to consider team for direction, and the possible first double move, and possible promotion
-                // TODO pawn promotion
-                if piece & TEAM_MASK == TEAM_WHITE {
-                    // add the possible push move
-                    let m1 = ChessMove::from_delta(x, y, 0, 1)
-                        .filter(|cm| !self.move_is_take(cm));
-
-                    // if the first move is allowed, check for the double push
-                    if m1.is_some() && y == 1 {
-                        ChessMove::from_delta(x, y, 0, 2)
-                            .filter(|cm| !self.move_is_take(cm))
-                            .map(|cm| pm.push(cm));
+                // have to consider team for direction, the possible first double move, taking en
+                // passant and promotion
+                let (dir, start_rank) = if piece & TEAM_MASK == TEAM_WHITE { (1, 1) } else { (-1, 6) };
+
+                // add the possible push move
+                let m1 = ChessMove::from_delta(x, y, 0, dir)
+                    .filter(|cm| !self.move_is_take(cm));
+
+                // if the first move is allowed, check for the double push
+                if m1.is_some() && y == start_rank {
+                    if let Some(cm) = ChessMove::from_delta(x, y, 0, 2 * dir)
+                        .filter(|cm| !self.move_is_take(cm)) {
+                        pm.push(cm);
                     }
+                }
 
-                    // add in the first move
-                    m1.map(|cm| pm.push(cm));
-
-                    // add in diagonal attacks
-                    ChessMove::from_delta(x, y, -1, 1)
-                        .filter(|cm| self.move_is_possible(cm) && self.move_is_take(cm))
-                        .map(|cm| pm.push(cm));
-                    ChessMove::from_delta(x, y, 1, 1)
-                        .filter(|cm| self.move_is_possible(cm) && self.move_is_take(cm))
-                        .map(|cm| pm.push(cm));
-
-                } else {
-                    // add the possible push move
-                    let m1 = ChessMove::from_delta(x, y, 0, -1)
-                        .filter(|cm| !self.move_is_take(cm));
-
-                    // if the first move is allowed, check for the double push
-                    if m1.is_some() && y == 6 {
-                        ChessMove::from_delta(x, y, 0, -2)
-                            .filter(|cm| !self.move_is_take(cm))
-                            .map(|cm| pm.push(cm));
-                    }
+                // add in the first move
+                if let Some(cm) = m1 {
+                    Board::push_pawn_move(pm, cm);
+                }
 
-                    // add in the first move
-                    m1.map(|cm| pm.push(cm));
-
-                    // add in diagonal attacks
-                    ChessMove::from_delta(x, y, -1, -1)
-                        .filter(|cm| self.move_is_possible(cm))
-                        .filter(|cm| self.move_is_take(cm))
-                        .map(|cm| pm.push(cm));
-                    ChessMove::from_delta(x, y, 1, -1)
-                        .filter(|cm| self.move_is_possible(cm))
-                        .filter(|cm| self.move_is_take(cm))
-                        .map(|cm| pm.push(cm));
+                // add in diagonal attacks. En passant is only there for the side that can take
+                // it, which is the side whos pawn is on the rank the square was skipped from
+                let en_passant = self.en_passant.filter(|ep| ep.1 == y + dir && (ep.1 == 5) == (dir == 1));
+                for dx in [-1, 1] {
+                    if let Some(cm) = ChessMove::from_delta(x, y, dx, dir)
+                        .filter(|cm| (self.move_is_possible(cm) && self.move_is_take(cm)) || Some(cm.to_ind) == en_passant) {
+                        Board::push_pawn_move(pm, cm);
+                    }
                 }
             },
             KING => {
-                // TODO castling LOL
                 let moves = [
                     ChessMove::from_delta(x, y, 0, 1),
                     ChessMove::from_delta(x, y, 1, 1),
@@ -550,9 +732,12 @@ impl Board {
                     ChessMove::from_delta(x, y, -1, 0),
                     ChessMove::from_delta(x, y, -1, 1),
                 ];
-                for opt_cm in IntoIter::new(moves) {
-                    opt_cm.filter(|cm| self.move_is_possible(cm)).map(|cm| pm.push(cm));
+                for opt_cm in moves {
+                    if let Some(cm) = opt_cm.filter(|cm| self.move_is_possible(cm)) {
+                        pm.push(cm);
+                    }
                 }
+                self.possible_castles(x, y, piece & TEAM_MASK, pm);
             },
             ROOK => {
                 // up down left right
@@ -580,8 +765,10 @@ impl Board {
                     ChessMove::from_delta(x, y, -2, 1),
                     ChessMove::from_delta(x, y, -2, -1),
                 ];
-                for opt_cm in IntoIter::new(moves) {
-                    opt_cm.filter(|cm| self.move_is_possible(cm)).map(|cm| pm.push(cm));
+                for opt_cm in moves {
+                    if let Some(cm) = opt_cm.filter(|cm| self.move_is_possible(cm)) {
+                        pm.push(cm);
+                    }
                 }
             },
             QUEEN => {
@@ -599,6 +786,79 @@ impl Board {
         }
     }
 
+    /// A pawn moving onto the last rank has to promote, so it's really four moves
+    fn push_pawn_move(pm: &mut Vec<ChessMove>, cm: ChessMove) {
+        if cm.to_ind.1 == 0 || cm.to_ind.1 == 7 {
+            for promotion in [QUEEN, ROOK, BISHOP, KNIGHT] {
+                pm.push(ChessMove { promotion, ..cm.clone() });
+            }
+        } else {
+            pm.push(cm);
+        }
+    }
+
+    /// Castling for the king on (x, y), if it still has the rights, the squares between it and
+    /// the rook are empty and it doesn't start in or pass through check. Ending up in check is left
+    /// to move_is_legal like any other move.
+    fn possible_castles(&self, x: i8, y: i8, team: u8, pm: &mut Vec<ChessMove>) {
+        let (rank, king_side, queen_side) = if team == TEAM_WHITE {
+            (0, self.white_ks_castle, self.white_qs_castle)
+        } else {
+            (7, self.black_ks_castle, self.black_qs_castle)
+        };
+        if (x, y) != (4, rank) || !(king_side || queen_side) {
+            return
+        }
+
+        let enemy = team ^ TEAM_MASK;
+        let empty = |cols: &[i8]| cols.iter().all(|&col| self.piece_at((col, rank)) == EMPTY);
+        let safe = |cols: &[i8]| cols.iter().all(|&col| !self.square_attacked((col, rank), enemy));
+
+        if king_side && self.piece_at((7, rank)) == team | ROOK && empty(&[5, 6]) && safe(&[4, 5]) {
+            pm.push(ChessMove { from_ind: (4, rank), to_ind: (6, rank), promotion: EMPTY });
+        }
+        if queen_side && self.piece_at((0, rank)) == team | ROOK && empty(&[1, 2, 3]) && safe(&[4, 3]) {
+            pm.push(ChessMove { from_ind: (4, rank), to_ind: (2, rank), promotion: EMPTY });
+        }
+    }
+
+    /// True if any piece of `team` attacks the square, whether or not anything is standing on it
+    fn square_attacked(&self, (x, y): (i8, i8), team: u8) -> bool {
+        let is = |dx: i8, dy: i8, kinds: &[BoardSlot]| {
+            ChessMove::within(x + dx) && ChessMove::within(y + dy)
+                && kinds.iter().any(|&kind| self.piece_at((x + dx, y + dy)) == team | kind)
+        };
+
+        // pawns attack towards the side they're moving to, so look back the other way
+        let pawn_dy = if team == TEAM_WHITE { -1 } else { 1 };
+        if is(-1, pawn_dy, &[PAWN]) || is(1, pawn_dy, &[PAWN]) {
+            return true
+        }
+
+        let knight = [(-1, 2), (1, 2), (-1, -2), (1, -2), (2, 1), (2, -1), (-2, 1), (-2, -1)];
+        if knight.iter().any(|&(dx, dy)| is(dx, dy, &[KNIGHT])) {
+            return true
+        }
+
+        let around = [(0, 1), (1, 1), (1, 0), (1, -1), (0, -1), (-1, -1), (-1, 0), (-1, 1)];
+        if around.iter().any(|&(dx, dy)| is(dx, dy, &[KING])) {
+            return true
+        }
+
+        // slide out from the square until something's in the way
+        around.iter().any(|&(dx, dy)| {
+            let sliders: &[BoardSlot] = if dx == 0 || dy == 0 { &[ROOK, QUEEN] } else { &[BISHOP, QUEEN] };
+            let mut i = 1;
+            while ChessMove::within(x + i * dx) && ChessMove::within(y + i * dy) {
+                if self.piece_at((x + i * dx, y + i * dy)) != EMPTY {
+                    return is(i * dx, i * dy, sliders)
+                }
+                i += 1;
+            }
+            false
+        })
+    }
+
     /// tries to find the location of a piece on the board
     fn find_piece(&self, piece: u8) -> Option<(i8, i8)> {
         for i in 0..8 {
@@ -618,14 +878,14 @@ impl Board {
         let from_slot = self.piece_at(cm.from_ind);
         let to_slot = self.piece_at(cm.to_ind);
 
-        return to_slot == EMPTY || (from_slot & TEAM_MASK != to_slot & TEAM_MASK)
+        to_slot == EMPTY || (from_slot & TEAM_MASK != to_slot & TEAM_MASK)
     }
 
     /// returns true if this move would take a piece
     /// the move is assumed to already have been checked by move_is_possible
-    fn move_is_take(&self, cm: &ChessMove) -> bool {
+    pub fn move_is_take(&self, cm: &ChessMove) -> bool {
         let to_slot = self.piece_at(cm.to_ind);
-        return to_slot != EMPTY
+        to_slot != EMPTY
     }
 
     /// Returns true if the move is actually legal. This means that
@@ -648,16 +908,13 @@ impl Board {
     fn possible_move_line<F>(&self, res: &mut Vec<ChessMove>, func: F)
         where F: Fn(i8) -> Option<ChessMove> {
         for i in 1..8 {
-            let opt_cm: Option<ChessMove> = func(i).filter(|cm| self.move_is_possible(&cm));
+            let opt_cm: Option<ChessMove> = func(i).filter(|cm| self.move_is_possible(cm));
             match opt_cm {
                 Some(cm) => {
-                    if self.move_is_possible(&cm) {
-                        res.push(cm.clone());
-                        // if this is a take move, exit the line scan
-                        if self.move_is_take(&cm) {
-                            return
-                        }
-                    } else {
+                    // if this is a take move, exit the line scan
+                    let is_take = self.move_is_take(&cm);
+                    res.push(cm);
+                    if is_take {
                         return
                     }
                 },
@@ -685,13 +942,146 @@ impl Board {
 
 #[cfg(test)]
 mod tests {
-    use crate::board::Board;
+    use crate::board::{Board, ChessMove, GameResult, EMPTY, KING, PAWN, ROOK, TEAM_BLACK, TEAM_WHITE};
 
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
 
+    fn perft(board: &Board, depth: u32) -> u64 {
+        if depth == 0 {
+            return 1
+        }
+        board.get_pseudo_legal_moves().iter()
+            .map(|cm| board.apply_move(cm))
+            .filter(|child| !child.moved_into_check())
+            .map(|child| perft(&child, depth - 1))
+            .sum()
+    }
+
+    fn fen(fen: &str) -> Board {
+        let parts: Vec<&str> = fen.split(' ').collect();
+        Board::from_fen([parts[0], parts[1], parts[2], parts[3], parts[4], parts[5]]).unwrap()
+    }
+
+    #[test]
+    fn test_perft() {
+        // well known counts, between them covering castling, en passant and promotion
+        assert_eq!(perft(&fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"), 3), 8902);
+        assert_eq!(perft(&fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1"), 2), 2039);
+        assert_eq!(perft(&fen("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1"), 3), 2812);
+        assert_eq!(perft(&fen("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1"), 3), 9467);
+    }
+
+    #[test]
+    fn test_en_passant() {
+        assert_eq!(fen("4k3/8/8/8/4Pp2/8/8/4K3 b - e3 0 1").en_passant(), Some((4, 2)));
+
+        let board = fen("4k3/5p2/8/4P3/8/8/8/4K3 b - - 0 1")
+            .apply_move(&ChessMove::from_long_algebraic_notation("f7f5"));
+        assert_eq!(board.en_passant(), Some((5, 5)));
+
+        // the pawn that's taken isn't on the square the capturing pawn lands on
+        let taken = board.apply_move(&ChessMove::from_long_algebraic_notation("e5f6"));
+        assert_eq!(taken.piece_at((5, 5)), TEAM_WHITE | PAWN);
+        assert_eq!(taken.piece_at((5, 4)), EMPTY);
+        assert_eq!(taken.en_passant(), None);
+
+        // and the chance goes if it isn't taken straight away
+        let later = board.apply_move(&ChessMove::from_long_algebraic_notation("e1d1"));
+        assert_eq!(later.en_passant(), None);
+    }
+
+    #[test]
+    fn test_castling_moves_rook_and_loses_rights() {
+        let board = fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+
+        let board = board.apply_move(&ChessMove::from_long_algebraic_notation("e1g1"));
+        assert_eq!(board.piece_at((6, 0)), TEAM_WHITE | KING);
+        assert_eq!(board.piece_at((5, 0)), TEAM_WHITE | ROOK);
+        assert_eq!(board.piece_at((7, 0)), EMPTY);
+        assert_eq!(board.castling_rights(), (false, false, true, true));
+
+        // black's rook stays black's
+        let board = board.apply_move(&ChessMove::from_long_algebraic_notation("e8c8"));
+        assert_eq!(board.piece_at((2, 7)), TEAM_BLACK | KING);
+        assert_eq!(board.piece_at((3, 7)), TEAM_BLACK | ROOK);
+        assert_eq!(board.piece_at((0, 7)), EMPTY);
+        assert_eq!(board.castling_rights(), (false, false, false, false));
+
+        // a rook moving, or being taken, on its starting square loses just that side
+        let board = fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1")
+            .apply_move(&ChessMove::from_long_algebraic_notation("a1a8"));
+        assert_eq!(board.castling_rights(), (true, false, true, false));
+    }
+
+    #[test]
+    fn test_move_clocks() {
+        // the half move clock counts since the last capture or pawn move, the full move clock
+        // goes up once black has moved
+        let clocks = |board: &Board| (board.half_move_clock, board.full_move_clock);
+        let mut board = fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        for (notation, expected) in [("e2e4", (0, 1)), ("g8f6", (1, 2)), ("g1f3", (2, 2)), ("f6e4", (0, 3)), ("f1e2", (1, 3))] {
+            board = board.apply_move(&ChessMove::from_long_algebraic_notation(notation));
+            assert_eq!(clocks(&board), expected, "after {}", notation);
+        }
+
+        // clocks from a fen carry on from there
+        let board = fen("4k3/8/8/8/8/8/8/R3K3 b - - 37 60").apply_move(&ChessMove::from_long_algebraic_notation("e8d8"));
+        assert_eq!(clocks(&board), (38, 61));
+    }
+
+    #[test]
+    fn test_id_is_the_same_however_the_position_came_about() {
+        let start = fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        let play = |board: &Board, line: &str| line.split(' ')
+            .fold(board.clone(), |board, notation| board.apply_move(&ChessMove::from_long_algebraic_notation(notation)));
+
+        // black to move
+        let e4 = fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1");
+        assert_eq!(play(&start, "e2e4").id(), e4.id());
+
+        // the clocks don't count, only whose move it is
+        let nf3 = fen("rnbqkbnr/pppppppp/8/8/8/5N2/PPPPPPPP/RNBQKB1R b KQkq - 7 9");
+        assert_eq!(play(&start, "g1f3").id(), nf3.id());
+        assert_eq!(play(&start, "g1f3 g8f6 b1c3 f6g8 c3b1").id(), nf3.id());
+        assert_eq!(play(&start, "g1f3 g8f6 f3g1 f6g8").id(), start.id());
+
+        // but not the same position with the other side to move
+        assert_ne!(play(&start, "g1f3 g8f6 f3g1").id(), fen("rnbqkb1r/pppppppp/5n2/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").id());
+    }
+
+    #[test]
+    fn test_game_result() {
+        assert_eq!(fen("R5k1/5ppp/8/8/8/8/8/6K1 b - - 0 1").game_result(), Some((GameResult::WhiteWin, "checkmate")));
+        assert_eq!(fen("7k/5Q2/8/8/8/8/8/6K1 b - - 0 1").game_result(), Some((GameResult::Draw, "stalemate")));
+        assert_eq!(fen("7k/8/8/8/8/8/2N5/6K1 w - - 0 1").game_result(), Some((GameResult::Draw, "insufficient material")));
+        assert_eq!(fen("7k/8/8/8/8/8/2R5/6K1 w - - 100 80").game_result(), Some((GameResult::Draw, "fifty move rule")));
+        assert_eq!(fen("7k/8/8/8/8/8/2R5/6K1 w - - 99 80").game_result(), None);
+    }
+
+    #[test]
+    fn test_promotion_notation() {
+        let board = fen("8/4P1k1/8/8/8/8/8/4K3 w - - 0 1");
+        let promotions: Vec<String> = board.get_pseudo_legal_moves().iter()
+            .filter(|cm| cm.promotion() != EMPTY)
+            .map(|cm| cm.to_long_algebraic_notation())
+            .collect();
+        assert_eq!(promotions, vec!["e7e8q", "e7e8r", "e7e8b", "e7e8n"]);
+        assert_eq!(ChessMove::from_long_algebraic_notation("e7e8q").to_long_algebraic_notation(), "e7e8q");
+    }
+
+    #[test]
+    fn test_to_fen() {
+        let kiwipete = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        assert_eq!(fen(kiwipete).to_fen(), kiwipete);
+        let board = fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1")
+            .apply_move(&ChessMove::from_long_algebraic_notation("e2e4"))
+            .apply_move(&ChessMove::from_long_algebraic_notation("e7e5"));
+        assert_eq!(board.to_fen(), "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2");
+    }
+
     #[test]
     fn test_empty_new() {
         assert_eq!(
@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+use std::fs;
+
+use rand::Rng;
+
+use crate::board::{Board, ChessMove, BISHOP, EMPTY, KING, KNIGHT, QUEEN, ROOK, UNIT_MASK};
+use crate::zobrist::polyglot_key;
+
+pub use crate::board::GameResult;
+
+// Polyglot opening books. A book is a flat file of 16 byte entries sorted by position key, all
+// big endian:
+//
+//   key: u64, move: u16, weight: u16, learn: u32
+//
+// The key is the Polyglot zobrist key of the position (see zobrist::polyglot_key) and the move
+// packs the to column, to rank, from column, from rank and promotion into 3 bits each, lowest
+// first. Promotions go 1 = knight up to 4 = queen. Castling is written as the king taking its own
+// rook, so e1g1 is stored as e1h1. The learn field isn't used by anyone, we write it as zero.
+
+const ENTRY_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selection {
+    /// Always the move with the highest weight
+    Best,
+    /// Any move, picked with chance proportional to its weight
+    WeightedRandom,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Entry {
+    key: u64,
+    raw_move: u16,
+    weight: u16,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Book {
+    entries: Vec<Entry>,
+}
+
+impl Book {
+    pub fn open(path: &str) -> std::result::Result<Book, String> {
+        let bytes = fs::read(path).map_err(|err| format!("can't read book {}: {}", path, err))?;
+        Book::from_bytes(&bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> std::result::Result<Book, String> {
+        if !bytes.len().is_multiple_of(ENTRY_SIZE) {
+            return Err(format!("book is {} bytes, not a whole number of entries", bytes.len()))
+        }
+
+        let entries: Vec<Entry> = bytes.chunks(ENTRY_SIZE)
+            .map(|chunk| Entry {
+                key: u64::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3], chunk[4], chunk[5], chunk[6], chunk[7]]),
+                raw_move: u16::from_be_bytes([chunk[8], chunk[9]]),
+                weight: u16::from_be_bytes([chunk[10], chunk[11]]),
+            })
+            .collect();
+
+        // lookups binary search, so an unsorted book would just silently miss positions
+        if entries.windows(2).any(|pair| pair[0].key > pair[1].key) {
+            return Err(String::from("book entries aren't sorted by key"))
+        }
+
+        Ok(Book { entries })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Every move the book has for this position along with its weight, in file order.
+    pub fn moves(&self, board: &Board) -> Vec<(ChessMove, u16)> {
+        let key = polyglot_key(board);
+        let start = self.entries.partition_point(|entry| entry.key < key);
+        self.entries[start..].iter()
+            .take_while(|entry| entry.key == key)
+            .map(|entry| (decode_move(board, entry.raw_move), entry.weight))
+            .collect()
+    }
+
+    /// Picks a book move for this position, if the book has a playable one.
+    pub fn pick(&self, board: &Board, selection: Selection) -> Option<ChessMove> {
+        // a book built for a different engine (or a key collision) can suggest nonsense, so only
+        // take moves the board agrees with
+        let legal = board.clone().get_legal_moves();
+        let moves: Vec<(ChessMove, u16)> = self.moves(board).into_iter()
+            .filter(|(cm, weight)| *weight > 0 && legal.contains(cm))
+            .collect();
+
+        match selection {
+            Selection::Best => moves.into_iter()
+                .max_by_key(|(_, weight)| *weight)
+                .map(|(cm, _)| cm),
+            Selection::WeightedRandom => {
+                let total: u32 = moves.iter().map(|(_, weight)| *weight as u32).sum();
+                if total == 0 {
+                    return None
+                }
+                let mut roll = rand::thread_rng().gen_range(0..total);
+                for (cm, weight) in moves {
+                    if roll < weight as u32 {
+                        return Some(cm)
+                    }
+                    roll -= weight as u32;
+                }
+                None
+            }
+        }
+    }
+}
+
+fn is_castle(board: &Board, chess_move: &ChessMove) -> bool {
+    let (fx, fy) = chess_move.from_ind();
+    let (tx, ty) = chess_move.to_ind();
+    board.piece_at((fx, fy)) & UNIT_MASK == KING && fy == ty && (tx - fx).abs() == 2
+}
+
+/// Turns a Polyglot move into ours, which needs the board to tell castling apart from the king
+/// taking a rook.
+pub fn decode_move(board: &Board, raw_move: u16) -> ChessMove {
+    let part = |shift: u16| ((raw_move >> shift) & 7) as i8;
+    let from = (part(6), part(9));
+    let mut to = (part(0), part(3));
+
+    if board.piece_at(from) & UNIT_MASK == KING && from.0 == 4 && from.1 == to.1 && (to.0 == 0 || to.0 == 7) {
+        to.0 = if to.0 == 7 { 6 } else { 2 };
+    }
+
+    let promotion = match part(12) {
+        1 => KNIGHT,
+        2 => BISHOP,
+        3 => ROOK,
+        4 => QUEEN,
+        _ => EMPTY,
+    };
+
+    ChessMove::new(from, to, promotion)
+}
+
+/// Turns one of our moves into a Polyglot move, made from the given board.
+pub fn encode_move(board: &Board, chess_move: &ChessMove) -> u16 {
+    let (fx, fy) = chess_move.from_ind();
+    let (mut tx, ty) = chess_move.to_ind();
+
+    if is_castle(board, chess_move) {
+        tx = if tx == 6 { 7 } else { 0 };
+    }
+
+    let promotion = match chess_move.promotion() & UNIT_MASK {
+        KNIGHT => 1,
+        BISHOP => 2,
+        ROOK => 3,
+        QUEEN => 4,
+        _ => 0,
+    };
+
+    (tx as u16) | (ty as u16) << 3 | (fx as u16) << 6 | (fy as u16) << 9 | promotion << 12
+}
+
+/// Collects moves out of games and writes them out as a book. Each time a move is played it
+/// scores 2 for the side that went on to win, 1 for a draw and nothing for a loss, the same as
+/// Polyglot's own `make-book`.
+#[derive(Debug, Clone, Default)]
+pub struct BookBuilder {
+    weights: HashMap<(u64, u16), u32>,
+}
+
+impl BookBuilder {
+    pub fn new() -> BookBuilder {
+        BookBuilder::default()
+    }
+
+    /// Number of distinct (position, move) pairs seen so far
+    pub fn len(&self) -> usize {
+        self.weights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.weights.is_empty()
+    }
+
+    /// Adds the first `max_ply` moves of a game played from `start`.
+    pub fn add_game(&mut self, start: &Board, moves: &[ChessMove], result: GameResult, max_ply: usize) {
+        let mut board = start.clone();
+        for chess_move in moves.iter().take(max_ply) {
+            let score = match (result, board.is_whites_move()) {
+                (GameResult::Draw, _) => 1,
+                (GameResult::WhiteWin, true) | (GameResult::BlackWin, false) => 2,
+                _ => 0,
+            };
+            let key = (polyglot_key(&board), encode_move(&board, chess_move));
+            *self.weights.entry(key).or_insert(0) += score;
+            board = board.apply_move(chess_move);
+        }
+    }
+
+    /// The book as it would be written to disk. Moves that never scored are left out, and if any
+    /// weight got too big for 16 bits every weight is scaled down to fit.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let max = self.weights.values().copied().max().unwrap_or(0);
+        let scale = (max as u64).div_ceil(u16::MAX as u64).max(1);
+
+        let mut entries: Vec<Entry> = self.weights.iter()
+            .map(|(&(key, raw_move), &weight)| Entry { key, raw_move, weight: (weight as u64 / scale) as u16 })
+            .filter(|entry| entry.weight > 0)
+            .collect();
+        entries.sort_by_key(|entry| (entry.key, u16::MAX - entry.weight, entry.raw_move));
+
+        let mut bytes = Vec::with_capacity(entries.len() * ENTRY_SIZE);
+        for entry in entries {
+            bytes.extend_from_slice(&entry.key.to_be_bytes());
+            bytes.extend_from_slice(&entry.raw_move.to_be_bytes());
+            bytes.extend_from_slice(&entry.weight.to_be_bytes());
+            bytes.extend_from_slice(&0u32.to_be_bytes());
+        }
+        bytes
+    }
+
+    pub fn write(&self, path: &str) -> std::result::Result<(), String> {
+        fs::write(path, self.to_bytes()).map_err(|err| format!("can't write book {}: {}", path, err))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::board::{Board, ChessMove};
+    use crate::book::{decode_move, encode_move, Book, BookBuilder, GameResult, Selection};
+
+    fn start() -> Board {
+        Board::from_fen(["rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR", "w", "KQkq", "-", "0", "1"]).unwrap()
+    }
+
+    #[test]
+    fn test_castling_is_king_takes_rook() {
+        let board = Board::from_fen(["r3k2r/8/8/8/8/8/8/R3K2R", "w", "KQkq", "-", "0", "1"]).unwrap();
+        let castle = ChessMove::from_long_algebraic_notation("e1g1");
+        let raw = encode_move(&board, &castle);
+        assert_eq!(decode_move(&board, raw), castle);
+        assert_eq!(decode_move(&board, raw).to_ind(), (6, 0));
+        assert_eq!(raw & 0o77, 7);
+    }
+
+    #[test]
+    fn test_built_book_round_trips() {
+        let e4 = ChessMove::from_long_algebraic_notation("e2e4");
+        let d4 = ChessMove::from_long_algebraic_notation("d2d4");
+        let e5 = ChessMove::from_long_algebraic_notation("e7e5");
+
+        let mut builder = BookBuilder::new();
+        builder.add_game(&start(), &[e4.clone(), e5.clone()], GameResult::WhiteWin, 20);
+        builder.add_game(&start(), &[e4.clone(), e5], GameResult::Draw, 20);
+        builder.add_game(&start(), std::slice::from_ref(&d4), GameResult::Draw, 20);
+
+        let book = Book::from_bytes(&builder.to_bytes()).unwrap();
+        // e7e5 only ever lost or drew, so it's in there with the draw's weight
+        assert_eq!(book.len(), 3);
+        assert_eq!(book.moves(&start()), vec![(e4.clone(), 3), (d4, 1)]);
+        assert_eq!(book.pick(&start(), Selection::Best), Some(e4));
+        assert!(book.pick(&start(), Selection::WeightedRandom).is_some());
+    }
+}
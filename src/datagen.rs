@@ -0,0 +1,357 @@
+use std::convert::TryInto;
+use std::fs;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::board::{Board, ChessMove, GameResult, EMPTY, TEAM_MASK, TEAM_WHITE};
+use crate::eval::piece_index;
+use crate::search::{self, SearchLimits, Searcher};
+use crate::table::Bound;
+use crate::tournament;
+use crate::uci;
+
+// Training data from self-play. Games start from the start position with a few random moves
+// played, then go on at a fixed number of nodes a move, which keeps them quick and makes every
+// run with the same seed and settings come out exactly the same. Positions are kept when they're
+// quiet enough to say something about the evaluation: not in check, not a mate score, and with
+// the search not wanting to capture or promote straight away.
+//
+// Each position gets the search's score (from white's side) and, once the game is over, its
+// result. There are two ways to write them out:
+//
+//  - text, one position per line as "FEN;score;result", the result being 1.0, 0.5 or 0.0 for
+//    white, which the tuner reads as is
+//  - binary, a fixed RECORD_SIZE bytes each, all little endian:
+//      occupied squares     u64, bit rank * 8 + column
+//      pieces               16 bytes, a nibble per occupied square in bit order, low nibble
+//                           first: eval's piece index, plus 8 for black
+//      side and castling    u8, bit 0 black to move, bits 1-4 castling KQkq
+//      en passant           u8, the square as rank * 8 + column, or 64 for none
+//      half move clock      u8
+//      full move number     u16
+//      score                i16
+//      result               u8, 2 white win, 1 draw, 0 black win
+
+pub const RECORD_SIZE: usize = 32;
+
+const PIECE_LETTERS: [char; 6] = ['P', 'N', 'B', 'R', 'Q', 'K'];
+
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub board: Board,
+    /// Search score in centipawns, positive favouring white
+    pub score: i32,
+    pub result: GameResult,
+}
+
+fn result_value(result: GameResult) -> f64 {
+    match result {
+        GameResult::WhiteWin => 1.0,
+        GameResult::Draw => 0.5,
+        GameResult::BlackWin => 0.0,
+    }
+}
+
+impl Record {
+    pub fn to_text(&self) -> String {
+        format!("{};{};{:.1}", self.board.to_fen(), self.score, result_value(self.result))
+    }
+
+    /// The reverse of `to_text`
+    pub fn parse(line: &str) -> std::result::Result<Record, String> {
+        let parts: Vec<&str> = line.split(';').map(str::trim).collect();
+        let (fen, score, result) = match parts[..] {
+            [fen, score, result] => (fen, score, result),
+            _ => return Err(format!("not a FEN;score;result line: {}", line)),
+        };
+        let board = uci::position_board(Some(fen), &[])?;
+        let score = score.parse::<i32>().map_err(|_| format!("bad score: {}", line))?;
+        let result = match result {
+            "1.0" => GameResult::WhiteWin,
+            "0.5" => GameResult::Draw,
+            "0.0" => GameResult::BlackWin,
+            _ => return Err(format!("bad result: {}", line)),
+        };
+        Ok(Record { board, score, result })
+    }
+
+    pub fn to_bytes(&self) -> [u8; RECORD_SIZE] {
+        let mut bytes = [0u8; RECORD_SIZE];
+        let mut occupied = 0u64;
+        let mut count = 0;
+        for sq in 0..64 {
+            let slot = self.board.piece_at(((sq % 8) as i8, (sq / 8) as i8));
+            if let Some(index) = piece_index(slot) {
+                let nibble = index as u8 | if slot & TEAM_MASK == TEAM_WHITE { 0 } else { 8 };
+                occupied |= 1 << sq;
+                bytes[8 + count / 2] |= nibble << (4 * (count % 2));
+                count += 1;
+            }
+        }
+        bytes[..8].copy_from_slice(&occupied.to_le_bytes());
+
+        let (wk, wq, bk, bq) = self.board.castling_rights();
+        bytes[24] = !self.board.is_whites_move() as u8 | (wk as u8) << 1 | (wq as u8) << 2 | (bk as u8) << 3 | (bq as u8) << 4;
+        bytes[25] = self.board.en_passant().map(|(x, y)| (y * 8 + x) as u8).unwrap_or(64);
+        bytes[26] = self.board.half_move_clock().clamp(0, 255) as u8;
+        bytes[27..29].copy_from_slice(&(self.board.full_move_number().clamp(0, u16::MAX as i32) as u16).to_le_bytes());
+        bytes[29..31].copy_from_slice(&(self.score.clamp(i16::MIN as i32, i16::MAX as i32) as i16).to_le_bytes());
+        bytes[31] = match self.result {
+            GameResult::WhiteWin => 2,
+            GameResult::Draw => 1,
+            GameResult::BlackWin => 0,
+        };
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> std::result::Result<Record, String> {
+        if bytes.len() != RECORD_SIZE {
+            return Err(format!("a record is {} bytes, not {}", RECORD_SIZE, bytes.len()))
+        }
+        let mut occupied = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+        if occupied.count_ones() > 32 {
+            return Err(String::from("bad record: more than 32 pieces"))
+        }
+
+        // piece letters by square, then the same FEN the board would write
+        let mut squares = [None; 64];
+        let mut count = 0;
+        while occupied != 0 {
+            let sq = occupied.trailing_zeros() as usize;
+            occupied &= occupied - 1;
+            let nibble = (bytes[8 + count / 2] >> (4 * (count % 2))) & 0xf;
+            let letter = *PIECE_LETTERS.get((nibble & 7) as usize).ok_or("bad record: unknown piece")?;
+            squares[sq] = Some(if nibble & 8 == 0 { letter } else { letter.to_ascii_lowercase() });
+            count += 1;
+        }
+        let mut ranks = Vec::with_capacity(8);
+        for y in (0..8).rev() {
+            let mut rank = String::new();
+            let mut empty = 0;
+            for x in 0..8 {
+                match squares[y * 8 + x] {
+                    Some(letter) => {
+                        if empty > 0 {
+                            rank.push_str(empty.to_string().as_str());
+                            empty = 0;
+                        }
+                        rank.push(letter);
+                    },
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                rank.push_str(empty.to_string().as_str());
+            }
+            ranks.push(rank);
+        }
+
+        let flags = bytes[24];
+        let castling: String = "KQkq".chars().enumerate()
+            .filter(|(i, _)| flags & (2 << i) != 0)
+            .map(|(_, c)| c)
+            .collect();
+        let en_passant = match bytes[25] {
+            64 => String::from("-"),
+            sq if sq < 64 => format!("{}{}", (b'a' + sq % 8) as char, sq / 8 + 1),
+            _ => return Err(String::from("bad record: en passant square")),
+        };
+        let fen = format!(
+            "{} {} {} {} {} {}",
+            ranks.join("/"),
+            if flags & 1 == 0 { "w" } else { "b" },
+            if castling.is_empty() { "-" } else { castling.as_str() },
+            en_passant,
+            bytes[26],
+            u16::from_le_bytes([bytes[27], bytes[28]]),
+        );
+        let board = uci::position_board(Some(fen.as_str()), &[])?;
+
+        let score = i16::from_le_bytes([bytes[29], bytes[30]]) as i32;
+        let result = match bytes[31] {
+            2 => GameResult::WhiteWin,
+            1 => GameResult::Draw,
+            0 => GameResult::BlackWin,
+            _ => return Err(String::from("bad record: result")),
+        };
+        Ok(Record { board, score, result })
+    }
+}
+
+/// Every record in a file, read as binary if the name ends in .bin and as text lines otherwise
+pub fn load(path: &str) -> std::result::Result<Vec<Record>, String> {
+    if !path.ends_with(".bin") {
+        let text = fs::read_to_string(path).map_err(|err| format!("can't read {}: {}", path, err))?;
+        return text.lines().map(str::trim).filter(|line| !line.is_empty()).map(Record::parse).collect()
+    }
+    let bytes = fs::read(path).map_err(|err| format!("can't read {}: {}", path, err))?;
+    if bytes.len() % RECORD_SIZE != 0 {
+        return Err(format!("{} isn't a whole number of records", path))
+    }
+    bytes.chunks(RECORD_SIZE).map(Record::from_bytes).collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Settings {
+    pub nodes: u64,
+    /// Random moves at the start of every game
+    pub random_plies: usize,
+    /// Openings the search scores further off level than this are thrown away
+    pub max_opening_score: i32,
+    /// A game is given to a side once its score has been at least this for `resign_plies` in a row
+    pub resign_score: i32,
+    pub resign_plies: usize,
+    /// A game is called a draw once the score has been within this of level for `draw_plies` in a
+    /// row, from `draw_after` plies on
+    pub draw_score: i32,
+    pub draw_plies: usize,
+    pub draw_after: usize,
+    pub max_plies: usize,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            nodes: 5000,
+            random_plies: 8,
+            max_opening_score: 300,
+            resign_score: 2000,
+            resign_plies: 6,
+            draw_score: 10,
+            draw_plies: 12,
+            draw_after: 80,
+            max_plies: 400,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Generator {
+    settings: Settings,
+    searcher: Searcher,
+    rng: StdRng,
+}
+
+impl Generator {
+    pub fn new(settings: Settings, seed: u64) -> Generator {
+        let mut searcher = Searcher::new();
+        searcher.set_threads(1);
+        Generator { settings, searcher, rng: StdRng::seed_from_u64(seed) }
+    }
+
+    pub fn searcher(&mut self) -> &mut Searcher {
+        &mut self.searcher
+    }
+
+    /// Best move and its score for the side to move
+    fn search(&mut self, board: &Board) -> Option<(ChessMove, i32)> {
+        let limits = SearchLimits { nodes: Some(self.settings.nodes), ..Default::default() };
+        let mut score = 0;
+        let best = self.searcher.find_best_move(board.clone(), &limits, |info| {
+            if info.bound == Bound::Exact {
+                score = info.score;
+            }
+        });
+        best.ok().map(|best| (best, score))
+    }
+
+    /// A start position with random moves played, along with the ids of the positions on the
+    /// way so repetitions count from the start
+    fn opening(&mut self) -> (Board, Vec<u64>) {
+        'retry: loop {
+            let mut board = uci::position_board(None, &[]).unwrap();
+            let mut history = vec![board.id()];
+            for _ in 0..self.settings.random_plies {
+                let moves = board.clone().get_legal_moves();
+                if moves.is_empty() {
+                    continue 'retry
+                }
+                board = board.apply_move(&moves[self.rng.gen_range(0..moves.len())]);
+                history.push(board.id());
+            }
+            if tournament::adjudicate(&board, &history).is_some() {
+                continue
+            }
+            match self.search(&board) {
+                Some((_, score)) if score.abs() <= self.settings.max_opening_score => return (board, history),
+                _ => continue,
+            }
+        }
+    }
+
+    /// Plays one game and returns the positions worth keeping from it
+    pub fn play_game(&mut self) -> Vec<Record> {
+        self.searcher.clear();
+        let (mut board, mut history) = self.opening();
+        let mut positions = Vec::new();
+        let mut resign_count = 0;
+        let mut draw_count = 0;
+        let mut plies = 0;
+
+        let result = loop {
+            if let Some((result, _)) = tournament::adjudicate(&board, &history) {
+                break result
+            }
+            if plies >= self.settings.max_plies {
+                break GameResult::Draw
+            }
+            let (best, score) = match self.search(&board) {
+                Some(found) => found,
+                None => break GameResult::Draw,
+            };
+            let white_score = if board.is_whites_move() { score } else { -score };
+
+            let quiet = !board.in_check() && !board.move_is_take(&best) && best.promotion() == EMPTY;
+            if quiet && search::mate_in(score).is_none() {
+                positions.push((board.clone(), white_score));
+            }
+
+            resign_count = if white_score.abs() >= self.settings.resign_score { resign_count + 1 } else { 0 };
+            if resign_count >= self.settings.resign_plies {
+                break if white_score > 0 { GameResult::WhiteWin } else { GameResult::BlackWin }
+            }
+            let level = plies >= self.settings.draw_after && white_score.abs() <= self.settings.draw_score;
+            draw_count = if level { draw_count + 1 } else { 0 };
+            if draw_count >= self.settings.draw_plies {
+                break GameResult::Draw
+            }
+
+            board = board.apply_move(&best);
+            history.push(board.id());
+            plies += 1;
+        };
+
+        positions.into_iter().map(|(board, score)| Record { board, score, result }).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::board::GameResult;
+    use crate::datagen::{Generator, Record, Settings};
+    use crate::uci;
+
+    #[test]
+    fn test_record_formats() {
+        let board = uci::position_board(Some("r3k2r/pp3ppp/8/3pP3/8/8/PPP2PPP/R3K2R w Kq d6 0 14"), &[]).unwrap();
+        let record = Record { board, score: -35, result: GameResult::BlackWin };
+        assert_eq!(record.to_text(), "r3k2r/pp3ppp/8/3pP3/8/8/PPP2PPP/R3K2R w Kq d6 0 14;-35;0.0");
+        assert_eq!(Record::parse(record.to_text().as_str()).unwrap().to_text(), record.to_text());
+
+        let decoded = Record::from_bytes(&record.to_bytes()).unwrap();
+        assert_eq!(decoded.board.to_fen(), record.board.to_fen());
+        assert_eq!((decoded.score, decoded.result), (-35, GameResult::BlackWin));
+        assert!(Record::from_bytes(&[0; 5]).is_err());
+    }
+
+    #[test]
+    fn test_games_repeat_with_the_same_seed() {
+        let settings = Settings { nodes: 200, max_plies: 16, ..Default::default() };
+        let first: Vec<String> = Generator::new(settings.clone(), 7).play_game().iter().map(Record::to_text).collect();
+        let second: Vec<String> = Generator::new(settings, 7).play_game().iter().map(Record::to_text).collect();
+        assert!(!first.is_empty());
+        assert_eq!(first, second);
+    }
+}
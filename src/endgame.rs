@@ -0,0 +1,476 @@
+use std::sync::OnceLock;
+
+use crate::board::{Board, TEAM_MASK, TEAM_WHITE};
+use crate::eval::{piece_index, EG_VALUE, MG_VALUE};
+
+// Endgame knowledge. Some material configurations the general evaluation gets badly wrong: it
+// has no plan for mating a lone king, thinks a bishop up is winning, and can't tell a won king
+// and pawn ending from a dead draw. Those are recognised here from the material on the board and
+// either get their own score outright, or have the endgame half of the normal score scaled down.
+//
+// Squares are numbered rank * 8 + column like the pawn bitboards, and teams are [white, black].
+
+/// Score for a position that's won but not yet a forced mate the search can see. Well clear of
+/// anything the normal evaluation gives, and well under the mate scores.
+pub const KNOWN_WIN: i32 = 10_000;
+
+/// Scale factors are out of this; the endgame score is multiplied by scale / SCALE_NORMAL
+pub const SCALE_NORMAL: i32 = 64;
+const SCALE_DRAW: i32 = 0;
+const SCALE_OPPOSITE_BISHOPS: i32 = 16;
+const SCALE_OPPOSITE_BISHOPS_WITH_PIECES: i32 = 44;
+const SCALE_NO_PAWNS_PIECES_UP: i32 = 4;
+
+// indexed like eval's piece arrays
+const PAWN: usize = 0;
+const KNIGHT: usize = 1;
+const BISHOP: usize = 2;
+const ROOK: usize = 3;
+const QUEEN: usize = 4;
+const KING: usize = 5;
+
+const DARK_SQUARES: u64 = 0xaa55_aa55_aa55_aa55;
+const FILE_A: u64 = 0x0101_0101_0101_0101;
+const FILE_H: u64 = FILE_A << 7;
+const BACK_RANKS: u64 = 0xff00_0000_0000_00ff;
+
+fn distance(a: usize, b: usize) -> i32 {
+    let dx = (a % 8) as i32 - (b % 8) as i32;
+    let dy = (a / 8) as i32 - (b / 8) as i32;
+    dx.abs().max(dy.abs())
+}
+
+/// More for a king the closer it is to the edge of the board
+fn push_to_edge(sq: usize) -> i32 {
+    let (x, y) = ((sq % 8) as i32, (sq / 8) as i32);
+    let from_centre = (3 - x).max(x - 4) + (3 - y).max(y - 4);
+    from_centre * 20
+}
+
+/// More the closer the two kings are
+fn push_close(a: usize, b: usize) -> i32 {
+    140 - 20 * distance(a, b)
+}
+
+/// Where every piece on the board stands, a bitboard per team and piece kind.
+#[derive(Debug, Clone, Default)]
+pub struct Material {
+    pub pieces: [[u64; 6]; 2],
+}
+
+impl Material {
+    pub fn from_board(board: &Board) -> Material {
+        let mut material = Material::default();
+        for x in 0..8 {
+            for y in 0..8 {
+                let slot = board.piece_at((x, y));
+                if let Some(index) = piece_index(slot) {
+                    let team = if slot & TEAM_MASK == TEAM_WHITE { 0 } else { 1 };
+                    material.pieces[team][index] |= 1 << (y * 8 + x);
+                }
+            }
+        }
+        material
+    }
+
+    pub fn count(&self, team: usize, piece: usize) -> u32 {
+        self.pieces[team][piece].count_ones()
+    }
+
+    /// Midgame value of everything but the pawns and king
+    pub fn non_pawn(&self, team: usize) -> i32 {
+        (KNIGHT..KING).map(|piece| self.count(team, piece) as i32 * MG_VALUE[piece]).sum()
+    }
+
+    fn king(&self, team: usize) -> usize {
+        self.pieces[team][KING].trailing_zeros() as usize
+    }
+
+    fn bare_king(&self, team: usize) -> bool {
+        self.pieces[team][..KING].iter().all(|&bits| bits == 0)
+    }
+
+    /// True if the team has nothing at all besides its king and the given pieces
+    fn only(&self, team: usize, pieces: &[usize]) -> bool {
+        (PAWN..KING).all(|piece| pieces.contains(&piece) || self.pieces[team][piece] == 0)
+    }
+
+    fn bishops_on_both_colours(&self, team: usize) -> bool {
+        let bishops = self.pieces[team][BISHOP];
+        bishops & DARK_SQUARES != 0 && bishops & !DARK_SQUARES != 0
+    }
+
+    /// Can't mate even with the other side helping
+    fn insufficient(&self, team: usize) -> bool {
+        let minors = self.count(team, KNIGHT) + self.count(team, BISHOP);
+        self.only(team, &[KNIGHT, BISHOP])
+            && (minors <= 1 || (self.count(team, BISHOP) == 0 && minors == 2))
+    }
+}
+
+/// A score for the position if its material is one of the recognised endgames. Positive favours
+/// white, same as the normal evaluation.
+pub fn evaluate(board: &Board, material: &Material) -> Option<i32> {
+    if material.pieces[0][KING] == 0 || material.pieces[1][KING] == 0 {
+        return None
+    }
+
+    if material.insufficient(0) && material.insufficient(1) {
+        return Some(0)
+    }
+
+    for strong in 0..2 {
+        let weak = 1 - strong;
+        if !material.bare_king(weak) {
+            continue
+        }
+        let sign = if strong == 0 { 1 } else { -1 };
+
+        // a pawn on the first or last rank can only come from a made up FEN, and isn't in the
+        // bitbase, so that's left to the normal evaluation
+        let score = if material.only(strong, &[PAWN]) && material.count(strong, PAWN) == 1 {
+            if material.pieces[strong][PAWN] & BACK_RANKS != 0 {
+                continue
+            }
+            kpk(board, material, strong)
+        } else if material.only(strong, &[KNIGHT, BISHOP])
+            && material.count(strong, KNIGHT) == 1
+            && material.count(strong, BISHOP) == 1 {
+            kbnk(material, strong)
+        } else if material.non_pawn(strong) >= MG_VALUE[ROOK] {
+            kxk(material, strong)
+        } else {
+            continue
+        };
+        return Some(sign * score)
+    }
+
+    None
+}
+
+/// Mating a lone king with enough material: drive it to the edge and bring our king up
+fn kxk(material: &Material, strong: usize) -> i32 {
+    let weak = 1 - strong;
+    let (strong_king, weak_king) = (material.king(strong), material.king(weak));
+
+    let mut score = (PAWN..KING)
+        .map(|piece| material.count(strong, piece) as i32 * EG_VALUE[piece])
+        .sum::<i32>()
+        + push_to_edge(weak_king)
+        + push_close(strong_king, weak_king);
+
+    let pieces = material.pieces[strong];
+    if pieces[QUEEN] != 0
+        || pieces[ROOK] != 0
+        || (pieces[BISHOP] != 0 && pieces[KNIGHT] != 0)
+        || material.bishops_on_both_colours(strong) {
+        score += KNOWN_WIN;
+    }
+    score
+}
+
+/// Bishop and knight can only mate in a corner the bishop covers, so the lone king is driven to
+/// one of those rather than just any edge.
+fn kbnk(material: &Material, strong: usize) -> i32 {
+    let weak = 1 - strong;
+    let (strong_king, weak_king) = (material.king(strong), material.king(weak));
+
+    let corners: [usize; 2] = if material.pieces[strong][BISHOP] & DARK_SQUARES != 0 {
+        [0, 63]
+    } else {
+        [7, 56]
+    };
+    let manhattan = |a: usize, b: usize| {
+        ((a % 8) as i32 - (b % 8) as i32).abs() + ((a / 8) as i32 - (b / 8) as i32).abs()
+    };
+    let to_corner = corners.iter().map(|&corner| manhattan(corner, weak_king)).min().unwrap_or(0);
+
+    KNOWN_WIN + EG_VALUE[BISHOP] + EG_VALUE[KNIGHT]
+        + push_close(strong_king, weak_king)
+        + 40 * (7 - to_corner.min(7))
+}
+
+/// King and pawn against king, straight from the bitbase
+fn kpk(board: &Board, material: &Material, strong: usize) -> i32 {
+    let pawn = material.pieces[strong][PAWN].trailing_zeros() as usize;
+    let strong_to_move = board.is_whites_move() == (strong == 0);
+
+    if kpk_win(strong == 0, strong_to_move, material.king(strong), pawn, material.king(1 - strong)) {
+        let rank = if strong == 0 { pawn / 8 } else { 7 - pawn / 8 };
+        KNOWN_WIN + EG_VALUE[PAWN] + rank as i32 * 10
+    } else {
+        0
+    }
+}
+
+/// How much of the normal endgame score `strong`, the side it favours, actually gets to keep,
+/// out of SCALE_NORMAL.
+pub fn scale_factor(material: &Material, strong: usize) -> i32 {
+    let weak = 1 - strong;
+    let strong_pawns = material.pieces[strong][PAWN];
+
+    // opposite coloured bishops are very hard to win with, more so with nothing else left
+    if material.count(strong, BISHOP) == 1 && material.count(weak, BISHOP) == 1 {
+        let strong_dark = material.pieces[strong][BISHOP] & DARK_SQUARES != 0;
+        let weak_dark = material.pieces[weak][BISHOP] & DARK_SQUARES != 0;
+        if strong_dark != weak_dark {
+            return if material.non_pawn(strong) == MG_VALUE[BISHOP] && material.non_pawn(weak) == MG_VALUE[BISHOP] {
+                SCALE_OPPOSITE_BISHOPS
+            } else {
+                SCALE_OPPOSITE_BISHOPS_WITH_PIECES
+            }
+        }
+    }
+
+    // rook pawns (maybe with the wrong bishop) against a king sat in front of them can't win
+    let on_rook_file = strong_pawns != 0 && (strong_pawns & !FILE_A == 0 || strong_pawns & !FILE_H == 0);
+    if on_rook_file && material.non_pawn(weak) == 0 {
+        let file = (strong_pawns.trailing_zeros() % 8) as usize;
+        let queening = if strong == 0 { 56 + file } else { file };
+        let queening_dark = DARK_SQUARES & (1 << queening) != 0;
+        let bishops = material.pieces[strong][BISHOP];
+
+        let wrong_bishop = material.only(strong, &[PAWN, BISHOP])
+            && material.count(strong, BISHOP) == 1
+            && (bishops & DARK_SQUARES != 0) != queening_dark;
+        let no_pieces = material.only(strong, &[PAWN]);
+
+        if (wrong_bishop || no_pieces) && distance(material.king(weak), queening) <= 1 {
+            return SCALE_DRAW
+        }
+    }
+
+    // without pawns you need at least a rook more to win
+    if strong_pawns == 0 && material.non_pawn(strong) - material.non_pawn(weak) <= MG_VALUE[BISHOP] {
+        return if material.non_pawn(strong) < MG_VALUE[ROOK] {
+            SCALE_DRAW
+        } else {
+            SCALE_NO_PAWNS_PIECES_UP
+        }
+    }
+
+    SCALE_NORMAL
+}
+
+//
+// KPK bitbase. Every position with a white pawn on files a to d (the rest are mirrors) is worked
+// out by going backwards from the positions whose result is obvious until nothing changes, and
+// the wins are kept as one bit each. It only takes a moment, and is done the first time it's
+// needed.
+//
+
+const KPK_SIZE: usize = 2 * 24 * 64 * 64;
+
+const KPK_INVALID: u8 = 0;
+const KPK_UNKNOWN: u8 = 1;
+const KPK_DRAW: u8 = 2;
+const KPK_WIN: u8 = 4;
+
+static KPK: OnceLock<Vec<u64>> = OnceLock::new();
+
+fn kpk_index(white_to_move: bool, white_king: usize, black_king: usize, pawn: usize) -> usize {
+    white_king
+        | (black_king << 6)
+        | ((!white_to_move as usize) << 12)
+        | ((pawn % 8) << 13)
+        | ((6 - pawn / 8) << 15)
+}
+
+fn king_moves(sq: usize) -> impl Iterator<Item = usize> {
+    let (x, y) = ((sq % 8) as i32, (sq / 8) as i32);
+    (-1..=1)
+        .flat_map(move |dx| (-1..=1).map(move |dy| (x + dx, y + dy)))
+        .filter(move |&(tx, ty)| (tx, ty) != (x, y) && (0..8).contains(&tx) && (0..8).contains(&ty))
+        .map(|(tx, ty)| (ty * 8 + tx) as usize)
+}
+
+fn pawn_attacks(pawn: usize, sq: usize) -> bool {
+    sq / 8 == pawn / 8 + 1 && ((sq % 8) as i32 - (pawn % 8) as i32).abs() == 1
+}
+
+fn kpk_initial(white_to_move: bool, white_king: usize, black_king: usize, pawn: usize) -> u8 {
+    if distance(white_king, black_king) <= 1 || white_king == pawn || black_king == pawn {
+        return KPK_INVALID
+    }
+    if white_to_move && pawn_attacks(pawn, black_king) {
+        return KPK_INVALID
+    }
+
+    let promote = pawn + 8;
+    if white_to_move
+        && pawn / 8 == 6
+        && white_king != promote
+        && black_king != promote
+        && (distance(black_king, promote) > 1 || distance(white_king, promote) == 1) {
+        return KPK_WIN
+    }
+
+    if !white_to_move {
+        let stalemated = king_moves(black_king)
+            .all(|to| distance(to, white_king) <= 1 || pawn_attacks(pawn, to));
+        let takes_pawn = distance(black_king, pawn) == 1 && distance(white_king, pawn) > 1;
+        if stalemated || takes_pawn {
+            return KPK_DRAW
+        }
+    }
+
+    KPK_UNKNOWN
+}
+
+fn kpk_classify(db: &[u8], white_to_move: bool, white_king: usize, black_king: usize, pawn: usize) -> u8 {
+    let mut seen = 0;
+
+    if white_to_move {
+        for to in king_moves(white_king) {
+            seen |= db[kpk_index(false, to, black_king, pawn)];
+        }
+        if pawn / 8 < 6 {
+            seen |= db[kpk_index(false, white_king, black_king, pawn + 8)];
+        }
+        if pawn / 8 == 1 && pawn + 8 != white_king && pawn + 8 != black_king {
+            seen |= db[kpk_index(false, white_king, black_king, pawn + 16)];
+        }
+
+        if seen & KPK_WIN != 0 {
+            KPK_WIN
+        } else if seen & KPK_UNKNOWN != 0 {
+            KPK_UNKNOWN
+        } else {
+            KPK_DRAW
+        }
+    } else {
+        for to in king_moves(black_king) {
+            seen |= db[kpk_index(true, white_king, to, pawn)];
+        }
+
+        if seen & KPK_DRAW != 0 {
+            KPK_DRAW
+        } else if seen & KPK_UNKNOWN != 0 {
+            KPK_UNKNOWN
+        } else {
+            KPK_WIN
+        }
+    }
+}
+
+fn kpk_decode(index: usize) -> (bool, usize, usize, usize) {
+    let pawn = (6 - (index >> 15)) * 8 + ((index >> 13) & 3);
+    ((index >> 12) & 1 == 0, index & 63, (index >> 6) & 63, pawn)
+}
+
+fn generate_kpk() -> Vec<u64> {
+    let mut db: Vec<u8> = (0..KPK_SIZE)
+        .map(|index| {
+            let (white_to_move, white_king, black_king, pawn) = kpk_decode(index);
+            kpk_initial(white_to_move, white_king, black_king, pawn)
+        })
+        .collect();
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for index in 0..KPK_SIZE {
+            if db[index] != KPK_UNKNOWN {
+                continue
+            }
+            let (white_to_move, white_king, black_king, pawn) = kpk_decode(index);
+            let result = kpk_classify(&db, white_to_move, white_king, black_king, pawn);
+            if result != KPK_UNKNOWN {
+                db[index] = result;
+                changed = true;
+            }
+        }
+    }
+
+    let mut wins = vec![0u64; KPK_SIZE / 64];
+    for (index, &result) in db.iter().enumerate() {
+        if result == KPK_WIN {
+            wins[index / 64] |= 1 << (index % 64);
+        }
+    }
+    wins
+}
+
+/// True if the side with the pawn wins. Squares are as they stand on the board; they're flipped
+/// round here so the pawn is white and on the queen's side. A pawn on the first or last rank
+/// never wins.
+pub fn kpk_win(strong_is_white: bool, strong_to_move: bool, strong_king: usize, pawn: usize, weak_king: usize) -> bool {
+    if (1 << pawn) & BACK_RANKS != 0 {
+        return false
+    }
+    let flip_ranks = if strong_is_white { 0 } else { 56 };
+    let flip_files = if pawn % 8 >= 4 { 7 } else { 0 };
+    let normalise = |sq: usize| sq ^ flip_ranks ^ flip_files;
+
+    let wins = KPK.get_or_init(generate_kpk);
+    let index = kpk_index(strong_to_move, normalise(strong_king), normalise(weak_king), normalise(pawn));
+    wins[index / 64] & (1 << (index % 64)) != 0
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::board::Board;
+    use crate::endgame::{kpk_win, KNOWN_WIN};
+    use crate::eval::evaluate;
+
+    fn board(fen: &str, turn: &str) -> Board {
+        Board::from_fen([fen, turn, "-", "-", "0", "1"]).unwrap()
+    }
+
+    fn sq(name: &str) -> usize {
+        let bytes = name.as_bytes();
+        (bytes[1] - b'1') as usize * 8 + (bytes[0] - b'a') as usize
+    }
+
+    #[test]
+    fn test_kpk() {
+        // king in front of the pawn on the sixth wins whoever is to move
+        assert!(kpk_win(true, true, sq("e6"), sq("e5"), sq("e8")));
+        assert!(kpk_win(true, false, sq("e6"), sq("e5"), sq("e8")));
+        // stalemate
+        assert!(!kpk_win(true, false, sq("e6"), sq("e7"), sq("e8")));
+        // the defending king gets to the corner in front of a rook pawn
+        assert!(!kpk_win(true, true, sq("b1"), sq("a2"), sq("a8")));
+        // the same for black, and mirrored onto the king's side
+        assert!(kpk_win(false, true, sq("d3"), sq("d4"), sq("d1")));
+        assert!(!kpk_win(false, true, sq("g8"), sq("h7"), sq("h1")));
+
+        // pawns a FEN can put where no pawn can be
+        assert!(!kpk_win(true, true, sq("e6"), sq("e8"), sq("a8")));
+        assert!(!kpk_win(false, true, sq("e3"), sq("e1"), sq("a1")));
+        evaluate(&board("4P3/8/4K3/8/8/8/8/k7", "w"));
+        evaluate(&board("k7/8/8/8/8/4K3/8/4p3", "b"));
+    }
+
+    #[test]
+    fn test_recognised_endgames() {
+        assert_eq!(evaluate(&board("8/8/4k3/8/8/2B5/8/4K3", "w")), 0);
+        assert_eq!(evaluate(&board("8/8/4k3/8/8/2N5/8/4K3", "b")), 0);
+        assert!(evaluate(&board("8/8/4k3/8/8/8/8/R3K3", "w")) > KNOWN_WIN);
+        assert!(evaluate(&board("r3k3/8/8/8/8/8/8/4K3", "w")) < -KNOWN_WIN);
+
+        // a bishop alone can't win, even against a pawn, so only the sliver of midgame score is left
+        assert!(evaluate(&board("8/8/4k3/8/8/2B5/7p/4K3", "w")) < 50);
+    }
+
+    #[test]
+    fn test_kbnk_prefers_the_right_corner() {
+        // dark squared bishop, so a1 and h8 are the corners to mate in
+        let right = evaluate(&board("7k/8/5K2/8/8/2B5/8/3N4", "w"));
+        let wrong = evaluate(&board("k7/8/2K5/8/8/2B5/8/3N4", "w"));
+        assert!(right > wrong);
+        assert!(wrong > KNOWN_WIN);
+    }
+
+    #[test]
+    fn test_scale_factors() {
+        // a pawn up with opposite coloured bishops is worth a lot less than with same coloured ones
+        let opposite = evaluate(&board("4k3/5b2/8/8/8/2B1P3/8/4K3", "w"));
+        let same = evaluate(&board("4k3/4b3/8/8/8/2B1P3/8/4K3", "w"));
+        assert!(opposite < same);
+
+        // rook pawn and the wrong bishop with the king in the corner
+        assert!(evaluate(&board("7k/8/8/7P/8/3B4/8/4K3", "w")) < 50);
+    }
+}
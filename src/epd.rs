@@ -0,0 +1,165 @@
+use std::fs;
+
+use crate::board::{Board, ChessMove};
+use crate::pgn;
+use crate::uci;
+
+// EPD test suites. Each line is the first four fields of a FEN followed by operations, each an
+// opcode and its operands ending in a semicolon:
+//
+//   r1b2rk1/... w - - bm Qg6; id "WAC.003"; c0 "Qg6=10, Qh5=3";
+//
+// The ones used here are `bm` (best moves, any of them solves it), `am` (moves to avoid), `id`,
+// and `c0`, which STS style suites use for how many points each move is worth.
+
+#[derive(Debug, Clone)]
+pub struct EpdPosition {
+    pub board: Board,
+    pub operations: Vec<(String, String)>,
+}
+
+impl EpdPosition {
+    pub fn parse(line: &str) -> std::result::Result<EpdPosition, String> {
+        // the operations come after the first four fields, however they're spaced
+        let mut rest = line.trim();
+        let mut fields = Vec::with_capacity(4);
+        for _ in 0..4 {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            fields.push(&rest[..end]);
+            rest = rest[end..].trim_start();
+        }
+        if fields.iter().any(|field| field.is_empty()) {
+            return Err(format!("not an epd line: {}", line))
+        }
+        let board = uci::position_board(Some(fields.join(" ").as_str()), &[])?;
+        let operations = parse_operations(rest);
+        Ok(EpdPosition { board, operations })
+    }
+
+    pub fn operation(&self, opcode: &str) -> Option<&str> {
+        self.operations.iter().find(|(op, _)| op == opcode).map(|(_, operand)| operand.as_str())
+    }
+
+    /// The `id`, or an empty string
+    pub fn id(&self) -> &str {
+        self.operation("id").unwrap_or("")
+    }
+
+    /// Moves in a `bm` or `am` operation, which are SAN separated by spaces
+    fn moves(&self, opcode: &str) -> std::result::Result<Vec<ChessMove>, String> {
+        self.operation(opcode)
+            .map(|operand| operand.split_whitespace().map(|san| pgn::parse_san(&self.board, san)).collect())
+            .unwrap_or_else(|| Ok(Vec::new()))
+    }
+
+    pub fn best_moves(&self) -> std::result::Result<Vec<ChessMove>, String> {
+        self.moves("bm")
+    }
+
+    pub fn avoid_moves(&self) -> std::result::Result<Vec<ChessMove>, String> {
+        self.moves("am")
+    }
+
+    /// Points per move from a `c0` comment like "f5=10, Be5+=2, Bf2=3". Empty when there's no
+    /// c0, or it isn't in that form.
+    pub fn move_points(&self) -> Vec<(ChessMove, u32)> {
+        let comment = match self.operation("c0") {
+            Some(comment) => comment,
+            None => return Vec::new(),
+        };
+        let points: Option<Vec<(ChessMove, u32)>> = comment.split(',')
+            .map(|item| {
+                let (san, points) = item.trim().split_once('=')?;
+                let chess_move = pgn::parse_san(&self.board, san.trim()).ok()?;
+                Some((chess_move, points.trim().parse::<u32>().ok()?))
+            })
+            .collect();
+        points.unwrap_or_default()
+    }
+
+    /// Whether playing `chess_move` counts as solving the position: one of the best moves if
+    /// there are any, and none of the moves to avoid.
+    pub fn solved_by(&self, chess_move: &ChessMove) -> std::result::Result<bool, String> {
+        let best = self.best_moves()?;
+        let avoid = self.avoid_moves()?;
+        Ok((best.is_empty() || best.contains(chess_move)) && !avoid.contains(chess_move))
+    }
+
+    /// Points for `chess_move` out of the most any move gets, from `c0`
+    pub fn points_for(&self, chess_move: &ChessMove) -> Option<(u32, u32)> {
+        let points = self.move_points();
+        let max = points.iter().map(|(_, points)| *points).max()?;
+        let got = points.iter().find(|(cm, _)| cm == chess_move).map(|(_, points)| *points).unwrap_or(0);
+        Some((got, max))
+    }
+}
+
+/// Splits "bm Qg6; id \"WAC.003\";" into opcodes and operands, with the quotes taken off
+fn parse_operations(text: &str) -> Vec<(String, String)> {
+    let mut operations = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+
+    for c in text.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                current.push(c);
+            },
+            ';' if !quoted => {
+                operations.extend(split_operation(&current));
+                current.clear();
+            },
+            c => current.push(c),
+        }
+    }
+    operations.extend(split_operation(&current));
+    operations
+}
+
+fn split_operation(text: &str) -> Option<(String, String)> {
+    let text = text.trim();
+    if text.is_empty() {
+        return None
+    }
+    let (opcode, operand) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    let operand = operand.trim();
+    let operand = operand.strip_prefix('"').and_then(|o| o.strip_suffix('"')).unwrap_or(operand);
+    Some((opcode.to_string(), operand.to_string()))
+}
+
+/// Every position in an EPD file. Blank lines and lines starting with # are skipped.
+pub fn load(path: &str) -> std::result::Result<Vec<EpdPosition>, String> {
+    let text = fs::read_to_string(path).map_err(|err| format!("can't read {}: {}", path, err))?;
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(EpdPosition::parse)
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::board::ChessMove;
+    use crate::epd::EpdPosition;
+
+    #[test]
+    fn test_operations() {
+        let position = EpdPosition::parse(
+            r#"1k6/1p3R2/p7/8/8/8/6PP/6K1 w - - bm Rf8+ Rxb7+; am Rf1; id "test; with a semicolon"; c0 "Rf8+=10, Rxb7+=6, Kf2=1";"#
+        ).unwrap();
+        assert_eq!(position.id(), "test; with a semicolon");
+
+        let rf8 = ChessMove::from_long_algebraic_notation("f7f8");
+        let rxb7 = ChessMove::from_long_algebraic_notation("f7b7");
+        let rf1 = ChessMove::from_long_algebraic_notation("f7f1");
+        assert_eq!(position.best_moves(), Ok(vec![rf8, rxb7.clone()]));
+        assert_eq!(position.solved_by(&rxb7), Ok(true));
+        assert_eq!(position.solved_by(&rf1), Ok(false));
+        assert_eq!(position.points_for(&rxb7), Some((6, 10)));
+        assert_eq!(position.points_for(&rf1), Some((0, 10)));
+
+        assert!(EpdPosition::parse("8/8/8 w").is_err());
+    }
+}
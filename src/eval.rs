@@ -0,0 +1,315 @@
+use crate::board::{Board, BoardSlot, UNIT_MASK, TEAM_MASK, TEAM_WHITE, PAWN, KNIGHT, BISHOP, ROOK, QUEEN, KING};
+use crate::pawns::{self, PawnEntry, PawnTable, DEFAULT_PAWN_TABLE_SIZE};
+use crate::endgame::{self, Material};
+use crate::params::{self, Params};
+use crate::{king_safety, mobility};
+
+// Tapered evaluation: every term has a midgame and an endgame value, and the final score slides
+// between the two depending on how much material is left on the board. Values are the PeSTO
+// tables, in centipawns.
+
+/// Phase of the starting position. Anything at or above this is scored as pure midgame.
+pub const MAX_PHASE: i32 = 24;
+
+pub const MG_VALUE: [i32; 6] = [82, 337, 365, 477, 1025, 0];
+pub const EG_VALUE: [i32; 6] = [94, 281, 297, 512, 936, 0];
+const PHASE_WEIGHT: [i32; 6] = [0, 1, 1, 2, 4, 0];
+
+// Tables are written the way a board is drawn, from a8 in the top left through to h1 in the
+// bottom right, and are from white's point of view. Black looks them up mirrored.
+
+pub(crate) const MG_PAWN: [i32; 64] = [
+      0,   0,   0,   0,   0,   0,   0,   0,
+     98, 134,  61,  95,  68, 126,  34, -11,
+     -6,   7,  26,  31,  65,  56,  25, -20,
+    -14,  13,   6,  21,  23,  12,  17, -23,
+    -27,  -2,  -5,  12,  17,   6,  10, -25,
+    -26,  -4,  -4, -10,   3,   3,  33, -12,
+    -35,  -1, -20, -23, -15,  24,  38, -22,
+      0,   0,   0,   0,   0,   0,   0,   0,
+];
+
+pub(crate) const EG_PAWN: [i32; 64] = [
+      0,   0,   0,   0,   0,   0,   0,   0,
+    178, 173, 158, 134, 147, 132, 165, 187,
+     94, 100,  85,  67,  56,  53,  82,  84,
+     32,  24,  13,   5,  -2,   4,  17,  17,
+     13,   9,  -3,  -7,  -7,  -8,   3,  -1,
+      4,   7,  -6,   1,   0,  -5,  -1,  -8,
+     13,   8,   8,  10,  13,   0,   2,  -7,
+      0,   0,   0,   0,   0,   0,   0,   0,
+];
+
+pub(crate) const MG_KNIGHT: [i32; 64] = [
+    -167, -89, -34, -49,  61, -97, -15, -107,
+     -73, -41,  72,  36,  23,  62,   7,  -17,
+     -47,  60,  37,  65,  84, 129,  73,   44,
+      -9,  17,  19,  53,  37,  69,  18,   22,
+     -13,   4,  16,  13,  28,  19,  21,   -8,
+     -23,  -9,  12,  10,  19,  17,  25,  -16,
+     -29, -53, -12,  -3,  -1,  18, -14,  -19,
+    -105, -21, -58, -33, -17, -28, -19,  -23,
+];
+
+pub(crate) const EG_KNIGHT: [i32; 64] = [
+    -58, -38, -13, -28, -31, -27, -63, -99,
+    -25,  -8, -25,  -2,  -9, -25, -24, -52,
+    -24, -20,  10,   9,  -1,  -9, -19, -41,
+    -17,   3,  22,  22,  22,  11,   8, -18,
+    -18,  -6,  16,  25,  16,  17,   4, -18,
+    -23,  -3,  -1,  15,  10,  -3, -20, -22,
+    -42, -20, -10,  -5,  -2, -20, -23, -44,
+    -29, -51, -23, -15, -22, -18, -50, -64,
+];
+
+pub(crate) const MG_BISHOP: [i32; 64] = [
+    -29,   4, -82, -37, -25, -42,   7,  -8,
+    -26,  16, -18, -13,  30,  59,  18, -47,
+    -16,  37,  43,  40,  35,  50,  37,  -2,
+     -4,   5,  19,  50,  37,  37,   7,  -2,
+     -6,  13,  13,  26,  34,  12,  10,   4,
+      0,  15,  15,  15,  14,  27,  18,  10,
+      4,  15,  16,   0,   7,  21,  33,   1,
+    -33,  -3, -14, -21, -13, -12, -39, -21,
+];
+
+pub(crate) const EG_BISHOP: [i32; 64] = [
+    -14, -21, -11,  -8,  -7,  -9, -17, -24,
+     -8,  -4,   7, -12,  -3, -13,  -4, -14,
+      2,  -8,   0,  -1,  -2,   6,   0,   4,
+     -3,   9,  12,   9,  14,  10,   3,   2,
+     -6,   3,  13,  19,   7,  10,  -3,  -9,
+    -12,  -3,   8,  10,  13,   3,  -7, -15,
+    -14, -18,  -7,  -1,   4,  -9, -15, -27,
+    -23,  -9, -23,  -5,  -9, -16,  -5, -17,
+];
+
+pub(crate) const MG_ROOK: [i32; 64] = [
+     32,  42,  32,  51,  63,   9,  31,  43,
+     27,  32,  58,  62,  80,  67,  26,  44,
+     -5,  19,  26,  36,  17,  45,  61,  16,
+    -24, -11,   7,  26,  24,  35,  -8, -20,
+    -36, -26, -12,  -1,   9,  -7,   6, -23,
+    -45, -25, -16, -17,   3,   0,  -5, -33,
+    -44, -16, -20,  -9,  -1,  11,  -6, -71,
+    -19, -13,   1,  17,  16,   7, -37, -26,
+];
+
+pub(crate) const EG_ROOK: [i32; 64] = [
+     13,  10,  18,  15,  12,  12,   8,   5,
+     11,  13,  13,  11,  -3,   3,   8,   3,
+      7,   7,   7,   5,   4,  -3,  -5,  -3,
+      4,   3,  13,   1,   2,   1,  -1,   2,
+      3,   5,   8,   4,  -5,  -6,  -8, -11,
+     -4,   0,  -5,  -1,  -7, -12,  -8, -16,
+     -6,  -6,   0,   2,  -9,  -9, -11,  -3,
+     -9,   2,   3,  -1,  -5, -13,   4, -20,
+];
+
+pub(crate) const MG_QUEEN: [i32; 64] = [
+    -28,   0,  29,  12,  59,  44,  43,  45,
+    -24, -39,  -5,   1, -16,  57,  28,  54,
+    -13, -17,   7,   8,  29,  56,  47,  57,
+    -27, -27, -16, -16,  -1,  17,  -2,   1,
+     -9, -26,  -9, -10,  -2,  -4,   3,  -3,
+    -14,   2, -11,  -2,  -5,   2,  14,   5,
+    -35,  -8,  11,   2,   8,  15,  -3,   1,
+     -1, -18,  -9,  10, -15, -25, -31, -50,
+];
+
+pub(crate) const EG_QUEEN: [i32; 64] = [
+     -9,  22,  22,  27,  27,  19,  10,  20,
+    -17,  20,  32,  41,  58,  25,  30,   0,
+    -20,   6,   9,  49,  47,  35,  19,   9,
+      3,  22,  24,  45,  57,  40,  57,  36,
+    -18,  28,  19,  47,  31,  34,  39,  23,
+    -16, -27,  15,   6,   9,  17,  10,   5,
+    -22, -23, -30, -16, -16, -23, -36, -32,
+    -33, -28, -22, -43,  -5, -32, -20, -41,
+];
+
+pub(crate) const MG_KING: [i32; 64] = [
+    -65,  23,  16, -15, -56, -34,   2,  13,
+     29,  -1, -20,  -7,  -8,  -4, -38, -29,
+     -9,  24,   2, -16, -20,   6,  22, -22,
+    -17, -20, -12, -27, -30, -25, -14, -36,
+    -49,  -1, -27, -39, -46, -44, -33, -51,
+    -14, -14, -22, -46, -44, -30, -15, -27,
+      1,   7,  -8, -64, -43, -16,   9,   8,
+    -15,  36,  12, -54,   8, -28,  24,  14,
+];
+
+pub(crate) const EG_KING: [i32; 64] = [
+    -74, -35, -18, -18, -11,  15,   4, -17,
+    -12,  17,  14,  17,  17,  38,  23,  11,
+     10,  17,  23,  15,  20,  45,  44,  13,
+     -8,  22,  24,  27,  26,  33,  26,   3,
+    -18,  -4,  21,  24,  27,  23,   9, -11,
+    -19,  -3,  11,  21,  23,  16,   7,  -9,
+    -27, -11,   4,  13,  14,   4,  -5, -17,
+    -53, -34, -21, -11, -28, -14, -24, -43,
+];
+
+/// Index into the value and table arrays for a piece, ignoring team
+pub fn piece_index(slot: BoardSlot) -> Option<usize> {
+    match slot & UNIT_MASK {
+        PAWN => Some(0),
+        KNIGHT => Some(1),
+        BISHOP => Some(2),
+        ROOK => Some(3),
+        QUEEN => Some(4),
+        KING => Some(5),
+        _ => None,
+    }
+}
+
+/// Index into a piece-square table for a piece standing on (column, rank)
+pub fn table_index(x: usize, y: usize, white: bool) -> usize {
+    if white {
+        (7 - y) * 8 + x
+    } else {
+        y * 8 + x
+    }
+}
+
+/// How far from the opening the position is, from MAX_PHASE (all the pieces are still on) down to
+/// 0 (only kings and pawns left). Promotions can push it past MAX_PHASE.
+pub fn game_phase(board: &Board) -> i32 {
+    let mut phase = 0;
+    for x in 0..8 {
+        for y in 0..8 {
+            if let Some(index) = piece_index(board.piece_at((x, y))) {
+                phase += PHASE_WEIGHT[index];
+            }
+        }
+    }
+    phase
+}
+
+/// Blends a midgame and endgame score by the game phase.
+pub fn taper(mg: i32, eg: i32, phase: i32) -> i32 {
+    let phase = phase.min(MAX_PHASE);
+    (mg * phase + eg * (MAX_PHASE - phase)) / MAX_PHASE
+}
+
+/// Static evaluation in centipawns. Positive favours white.
+pub fn evaluate(board: &Board) -> i32 {
+    evaluate_with_params(board, &params::DEFAULT)
+}
+
+/// `evaluate` with other weights than the engine's own, for tuning them
+pub fn evaluate_with_params(board: &Board, params: &Params) -> i32 {
+    evaluate_with(board, params, |pawns| pawns::analyse_with(pawns, params))
+}
+
+/// Static evaluation in centipawns from the point of view of the player whos turn it is
+pub fn evaluate_relative(board: &Board) -> i32 {
+    let score = evaluate(board);
+    if board.is_whites_move() { score } else { -score }
+}
+
+/// Evaluation with its own pawn hash table. Each search thread gets one of these.
+#[derive(Debug, Clone, Default)]
+pub struct Evaluator {
+    pawn_table: PawnTable,
+}
+
+impl Evaluator {
+    pub fn new() -> Evaluator {
+        Evaluator {
+            pawn_table: PawnTable::new(DEFAULT_PAWN_TABLE_SIZE),
+        }
+    }
+
+    pub fn evaluate(&mut self, board: &Board) -> i32 {
+        let table = &mut self.pawn_table;
+        evaluate_with(board, &params::DEFAULT, |pawns| table.get(board.pawn_key(), pawns))
+    }
+
+    pub fn evaluate_relative(&mut self, board: &Board) -> i32 {
+        let score = self.evaluate(board);
+        if board.is_whites_move() { score } else { -score }
+    }
+}
+
+fn evaluate_with<F>(board: &Board, params: &Params, pawn_entry: F) -> i32
+    where F: FnOnce([u64; 2]) -> PawnEntry {
+    let mut mg = 0;
+    let mut eg = 0;
+    let mut phase = 0;
+    let mut material = Material::default();
+    let mut kings = [(-1, -1); 2];
+
+    for x in 0..8 {
+        for y in 0..8 {
+            let slot = board.piece_at((x as i8, y as i8));
+            let index = match piece_index(slot) {
+                Some(index) => index,
+                None => continue,
+            };
+
+            let white = slot & TEAM_MASK == TEAM_WHITE;
+            let team = if white { 0 } else { 1 };
+            let sq = table_index(x, y, white);
+            let sign = if white { 1 } else { -1 };
+
+            mg += sign * (params.mg_value[index] + params.mg_tables[index][sq]);
+            eg += sign * (params.eg_value[index] + params.eg_tables[index][sq]);
+            phase += PHASE_WEIGHT[index];
+
+            material.pieces[team][index] |= 1 << (y * 8 + x);
+            if slot & UNIT_MASK == KING {
+                kings[team] = (x as i8, y as i8);
+            }
+        }
+    }
+
+    if let Some(score) = endgame::evaluate(board, &material) {
+        return score
+    }
+
+    let pawns = [material.pieces[0][0], material.pieces[1][0]];
+    let entry = pawn_entry(pawns);
+    let (pawn_mg, pawn_eg) = pawns::dynamic_terms(board, &entry, kings, params);
+    mg += entry.mg + pawn_mg;
+    eg += entry.eg + pawn_eg;
+
+    let (mobility_mg, mobility_eg) = mobility::evaluate_with(board, &pawns, params);
+    let (safety_mg, safety_eg) = king_safety::evaluate_with(board, &pawns, kings, params);
+    mg += mobility_mg + safety_mg;
+    eg += mobility_eg + safety_eg;
+
+    let strong = if eg > 0 { 0 } else { 1 };
+    eg = eg * endgame::scale_factor(&material, strong) / endgame::SCALE_NORMAL;
+
+    taper(mg, eg, phase)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::board::Board;
+    use crate::eval::{evaluate, game_phase, taper, MAX_PHASE};
+
+    #[test]
+    fn test_start_position_is_level() {
+        let board = Board::from_fen(["rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR", "w", "KQkq", "-", "0", "1"]).unwrap();
+        assert_eq!(evaluate(&board), 0);
+        assert_eq!(game_phase(&board), MAX_PHASE);
+    }
+
+    #[test]
+    fn test_mirrored_positions_negate() {
+        let white = Board::from_fen(["4k3/8/8/8/3N4/8/PP6/4K3", "w", "-", "-", "0", "1"]).unwrap();
+        let black = Board::from_fen(["4k3/pp6/8/3n4/8/8/8/4K3", "b", "-", "-", "0", "1"]).unwrap();
+        assert!(evaluate(&white) > 0);
+        assert_eq!(evaluate(&white), -evaluate(&black));
+    }
+
+    #[test]
+    fn test_taper() {
+        assert_eq!(taper(100, 0, MAX_PHASE), 100);
+        assert_eq!(taper(100, 0, 0), 0);
+        assert_eq!(taper(100, 0, MAX_PHASE / 2), 50);
+    }
+}
@@ -0,0 +1,242 @@
+// Just enough JSON for the monitor and the analysis API: escaping strings on the way out, and a
+// small parser for the request bodies coming in. Numbers are all kept as f64, which is plenty
+// for depths and milliseconds.
+
+// arrays and objects inside each other any deeper than this are refused, rather than parsed with
+// one level of recursion each until the stack runs out
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// A field of an object, None for anything else or if it isn't there
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s.as_str()),
+            _ => None,
+        }
+    }
+
+    /// Whole, non-negative numbers only
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Value::Number(n) if *n >= 0.0 && n.fract() == 0.0 && *n <= u64::MAX as f64 => Some(*n as u64),
+            _ => None,
+        }
+    }
+}
+
+/// A string as a JSON string literal
+pub fn string(s: &str) -> String {
+    let mut res = String::with_capacity(s.len() + 2);
+    res.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            c if (c as u32) < 0x20 => res.push_str(format!("\\u{:04x}", c as u32).as_str()),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+pub fn parse(text: &str) -> std::result::Result<Value, String> {
+    let mut parser = Parser { chars: text.chars().collect(), pos: 0, depth: 0 };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.pos != parser.chars.len() {
+        return Err(format!("unexpected {} after the json value", parser.chars[parser.pos]))
+    }
+    Ok(value)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    depth: usize,  // arrays and objects we're inside of
+}
+
+impl Parser {
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.chars.len() && self.chars[self.pos].is_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn next(&mut self) -> std::result::Result<char, String> {
+        let c = *self.chars.get(self.pos).ok_or_else(|| String::from("json ended early"))?;
+        self.pos += 1;
+        Ok(c)
+    }
+
+    fn expect(&mut self, expected: char) -> std::result::Result<(), String> {
+        self.skip_whitespace();
+        match self.next()? {
+            c if c == expected => Ok(()),
+            c => Err(format!("expected {} in json, got {}", expected, c)),
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Value) -> std::result::Result<Value, String> {
+        for expected in word.chars() {
+            if self.next()? != expected {
+                return Err(format!("bad literal in json, expected {}", word))
+            }
+        }
+        Ok(value)
+    }
+
+    fn value(&mut self) -> std::result::Result<Value, String> {
+        self.skip_whitespace();
+        match self.chars.get(self.pos) {
+            Some(&c) if c == '{' || c == '[' => {
+                if self.depth == MAX_DEPTH {
+                    return Err(format!("json nested more than {} deep", MAX_DEPTH))
+                }
+                self.depth += 1;
+                let value = if c == '{' { self.object() } else { self.array() };
+                self.depth -= 1;
+                value
+            },
+            Some('"') => self.string().map(Value::String),
+            Some('t') => self.literal("true", Value::Bool(true)),
+            Some('f') => self.literal("false", Value::Bool(false)),
+            Some('n') => self.literal("null", Value::Null),
+            Some(c) if *c == '-' || c.is_ascii_digit() => self.number(),
+            Some(c) => Err(format!("unexpected {} in json", c)),
+            None => Err(String::from("json ended early")),
+        }
+    }
+
+    fn object(&mut self) -> std::result::Result<Value, String> {
+        self.expect('{')?;
+        let mut fields = Vec::new();
+        self.skip_whitespace();
+        if self.chars.get(self.pos) == Some(&'}') {
+            self.pos += 1;
+            return Ok(Value::Object(fields))
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.expect(':')?;
+            fields.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.next()? {
+                ',' => continue,
+                '}' => return Ok(Value::Object(fields)),
+                c => return Err(format!("expected , or }} in json object, got {}", c)),
+            }
+        }
+    }
+
+    fn array(&mut self) -> std::result::Result<Value, String> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.chars.get(self.pos) == Some(&']') {
+            self.pos += 1;
+            return Ok(Value::Array(items))
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.next()? {
+                ',' => continue,
+                ']' => return Ok(Value::Array(items)),
+                c => return Err(format!("expected , or ] in json array, got {}", c)),
+            }
+        }
+    }
+
+    fn string(&mut self) -> std::result::Result<String, String> {
+        if self.next()? != '"' {
+            return Err(String::from("expected a string in json"))
+        }
+        let mut res = String::new();
+        loop {
+            match self.next()? {
+                '"' => return Ok(res),
+                '\\' => match self.next()? {
+                    'n' => res.push('\n'),
+                    'r' => res.push('\r'),
+                    't' => res.push('\t'),
+                    'b' => res.push('\u{8}'),
+                    'f' => res.push('\u{c}'),
+                    'u' => {
+                        let hex: String = (0..4).map(|_| self.next()).collect::<std::result::Result<_, _>>()?;
+                        let code = u32::from_str_radix(&hex, 16).map_err(|_| format!("bad escape \\u{} in json", hex))?;
+                        // surrogate pairs aren't worth the bother for what we get sent
+                        res.push(char::from_u32(code).unwrap_or('\u{fffd}'));
+                    },
+                    c => res.push(c),
+                },
+                c => res.push(c),
+            }
+        }
+    }
+
+    fn number(&mut self) -> std::result::Result<Value, String> {
+        let start = self.pos;
+        while self.pos < self.chars.len() && matches!(self.chars[self.pos], '-' | '+' | '.' | 'e' | 'E' | '0'..='9') {
+            self.pos += 1;
+        }
+        let text: String = self.chars[start..self.pos].iter().collect();
+        text.parse::<f64>().map(Value::Number).map_err(|_| format!("bad number in json: {}", text))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::json::{parse, string, Value};
+
+    #[test]
+    fn test_string() {
+        assert_eq!(string("a \"b\"\\\n"), r#""a \"b\"\\\n""#);
+    }
+
+    #[test]
+    fn test_parse() {
+        let value = parse(r#" {"fen": "8/8/8/8/8/8/8/K1k5 w - - 0 1", "depth": 6, "multipv": 2.5, "x": [true, null, -1e2, "A\n"], "y": {}} "#).unwrap();
+        assert_eq!(value.get("fen").and_then(Value::as_str), Some("8/8/8/8/8/8/8/K1k5 w - - 0 1"));
+        assert_eq!(value.get("depth").and_then(Value::as_u64), Some(6));
+        assert_eq!(value.get("multipv").and_then(Value::as_u64), None);
+        assert_eq!(value.get("x"), Some(&Value::Array(vec![Value::Bool(true), Value::Null, Value::Number(-100.0), Value::String(String::from("A\n"))])));
+        assert_eq!(value.get("y"), Some(&Value::Object(Vec::new())));
+
+        assert!(parse(r#"{"fen": }"#).is_err());
+        assert!(parse(r#"{"depth": 1} x"#).is_err());
+        assert!(parse("").is_err());
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(parse(&nested(64)).is_ok());
+        assert_eq!(parse(&nested(65)), Err(String::from("json nested more than 64 deep")));
+        // used to run out of stack long before getting to the end
+        assert!(parse(&"[".repeat(60_000)).is_err());
+        assert!(parse(&r#"{"a":"#.repeat(60_000)).is_err());
+    }
+}
@@ -0,0 +1,130 @@
+use crate::board::{Board, TEAM_MASK, TEAM_WHITE};
+use crate::eval::piece_index;
+use crate::params::{self, Params};
+use crate::pawns::{bit, file_mask, forward};
+
+// King safety, midgame only. Three things count against a king:
+//  - enemy pieces that can move into the king zone (the squares around the king plus the three
+//    in front of those), weighted by piece and growing quickly once more than one piece joins in
+//  - files next to the king with none of its own pawns on them, worse still with no pawns at all
+//  - enemy pawns coming up the files around the king
+//
+// The attacks come from the board's pseudo-legal move lists, so pieces stopped by their own men
+// don't count, which is close enough.
+
+// indexed like eval's piece arrays (P, N, B, R, Q, K); pawns are handled by the storm instead
+pub(crate) const ATTACK_WEIGHT: [i32; 6] = [0, 2, 2, 3, 5, 0];
+const MAX_DANGER: i32 = 500;
+
+pub(crate) const SEMI_OPEN_FILE_MG: i32 = -12;
+pub(crate) const OPEN_FILE_MG: i32 = -25;
+
+// by how many ranks the storming pawn is in front of the king
+pub(crate) const STORM_MG: [i32; 5] = [0, -30, -20, -10, -5];
+
+fn king_zone(kx: i8, ky: i8, team: usize) -> u64 {
+    let mut zone = 0;
+    for dx in -1..=1 {
+        for dy in -1..=1 {
+            zone |= bit(kx + dx, ky + dy);
+        }
+        zone |= bit(kx + dx, ky + 2 * forward(team));
+    }
+    zone
+}
+
+/// King safety for both teams as (mg, eg) from white's side. `pawns` are the pawn bitboards
+/// [white, black], and `kings` where each king stands, (-1, -1) if it's missing.
+pub fn evaluate(board: &Board, pawns: &[u64; 2], kings: [(i8, i8); 2]) -> (i32, i32) {
+    evaluate_with(board, pawns, kings, &params::DEFAULT)
+}
+
+pub fn evaluate_with(board: &Board, pawns: &[u64; 2], kings: [(i8, i8); 2], params: &Params) -> (i32, i32) {
+    let mut mg = 0;
+
+    for (team, &(kx, ky)) in kings.iter().enumerate() {
+        if kx < 0 {
+            continue
+        }
+        let sign = if team == 0 { 1 } else { -1 };
+        let zone = king_zone(kx, ky, team);
+
+        // one bit per enemy piece that reaches the zone, so a piece hitting three squares is still
+        // only one attacker
+        let mut attackers = 0u64;
+        for cm in board.moves_for(team != 0) {
+            let (x, y) = cm.to_ind();
+            if zone & bit(x, y) != 0 {
+                let (fx, fy) = cm.from_ind();
+                attackers |= bit(fx, fy);
+            }
+        }
+
+        let mut count = 0;
+        let mut weight = 0;
+        let mut bits = attackers;
+        while bits != 0 {
+            let sq = bits.trailing_zeros() as i8;
+            bits &= bits - 1;
+            let slot = board.piece_at((sq % 8, sq / 8));
+            if (slot & TEAM_MASK == TEAM_WHITE) == (team == 0) {
+                continue
+            }
+            if let Some(index) = piece_index(slot) {
+                if params.attack_weight[index] > 0 {
+                    count += 1;
+                    weight += params.attack_weight[index];
+                }
+            }
+        }
+        // a lone attacker is rarely a threat on its own
+        if count >= 2 {
+            mg -= sign * (weight * weight * 2).min(MAX_DANGER);
+        }
+
+        let own = pawns[team];
+        let enemy = pawns[1 - team];
+        for x in (kx - 1)..=(kx + 1) {
+            let file = file_mask(x);
+            if file == 0 {
+                continue
+            }
+
+            if own & file == 0 {
+                mg += sign * if enemy & file == 0 { params.open_file_mg } else { params.semi_open_file_mg };
+            }
+
+            let mut storm = enemy & file;
+            while storm != 0 {
+                let sq = storm.trailing_zeros() as i8;
+                storm &= storm - 1;
+                let ahead = (sq / 8 - ky) * forward(team);
+                if (1..params.storm_mg.len() as i8).contains(&ahead) {
+                    mg += sign * params.storm_mg[ahead as usize];
+                }
+            }
+        }
+    }
+
+    (mg, 0)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::board::Board;
+    use crate::king_safety::evaluate;
+
+    // f, g and h pawns in front of both kings castled short
+    const PAWNS: [u64; 2] = [0b111 << 13, 0b111 << 53];
+    const KINGS: [(i8, i8); 2] = [(6, 0), (6, 7)];
+
+    #[test]
+    fn test_attacked_king_is_worse_off() {
+        // same material, but in the first black's queen and rook are bearing down on white's king
+        let attacked = Board::from_fen(["6k1/5ppp/8/8/7q/5r2/5PPP/6K1", "w", "-", "-", "0", "1"]).unwrap();
+        let quiet = Board::from_fen(["q5k1/r4ppp/8/8/8/8/5PPP/6K1", "w", "-", "-", "0", "1"]).unwrap();
+        assert!(evaluate(&attacked, &PAWNS, KINGS).0 < 0);
+        assert_eq!(evaluate(&quiet, &PAWNS, KINGS).0, 0);
+    }
+}
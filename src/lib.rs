@@ -1,4 +1,29 @@
 pub mod board;
+pub mod eval;
+pub mod nnue;
+pub mod pawns;
+pub mod mobility;
+pub mod king_safety;
+pub mod endgame;
+pub mod params;
+pub mod syzygy;
+pub mod zobrist;
+pub mod book;
+pub mod options;
+pub mod pgn;
 pub mod v2;
-mod echo_client;
-mod echo_server;
+pub mod search;
+pub mod table;
+pub mod uci;
+pub mod logging;
+pub mod monitor;
+pub mod json;
+pub mod api;
+pub mod stats;
+pub mod tournament;
+pub mod epd;
+pub mod tune;
+pub mod datagen;
+pub mod trainer;
+mod analyzer;
+mod brute;
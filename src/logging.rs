@@ -0,0 +1,121 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+// Logging. Stdout belongs to the UCI protocol, so nothing in here ever goes there. Every line
+// goes to the debug log file if one is set (the `DebugLogFile` option), and to stderr if it's
+// important enough: info and up normally, everything once the GUI sends `debug on`.
+//
+// Lines look like "[   1.234s] INFO  message", timed from the first thing logged.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    fn name(&self) -> &'static str {
+        match self {
+            Level::Debug => "DEBUG",
+            Level::Info => "INFO",
+            Level::Warn => "WARN",
+            Level::Error => "ERROR",
+        }
+    }
+}
+
+struct Logger {
+    stderr_level: Level,
+    file: Option<File>,
+}
+
+static LOGGER: Mutex<Logger> = Mutex::new(Logger { stderr_level: Level::Info, file: None });
+static START: OnceLock<Instant> = OnceLock::new();
+
+/// `debug on` shows everything on stderr, `debug off` goes back to info and up
+pub fn set_debug(on: bool) {
+    if let Ok(mut logger) = LOGGER.lock() {
+        logger.stderr_level = if on { Level::Debug } else { Level::Info };
+    }
+}
+
+/// Appends every line logged from now on to the file at `path`. An empty path stops logging
+/// to a file.
+pub fn set_file(path: &str) -> std::result::Result<(), String> {
+    let file = if path.is_empty() {
+        None
+    } else {
+        let file = OpenOptions::new().create(true).append(true).open(path)
+            .map_err(|err| format!("can't open log file {}: {}", path, err))?;
+        Some(file)
+    };
+
+    if let Ok(mut logger) = LOGGER.lock() {
+        logger.file = file;
+    }
+    Ok(())
+}
+
+fn format_line(seconds: f64, level: Level, message: &str) -> String {
+    format!("[{:>8.3}s] {:<5} {}\n", seconds, level.name(), message)
+}
+
+pub fn log(level: Level, message: &str) {
+    let seconds = START.get_or_init(Instant::now).elapsed().as_secs_f64();
+    let line = format_line(seconds, level, message);
+
+    // a poisoned lock means some thread panicked while logging, which isn't worth another panic
+    if let Ok(mut logger) = LOGGER.lock() {
+        if level >= logger.stderr_level {
+            let _ = std::io::stderr().write_all(line.as_bytes());
+        }
+        if let Some(file) = logger.file.as_mut() {
+            let _ = file.write_all(line.as_bytes());
+        }
+    }
+}
+
+pub fn debug(message: &str) {
+    log(Level::Debug, message)
+}
+
+pub fn info(message: &str) {
+    log(Level::Info, message)
+}
+
+pub fn warn(message: &str) {
+    log(Level::Warn, message)
+}
+
+pub fn error(message: &str) {
+    log(Level::Error, message)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::logging::{self, format_line, Level};
+
+    #[test]
+    fn test_file_sink() {
+        assert_eq!(format_line(1.5, Level::Warn, "careful"), "[   1.500s] WARN  careful\n");
+
+        let path = std::env::temp_dir().join(format!("chess-log-test-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        logging::set_file(path).unwrap();
+        logging::debug("into the file");
+        logging::set_file("").unwrap();
+        logging::debug("not into the file");
+
+        let contents = fs::read_to_string(path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert!(contents.contains("DEBUG into the file"));
+        assert!(!contents.contains("not into"));
+    }
+}
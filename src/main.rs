@@ -1,142 +1,327 @@
-use std::io::{self, Error, ErrorKind};
-
-use crate::board::{Board, ChessMove};
-use crate::v2::Manager;
-use crate::echo_client::log;
-
-mod echo_server;
-mod echo_client;
-mod board;
-mod analyzer;
-mod brute;
-mod v2;
+use std::io::{self, BufRead, Error};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::Duration;
+
+use chess::board::{Board, ChessMove};
+use chess::book::{Book, Selection};
+use chess::options::Options;
+use chess::search::{Searcher, SearchInfo, SearchLimits, MAX_MULTI_PV, MAX_THREADS};
+use chess::uci::{self, UciCommand};
+use chess::logging;
+use chess::monitor::{self, Monitor};
+use chess::nnue::Network;
+use chess::v2::{self, LeafValue};
 
 #[derive(Debug, Clone)]
 pub struct State {
     board: Board,
-    manager: Manager,
+    searcher: Searcher,
+    book: Option<Book>,
+    options: Options,
+    position: Option<(Option<String>, Vec<ChessMove>)>,  // the last `position` we set up, fen and moves
+    monitor: Option<Arc<Monitor>>,  // shuts down when the last copy is dropped
+    network: Option<Arc<Network>>,  // loaded from EvalFile, only searched with while UseNNUE is on
+    manager: v2::Manager,  // the tree search, used instead of the searcher unless SearchMode is AlphaBeta
+    held_bestmove: Option<String>,  // the answer to a go infinite or go ponder, until stop comes
+    stop: Arc<AtomicBool>,  // set by the input thread to end the search that's running
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl State {
     pub fn new() -> State {
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut searcher = Searcher::new();
+        searcher.set_stop_signal(stop.clone());
+        let mut manager = v2::Manager::new();
+        manager.set_stop_signal(stop.clone());
+
         State {
             board: Board::new(),
-            manager: Manager::new(),
+            searcher,
+            book: None,
+            options: engine_options(),
+            position: None,
+            monitor: None,
+            network: None,
+            manager,
+            held_bestmove: None,
+            stop,
         }
     }
 
     pub fn new_board(mut self) -> State {
         self.board = Board::new();
-        return self
+        self.position = None;
+        self
     }
 
     pub fn set_board(mut self, board: Board) -> State {
         self.board = board;
-        return self
+        self
     }
 }
 
+/// Everything we advertise in the `uci` reply
+fn engine_options() -> Options {
+    Options::new()
+        .spin("Threads", 1, 1, MAX_THREADS as i64)
+        .spin("Hash", 16, 1, 4096)
+        .button("Clear Hash")
+        .spin("MultiPV", 1, 1, MAX_MULTI_PV as i64)
+        .spin("Move Overhead", 10, 0, 5000)
+        .string("SyzygyPath", "")
+        .string("EvalFile", "")
+        .check("UseNNUE", false)
+        .combo("SearchMode", "AlphaBeta", &["AlphaBeta", "MCTS-Eval", "MCTS-Rollout"])
+        .check("OwnBook", false)
+        .string("BookFile", "")
+        .combo("BookSelection", "Random", &["Random", "Best"])
+        .string("DebugLogFile", "")
+}
+
 
 // pub type CommandResult<T> = std::result::Result<T, CommandError>;
 
 fn respond(message: &str) {
-    echo_client::log(format!("Responding: {}", message).as_str());
+    logging::debug(format!("> {}", message).as_str());
     println!("{}", message)
 }
 
 fn io_error(message: &str) -> io::Result<State> {
-    io::Result::Err(Error::new(ErrorKind::Other, message))
+    io::Result::Err(Error::other(message))
 }
 
 
-fn start_view_server(state: State) -> io::Result<State> {
-    echo_server::start();
+fn handle_command_server(mut state: State, address: Option<String>) -> io::Result<State> {
+    // only one at a time, so starting another replaces the old one
+    state.monitor = None;
+    match Monitor::start(address.as_deref().unwrap_or(monitor::DEFAULT_ADDRESS)) {
+        Ok(started) => {
+            respond(format!("info string monitor listening on {}", started.address()).as_str());
+            state.monitor = Some(Arc::new(started));
+        },
+        Err(err) => respond(format!("info string {}", err).as_str()),
+    }
     io::Result::Ok(state)
 }
 
 fn handle_command_exit() -> io::Result<State> {
-    echo_client::log("exiting");
+    logging::info("exiting");
     io_error("exiting")
 }
 
-fn handle_command_unknown(state: State, _command: &str) -> io::Result<State> {
-    echo_client::log("command unknown");
+fn handle_command_position(mut state: State, fen: Option<String>, moves: Vec<ChessMove>) -> io::Result<State> {
+    // GUIs send the whole game again every move. When it's the last position with a move or two
+    // on the end, only those need playing
+    let played = match &state.position {
+        Some((last_fen, last_moves)) if *last_fen == fen && moves.starts_with(last_moves) => {
+            uci::play_moves(state.board.clone(), &moves[last_moves.len()..], last_moves.len())
+        },
+        _ => uci::position_board(fen.as_deref(), &moves),
+    };
+
+    // a bad position leaves the old one in place, which is about the best we can do
+    match played {
+        Ok(board) => {
+            logging::debug(board.as_debug_string().as_str());
+            state.board = board;
+            state.position = Some((fen, moves));
+        },
+        Err(err) => respond(format!("info string {}", err).as_str()),
+    }
     io::Result::Ok(state)
 }
 
-fn handle_command_position(mut state: State, parts: &Vec<&str>) -> io::Result<State> {
-    if parts.len() < 2 {
-        io_error("expected more args")
-    } else {
-        let (res, index) = if parts[1] == "startpos" {
-            let res = Board::from_fen(["rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR", "w", "KQkq", "-", "0", "1"]);
-            // let res = state.board.clone().reset_from_fen(["rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR", "w", "KQkq", "-", "0", "1"]);
-            (res, 2)
-        } else if parts[1] == "fen" {
-            let mut arg: [&str; 6] = Default::default();
-            arg.copy_from_slice(&parts[2..8]);
-            let res = Board::from_fen(arg);
-            // let res = state.board.clone().reset_from_fen(arg);
-            (res, 8)
-        } else {
-            echo_client::log(format!("Unsupported position type: {}", parts[1]).as_str());
-            return io_error("Unsupported position type")
-        };
+fn handle_command_go(state: &mut State, mut limits: SearchLimits) -> io::Result<()> {
+    if state.options.get_check("OwnBook") {
+        let selection = if state.options.get_string("BookSelection") == "Best" { Selection::Best } else { Selection::WeightedRandom };
+        let book_move = state.book.as_ref().and_then(|book| book.pick(&state.board, selection));
+        if let Some(chosen_move) = book_move {
+            logging::info("playing a book move");
+            send_bestmove(state, &limits, format!("bestmove {}", chosen_move.to_long_algebraic_notation()));
+            return io::Result::Ok(())
+        }
+    }
 
-        res
-            .and_then(|board| {
-                state.board = board;
+    limits.move_overhead = Duration::from_millis(state.options.get_spin("Move Overhead") as u64);
+
+    // anything the gui asks for that can't be played here is dropped, and if that leaves nothing
+    // we just search everything
+    if !limits.searchmoves.is_empty() {
+        let legal = state.board.clone().get_legal_moves();
+        let board = &state.board;
+        limits.searchmoves = limits.searchmoves.iter().map(|cm| uci::castle_from_king_takes_rook(board, cm)).collect();
+        limits.searchmoves.retain(|cm| {
+            let ok = legal.contains(cm);
+            if !ok {
+                respond(format!("info string ignoring illegal searchmove {}", cm.to_long_algebraic_notation()).as_str());
+            }
+            ok
+        });
+    }
 
-                if parts.len() < index + 2 {
-                    return Ok(state)
-                }
+    // the tree search plays by playouts and time with its own evaluation, so say what it's
+    // leaving out rather than quietly dropping it
+    let alpha_beta = state.options.get_string("SearchMode") == "AlphaBeta";
+    if !alpha_beta {
+        if limits.depth.is_some() {
+            respond("info string MCTS ignores depth, searching to the node and time limits");
+        }
+        if state.options.get_spin("MultiPV") > 1 {
+            respond("info string MCTS ignores MultiPV, showing one line");
+        }
+        if state.options.get_check("UseNNUE") {
+            respond("info string MCTS ignores UseNNUE, using the handcrafted eval");
+        }
+    }
 
-                let moves = &parts[index + 1..];
+    let monitor = state.monitor.clone();
+    if let Some(monitor) = &monitor {
+        monitor.publish(&monitor::position_event(state.board.to_fen().as_str()));
+    }
+    let report = |info: &SearchInfo| {
+        respond(info.to_uci_string().as_str());
+        if let Some(monitor) = &monitor {
+            monitor.publish(&monitor::info_event(info));
+        }
+    };
 
-                let mut r_state = Ok(state);
+    let result = if alpha_beta {
+        state.searcher.find_best_move(state.board.clone(), &limits, report)
+    } else {
+        state.manager.find_best_move(state.board.clone(), &limits, report)
+    };
+    let bestmove = match result {
+        Ok(chosen_move) => {
+            if let Some(monitor) = &monitor {
+                monitor.publish(&monitor::bestmove_event(&chosen_move));
+            }
+            format!("bestmove {}", chosen_move.to_long_algebraic_notation())
+        },
+        Err(err) => {
+            // the gui is still waiting on a bestmove, and 0000 is the null move
+            respond(format!("info string {}", err).as_str());
+            String::from("bestmove 0000")
+        },
+    };
+    send_bestmove(state, &limits, bestmove);
+    io::Result::Ok(())
+}
 
-                for str_move in moves {
-                    r_state = r_state.and_then(move |mut state| {
-                        let chess_move = ChessMove::from_long_algebraic_notation(str_move);
-                        state.board = state.board.apply_move(&chess_move);
-                        Ok(state)
-                    });
-                }
-                r_state
-            })
-            .map(|board| io::Result::Ok(board))
-            .map_err(|err| io_error(err.as_str()))
-            .unwrap()
+/// A go infinite or go ponder search can still finish on its own before the stop (or ponderhit)
+/// comes, having searched as deep as it goes. The protocol says bestmove can't come before then,
+/// so it's held back until it does.
+fn send_bestmove(state: &mut State, limits: &SearchLimits, bestmove: String) {
+    if limits.infinite || limits.ponder {
+        state.held_bestmove = Some(bestmove);
+    } else {
+        respond(bestmove.as_str());
     }
 }
 
-fn handle_command_go(state: &mut State, _parts: &Vec<&str>) -> io::Result<()> {
-    // TODO parse all the args
-    // let args = parts &parts[1..];
+fn handle_command_setoption(mut state: State, name: &str, value: &str) -> io::Result<State> {
+    match state.options.set(name, value) {
+        Ok(name) => apply_option(&mut state, name.as_str()),
+        Err(err) => respond(format!("info string {}", err).as_str()),
+    }
 
-    // for now just ask the board for best move, this gonna have to be heavily refactored
-    // brute::find_best_move(&state.board)
-    v2::find_best_move(state.board.clone(), &mut state.manager)
-        .map(|chosen_move| {
-            respond(format!("bestmove {}", chosen_move.to_long_algebraic_notation()).as_str());
-            io::Result::Ok(())
-        })
-        .map_err(|err| io_error(err.as_str()))
-        .unwrap()
+    io::Result::Ok(state)
+}
+
+/// Passes a newly set option on to whatever uses it
+fn apply_option(state: &mut State, name: &str) {
+    match name {
+        "Threads" => state.searcher.set_threads(state.options.get_spin("Threads") as usize),
+        "Hash" => {
+            let megabytes = state.options.get_spin("Hash") as usize;
+            state.searcher.set_hash_size(megabytes);
+            state.manager.set_hash_size(megabytes);
+        },
+        "Clear Hash" => {
+            state.searcher.clear();
+            state.manager.clear();
+        },
+        "MultiPV" => state.searcher.set_multi_pv(state.options.get_spin("MultiPV") as usize),
+        "SyzygyPath" => {
+            state.searcher.set_syzygy_path(state.options.get_string("SyzygyPath"));
+            let tablebases = state.searcher.tablebases();
+            respond(format!("info string found {} tablebases, up to {} pieces", tablebases.len(), tablebases.max_pieces()).as_str());
+        },
+        "EvalFile" => {
+            let path = state.options.get_string("EvalFile");
+            state.network = if path.is_empty() {
+                None
+            } else {
+                match Network::load(path) {
+                    Ok(network) => {
+                        respond(format!("info string loaded network with {} hidden neurons", network.hidden()).as_str());
+                        Some(Arc::new(network))
+                    },
+                    Err(err) => {
+                        respond(format!("info string {}", err).as_str());
+                        None
+                    },
+                }
+            };
+            apply_eval(state);
+        },
+        "UseNNUE" => apply_eval(state),
+        "SearchMode" => {
+            let leaf = if state.options.get_string("SearchMode") == "MCTS-Rollout" { LeafValue::Rollout } else { LeafValue::Evaluation };
+            state.manager.set_leaf_value(leaf);
+        },
+        "BookFile" => {
+            let path = state.options.get_string("BookFile");
+            state.book = if path.is_empty() {
+                None
+            } else {
+                match Book::open(path) {
+                    Ok(book) => {
+                        respond(format!("info string loaded book with {} entries", book.len()).as_str());
+                        Some(book)
+                    },
+                    Err(err) => {
+                        respond(format!("info string {}", err).as_str());
+                        None
+                    },
+                }
+            };
+        },
+        "DebugLogFile" => {
+            if let Err(err) = logging::set_file(state.options.get_string("DebugLogFile")) {
+                respond(format!("info string {}", err).as_str());
+            }
+        },
+        // everything else is read when it's needed
+        _ => {},
+    }
+}
 
-    // optm
-    //     .map(|chosen_move| {
-    //         respond(format!("bestmove {}", chosen_move.to_long_algebraic_notation()).as_str());
-    //         io::Result::Ok(board)
-    //     })
-    //     .map_err(|err| io_error(err))
-    //     .unwrap()
+/// Hands the searcher the network if UseNNUE is on and one's been loaded
+fn apply_eval(state: &mut State) {
+    let network = if state.options.get_check("UseNNUE") {
+        if state.network.is_none() {
+            respond("info string UseNNUE needs a network from EvalFile, using the handcrafted eval");
+        }
+        state.network.clone()
+    } else {
+        None
+    };
+    state.searcher.set_network(network);
 }
 
 fn handle_command_playground(mut state: State) -> io::Result<State> {
     state.board = Board::from_fen(["rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR", "w", "KQkq", "-", "0", "1"]).unwrap();
 
-    println!("{}", state.board.as_debug_string().as_str());
+    logging::info(state.board.as_debug_string().as_str());
 
     io::Result::Ok(state)
 }
@@ -145,7 +330,9 @@ fn handle_command_uci(state: State) -> io::Result<State> {
     respond("id name Cesac 0.1 ALPHA");
     respond("id author Lucas Simpson");
 
-    // TODO sent options supported
+    for option in state.options.iter() {
+        respond(option.to_string().as_str());
+    }
 
     respond("uciok");
     io::Result::Ok(state)
@@ -157,58 +344,105 @@ fn handle_command_is_ready(state: State) -> io::Result<State> {
 }
 
 fn handle_command(mut state: State, command: &str) -> io::Result<State> {
-    echo_client::log(format!("\n\n\nReceived command: {}", command).as_str());
-
-    // split by spaces
-    let parts: Vec<&str> = command.split(" ").collect();
-
-    // if this fails its weird, just assume unknown
-    if parts.len() == 0 {
-        handle_command_unknown(state, command)
-    } else if parts[0] == "exit" {
-        handle_command_exit()
-    } else if parts[0] == "server" {
-        start_view_server(state)
-    } else if parts[0] == "uci" {
-        handle_command_uci(state)
-    } else if parts[0] == "ucinewgame" {
-        io::Result::Ok(state.new_board())
-    } else if parts[0] == "isready" {
-        handle_command_is_ready(state)
-    } else if parts[0] == "position" {
-        handle_command_position(state, &parts)
-    } else if parts[0] == "go" {
-        handle_command_go(&mut state, &parts).map(|_| state)
-    } else if parts[0] == "stop" {
-        handle_command_exit()
-    } else if parts[0] == "t" {
-        handle_command_playground(state)
-    } else {
-        handle_command_unknown(state, command)
+    logging::debug(format!("< {}", command).as_str());
+
+    let command = match uci::parse(command) {
+        Ok(Some(command)) => command,
+        Ok(None) => return io::Result::Ok(state),
+        Err(err) => {
+            logging::warn(err.as_str());
+            respond(format!("info string {}", err).as_str());
+            return io::Result::Ok(state)
+        },
+    };
+
+    match command {
+        UciCommand::Uci => handle_command_uci(state),
+        UciCommand::Debug(on) => {
+            logging::set_debug(on);
+            io::Result::Ok(state)
+        },
+        UciCommand::IsReady => handle_command_is_ready(state),
+        UciCommand::SetOption { name, value } => handle_command_setoption(state, name.as_str(), value.as_str()),
+        UciCommand::UciNewGame => {
+            state.searcher.clear();
+            state.manager.clear();
+            io::Result::Ok(state.new_board())
+        },
+        // a held back bestmove belongs to the search before, and is no answer to anything new
+        UciCommand::Position { fen, moves } => {
+            state.held_bestmove = None;
+            handle_command_position(state, fen, moves)
+        },
+        UciCommand::Go(limits) => {
+            state.held_bestmove = None;
+            handle_command_go(&mut state, limits).map(|_| state)
+        },
+        // the input thread has already stopped the search, leaving only the bestmove to send
+        UciCommand::Stop | UciCommand::PonderHit => {
+            if let Some(bestmove) = state.held_bestmove.take() {
+                respond(bestmove.as_str());
+            }
+            io::Result::Ok(state)
+        },
+        UciCommand::Quit => {
+            // say goodbye to anyone watching before we go
+            state.monitor = None;
+            handle_command_exit()
+        },
+        UciCommand::Server { address } => handle_command_server(state, address),
+        UciCommand::ServerStop => {
+            state.monitor = None;
+            io::Result::Ok(state)
+        },
+        UciCommand::Playground => handle_command_playground(state),
     }
 }
 
-fn read_input(state: State) -> io::Result<()> {
-    echo_client::log(state.board.as_debug_string().as_str());
+/// Reads stdin on its own thread, so a stop can get to the search while it's still running.
+/// Whatever ends a search sets the stop signal straight away, and a go clears it, since one only
+/// counts for the search in front of it. Every line is then passed on to be handled in order.
+fn read_lines(sender: Sender<String>, stop: Arc<AtomicBool>) {
+    for line in io::stdin().lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        match uci::parse(line.as_str()) {
+            Ok(Some(UciCommand::Go(_))) => stop.store(false, Ordering::Relaxed),
+            Ok(Some(UciCommand::Stop)) | Ok(Some(UciCommand::PonderHit)) | Ok(Some(UciCommand::Quit)) => stop.store(true, Ordering::Relaxed),
+            _ => {},
+        }
+        if sender.send(line).is_err() {
+            break
+        }
+    }
+}
+
+fn read_input(mut state: State) -> io::Result<()> {
+    let (sender, receiver) = mpsc::channel();
+    let stop = state.stop.clone();
+    thread::spawn(move || read_lines(sender, stop));
 
-    let mut buffer = String::new();
-    io::stdin()
-        .read_line(&mut buffer)
-        .and_then(|_| handle_command(state, buffer.trim()))
-        .and_then(|state| read_input(state))
+    for line in receiver {
+        state = handle_command(state, line.trim())?;
+    }
+    // the gui went away without saying quit
+    io::Result::Ok(())
 }
 
+#[allow(dead_code)]
 fn test(pos: &str) {
     handle_command(State::new(), pos)
         .and_then(|state| handle_command(state, "go"))
-        .and_then(|state| read_input(state))
+        .and_then(read_input)
         .unwrap();
 }
 
 fn log_unwrap(res: io::Result<()>) {
     match res {
-        Ok(_) => log("exited OK"),
-        Err(err) => log(err.to_string().as_str())
+        Ok(_) => logging::info("exited OK"),
+        Err(err) => logging::info(err.to_string().as_str())
     }
 }
 
@@ -237,3 +471,73 @@ fn main() {
     //     .unwrap();
 }
 
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+    use std::thread;
+    use std::time::Duration;
+
+    use chess::board::ChessMove;
+    use chess::uci;
+
+    use crate::{handle_command, State};
+
+    #[test]
+    fn test_position_extending_the_last_one() {
+        let line = "e2e4 e7e5 g1f3 b8c6 f1c4 g8f6 e1g1";
+        let moves: Vec<ChessMove> = line.split(' ').map(ChessMove::from_long_algebraic_notation).collect();
+        let replayed = uci::position_board(None, &moves).unwrap();
+
+        // only the last three moves get played onto the board from the first command
+        let state = handle_command(State::new(), "position startpos moves e2e4 e7e5 g1f3 b8c6").unwrap();
+        let state = handle_command(state, format!("position startpos moves {}", line).as_str()).unwrap();
+        assert_eq!(state.board.id(), replayed.id());
+        assert_eq!(state.board.to_fen(), replayed.to_fen());
+
+        // a different game starts again from scratch
+        let state = handle_command(state, "position startpos moves d2d4").unwrap();
+        let d4 = uci::position_board(None, &[ChessMove::from_long_algebraic_notation("d2d4")]).unwrap();
+        assert_eq!(state.board.to_fen(), d4.to_fen());
+    }
+
+    // what the input thread does when it reads a stop, a little while into the search
+    fn stop_after(state: &State, millis: u64) -> thread::JoinHandle<()> {
+        let stop = state.stop.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(millis));
+            stop.store(true, Ordering::Relaxed);
+        })
+    }
+
+    #[test]
+    fn test_infinite_holds_bestmove_until_stop() {
+        let state = handle_command(State::new(), "position fen 6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+        let stopper = stop_after(&state, 200);
+        let state = handle_command(state, "go infinite").unwrap();
+        stopper.join().unwrap();
+        assert_eq!(state.held_bestmove.as_deref(), Some("bestmove a1a8"));
+        let state = handle_command(state, "stop").unwrap();
+        assert_eq!(state.held_bestmove, None);
+
+        // with a normal go there's nothing to wait for
+        state.stop.store(false, Ordering::Relaxed);
+        let state = handle_command(state, "go depth 2").unwrap();
+        assert_eq!(state.held_bestmove, None);
+    }
+
+    #[test]
+    fn test_held_bestmove_dropped_by_new_position_or_go() {
+        for next in ["position startpos", "go depth 1"] {
+            let state = handle_command(State::new(), "position fen 6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+            let stopper = stop_after(&state, 50);
+            let state = handle_command(state, "go ponder").unwrap();
+            stopper.join().unwrap();
+            assert!(state.held_bestmove.is_some());
+
+            state.stop.store(false, Ordering::Relaxed);
+            let state = handle_command(state, next).unwrap();
+            assert_eq!(state.held_bestmove, None, "{}", next);
+        }
+    }
+}
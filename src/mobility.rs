@@ -0,0 +1,72 @@
+use crate::board::{Board, TEAM_MASK, TEAM_WHITE};
+use crate::eval::piece_index;
+use crate::params::{self, Params};
+use crate::pawns::{self, bit};
+
+// Mobility. Counted straight off the pseudo-legal move lists the board already keeps for both
+// teams, leaving out squares an enemy pawn covers since a piece can't really go there. Each piece
+// scores a little for every move over (or loses a little for every move under) what a piece of
+// that kind usually has.
+//
+// Indexed like eval's piece arrays (P, N, B, R, Q, K); pawns and kings don't score mobility.
+
+pub(crate) const MOBILITY_MG: [i32; 6] = [0, 4, 5, 2, 1, 0];
+pub(crate) const MOBILITY_EG: [i32; 6] = [0, 4, 5, 4, 2, 0];
+const MOBILITY_TYPICAL: [i32; 6] = [0, 4, 6, 6, 12, 0];
+
+/// Mobility for both teams as (mg, eg) from white's side. `pawns` are the pawn bitboards
+/// [white, black].
+pub fn evaluate(board: &Board, pawns: &[u64; 2]) -> (i32, i32) {
+    evaluate_with(board, pawns, &params::DEFAULT)
+}
+
+pub fn evaluate_with(board: &Board, pawns: &[u64; 2], params: &Params) -> (i32, i32) {
+    let mut mg = 0;
+    let mut eg = 0;
+
+    for team in 0..2 {
+        let sign = if team == 0 { 1 } else { -1 };
+        let covered = pawns::pawn_attacks(pawns[1 - team], 1 - team);
+
+        // moves per square the piece moves from, so each piece gets its own count
+        let mut counts = [0i32; 64];
+        for cm in board.moves_for(team == 0) {
+            let (x, y) = cm.to_ind();
+            if covered & bit(x, y) == 0 {
+                let (fx, fy) = cm.from_ind();
+                counts[fy as usize * 8 + fx as usize] += 1;
+            }
+        }
+
+        for (sq, &count) in counts.iter().enumerate() {
+            let slot = board.piece_at(((sq % 8) as i8, (sq / 8) as i8));
+            if (slot & TEAM_MASK == TEAM_WHITE) != (team == 0) {
+                continue
+            }
+            if let Some(index) = piece_index(slot) {
+                if params.mobility_mg[index] == 0 && params.mobility_eg[index] == 0 {
+                    continue
+                }
+                let extra = count - MOBILITY_TYPICAL[index];
+                mg += sign * extra * params.mobility_mg[index];
+                eg += sign * extra * params.mobility_eg[index];
+            }
+        }
+    }
+
+    (mg, eg)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::board::Board;
+    use crate::mobility::evaluate;
+
+    #[test]
+    fn test_centralised_knight_is_more_mobile() {
+        let centre = Board::from_fen(["4k3/8/8/8/3N4/8/8/4K3", "w", "-", "-", "0", "1"]).unwrap();
+        let corner = Board::from_fen(["4k3/8/8/8/8/8/8/N3K3", "w", "-", "-", "0", "1"]).unwrap();
+        assert!(evaluate(&centre, &[0, 0]).0 > evaluate(&corner, &[0, 0]).0);
+    }
+}
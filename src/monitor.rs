@@ -0,0 +1,240 @@
+use std::fmt;
+use std::io::{ErrorKind, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::board::ChessMove;
+use crate::json;
+use crate::logging;
+use crate::search::{mate_in, SearchInfo};
+use crate::table::Bound;
+
+// Search monitor. A small TCP server that anyone can connect to and get every search event as
+// one JSON object per line, so a long analysis can be watched from another terminal with
+// something like `nc 127.0.0.1 8080`. Nothing is read from clients; a client that can't keep up
+// or goes away is dropped.
+//
+// Publishing never waits on the network: events go onto a queue, and a writer thread of its own
+// sends them on to the clients, so a slow client holds up the writer rather than the search. If
+// the queue ever fills up, events are thrown away until there's room again.
+//
+// Events all have a "type":
+//   {"type":"position","fen":"..."}
+//   {"type":"info","depth":12,"seldepth":18,"multipv":1,"score":{"cp":31},"bound":"exact",
+//    "nodes":123456,"nps":250000,"tbhits":0,"hashfull":42,"time_ms":493,"pv":["e2e4","e7e5"]}
+//   {"type":"bestmove","move":"e2e4"}
+//   {"type":"shutdown"}
+
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:8080";
+
+// how often the accept loop looks up to see if it should stop
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+// events waiting for the writer thread
+const QUEUE_SIZE: usize = 4096;
+
+pub struct Monitor {
+    address: SocketAddr,
+    clients: Arc<Mutex<Vec<TcpStream>>>,
+    stop: Arc<AtomicBool>,
+    acceptor: Option<JoinHandle<()>>,
+    events: Option<SyncSender<String>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl fmt::Debug for Monitor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Monitor {{ address: {}, clients: {} }}", self.address, self.client_count())
+    }
+}
+
+impl Monitor {
+    /// Starts listening on `address` in the background
+    pub fn start(address: &str) -> std::result::Result<Monitor, String> {
+        let listener = TcpListener::bind(address).map_err(|err| format!("can't listen on {}: {}", address, err))?;
+        let local = listener.local_addr().map_err(|err| err.to_string())?;
+        // non-blocking so the accept loop gets a chance to notice it's been told to stop
+        listener.set_nonblocking(true).map_err(|err| err.to_string())?;
+
+        let clients = Arc::new(Mutex::new(Vec::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let acceptor = {
+            let clients = clients.clone();
+            let stop = stop.clone();
+            thread::spawn(move || accept_clients(listener, clients, stop))
+        };
+        let (events, queue) = mpsc::sync_channel(QUEUE_SIZE);
+        let writer = {
+            let clients = clients.clone();
+            thread::spawn(move || write_events(queue, clients))
+        };
+
+        logging::info(format!("monitor listening on {}", local).as_str());
+        Ok(Monitor {
+            address: local,
+            clients,
+            stop,
+            acceptor: Some(acceptor),
+            events: Some(events),
+            writer: Some(writer),
+        })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn client_count(&self) -> usize {
+        self.clients.lock().map(|clients| clients.len()).unwrap_or(0)
+    }
+
+    /// Queues one line for every client, without waiting for it to be sent
+    pub fn publish(&self, line: &str) {
+        if let Some(events) = &self.events {
+            let _ = events.try_send(format!("{}\n", line));
+        }
+    }
+
+    /// Tells the clients we're going, closes their connections and stops listening
+    pub fn shutdown(&mut self) {
+        if self.acceptor.is_none() {
+            return
+        }
+
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(acceptor) = self.acceptor.take() {
+            let _ = acceptor.join();
+        }
+
+        // the writer finishes off the queue, shutdown event last, once there's nothing more to come
+        if let Some(events) = self.events.take() {
+            let _ = events.send(String::from("{\"type\":\"shutdown\"}\n"));
+        }
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+        if let Ok(mut clients) = self.clients.lock() {
+            for client in clients.drain(..) {
+                let _ = client.shutdown(Shutdown::Both);
+            }
+        }
+        logging::info(format!("monitor on {} stopped", self.address).as_str());
+    }
+}
+
+impl Drop for Monitor {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+fn accept_clients(listener: TcpListener, clients: Arc<Mutex<Vec<TcpStream>>>, stop: Arc<AtomicBool>) {
+    while !stop.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, peer)) => {
+                logging::info(format!("monitor client connected from {}", peer).as_str());
+                // a stuck client shouldn't be able to hold up the writer for long
+                let _ = stream.set_nonblocking(false);
+                let _ = stream.set_write_timeout(Some(Duration::from_millis(100)));
+                if let Ok(mut clients) = clients.lock() {
+                    clients.push(stream);
+                }
+            },
+            Err(err) if err.kind() == ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+            Err(err) => logging::warn(format!("monitor accept failed: {}", err).as_str()),
+        }
+    }
+}
+
+fn write_events(queue: Receiver<String>, clients: Arc<Mutex<Vec<TcpStream>>>) {
+    for line in queue {
+        if let Ok(mut clients) = clients.lock() {
+            clients.retain_mut(|client| client.write_all(line.as_bytes()).is_ok());
+        }
+    }
+}
+
+pub fn position_event(fen: &str) -> String {
+    format!(r#"{{"type":"position","fen":{}}}"#, json::string(fen))
+}
+
+/// {"cp":31} or {"mate":-3}
+pub fn score_json(score: i32) -> String {
+    match mate_in(score) {
+        Some(moves) => format!(r#"{{"mate":{}}}"#, moves),
+        None => format!(r#"{{"cp":{}}}"#, score),
+    }
+}
+
+pub fn info_event(info: &SearchInfo) -> String {
+    let score = score_json(info.score);
+    let bound = match info.bound {
+        Bound::Exact => "exact",
+        Bound::Lower => "lower",
+        Bound::Upper => "upper",
+    };
+    let millis = info.time.as_millis() as u64;
+    let nps = (info.nodes * 1000).checked_div(millis).unwrap_or(0);
+    let pv: Vec<String> = info.pv.iter().map(|cm| json::string(cm.to_long_algebraic_notation().as_str())).collect();
+
+    format!(
+        r#"{{"type":"info","depth":{},"seldepth":{},"multipv":{},"score":{},"bound":"{}","nodes":{},"nps":{},"tbhits":{},"hashfull":{},"time_ms":{},"pv":[{}]}}"#,
+        info.depth, info.seldepth, info.multipv.unwrap_or(1), score, bound, info.nodes, nps, info.tbhits, info.hashfull, millis, pv.join(","),
+    )
+}
+
+pub fn bestmove_event(chess_move: &ChessMove) -> String {
+    format!(r#"{{"type":"bestmove","move":{}}}"#, json::string(chess_move.to_long_algebraic_notation().as_str()))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpStream;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::board::ChessMove;
+    use crate::monitor::{bestmove_event, Monitor};
+
+    fn wait_for_clients(monitor: &Monitor, count: usize) {
+        let start = Instant::now();
+        while monitor.client_count() < count && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_clients_get_events_and_shutdown() {
+        let mut monitor = Monitor::start("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(monitor.address()).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        wait_for_clients(&monitor, 1);
+
+        monitor.publish(&bestmove_event(&ChessMove::from_long_algebraic_notation("e2e4")));
+        monitor.shutdown();
+
+        let lines: Vec<String> = BufReader::new(client).lines().map(|line| line.unwrap()).collect();
+        assert_eq!(lines, vec![r#"{"type":"bestmove","move":"e2e4"}"#, r#"{"type":"shutdown"}"#]);
+    }
+
+    #[test]
+    fn test_stuck_clients_dont_hold_up_publishing() {
+        let monitor = Monitor::start("127.0.0.1:0").unwrap();
+        // clients that never read, so their buffers fill and writes to them start timing out
+        let _stuck: Vec<TcpStream> = (0..3).map(|_| TcpStream::connect(monitor.address()).unwrap()).collect();
+        wait_for_clients(&monitor, 3);
+
+        let event = format!(r#"{{"type":"position","fen":"{}"}}"#, "x".repeat(4000));
+        let start = Instant::now();
+        for _ in 0..2000 {
+            monitor.publish(&event);
+        }
+        assert!(start.elapsed() < Duration::from_millis(100), "publishing took {:?}", start.elapsed());
+    }
+}
@@ -0,0 +1,272 @@
+use std::convert::TryInto;
+use std::fmt;
+use std::fs;
+
+use crate::board::{Board, TEAM_MASK, TEAM_WHITE};
+use crate::eval::piece_index;
+
+// A small efficiently updatable neural network, as an alternative to the handcrafted evaluation.
+//
+// The inputs are 768 on/off features, one per (side, piece, square), seen from both sides: from
+// white's view white's pieces are "ours", from black's the board is flipped top to bottom and
+// black's pieces are. Each view goes through the same feature transformer, a weight column per
+// feature summed into a hidden layer of HIDDEN values, the accumulator. A move only turns a few
+// features off and on, so instead of being summed from scratch at every node the accumulator is
+// copied from the position before and patched with those columns.
+//
+// The output is both accumulators, the side to move's first, through a clipped ReLU and a single
+// dot product:
+//
+//   eval = (sum(crelu(ours) * out_ours) + sum(crelu(theirs) * out_theirs) + bias) * SCALE / (QA * QB)
+//
+// Everything is integer: the transformer is quantised by QA and the output weights by QB, with
+// the loops written over plain slices so the compiler can vectorise them.
+//
+// The file is little endian throughout:
+//   magic            4 bytes, "CNUE"
+//   version          u32, 1
+//   hidden           u32, a multiple of 16
+//   feature weights  i16 x 768 x hidden, a feature's column at a time
+//   feature biases   i16 x hidden
+//   output weights   i16 x 2 x hidden, ours then theirs
+//   output bias      i32
+
+pub const INPUTS: usize = 768;
+pub const QA: i32 = 255;
+pub const QB: i32 = 64;
+pub const SCALE: i32 = 400;
+
+const MAGIC: &[u8; 4] = b"CNUE";
+const VERSION: u32 = 1;
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Network {
+    hidden: usize,
+    feature_weights: Vec<i16>,
+    feature_biases: Vec<i16>,
+    output_weights: Vec<i16>,
+    output_bias: i32,
+}
+
+/// The hidden layer for a position, from white's view and from black's
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Accumulator {
+    values: [Vec<i16>; 2],
+}
+
+impl fmt::Debug for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Network").field("hidden", &self.hidden).finish()
+    }
+}
+
+/// The feature a piece turns on, from white's view and from black's
+pub fn features(slot: u8, x: i8, y: i8) -> Option<[usize; 2]> {
+    let piece = piece_index(slot)?;
+    let white = slot & TEAM_MASK == TEAM_WHITE;
+    let sq = y as usize * 8 + x as usize;
+    let white_view = if white { 0 } else { 384 } + piece * 64 + sq;
+    let black_view = if white { 384 } else { 0 } + piece * 64 + (sq ^ 56);
+    Some([white_view, black_view])
+}
+
+fn add(values: &mut [i16], column: &[i16]) {
+    for (value, weight) in values.iter_mut().zip(column) {
+        *value = value.wrapping_add(*weight);
+    }
+}
+
+fn sub(values: &mut [i16], column: &[i16]) {
+    for (value, weight) in values.iter_mut().zip(column) {
+        *value = value.wrapping_sub(*weight);
+    }
+}
+
+fn crelu_dot(values: &[i16], weights: &[i16]) -> i32 {
+    values.iter().zip(weights)
+        .map(|(value, weight)| (*value as i32).clamp(0, QA) * *weight as i32)
+        .sum()
+}
+
+impl Network {
+    pub fn new(hidden: usize, feature_weights: Vec<i16>, feature_biases: Vec<i16>, output_weights: Vec<i16>, output_bias: i32) -> std::result::Result<Network, String> {
+        if hidden == 0 || !hidden.is_multiple_of(16) {
+            return Err(format!("the hidden layer has to be a multiple of 16, not {}", hidden))
+        }
+        if feature_weights.len() != INPUTS * hidden || feature_biases.len() != hidden || output_weights.len() != 2 * hidden {
+            return Err(String::from("weights don't match the hidden layer size"))
+        }
+        Ok(Network { hidden, feature_weights, feature_biases, output_weights, output_bias })
+    }
+
+    pub fn load(path: &str) -> std::result::Result<Network, String> {
+        let bytes = fs::read(path).map_err(|err| format!("can't read {}: {}", path, err))?;
+        Network::from_bytes(&bytes).map_err(|err| format!("{}: {}", path, err))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> std::result::Result<Network, String> {
+        if bytes.len() < 12 || &bytes[..4] != MAGIC {
+            return Err(String::from("not a network file"))
+        }
+        let version = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+        if version != VERSION {
+            return Err(format!("unknown network version {}", version))
+        }
+        let hidden = u32::from_le_bytes(bytes[8..12].try_into().unwrap()) as usize;
+        let size = 12 + 2 * (INPUTS * hidden + hidden + 2 * hidden) + 4;
+        if bytes.len() != size {
+            return Err(format!("expected {} bytes for a hidden layer of {}, got {}", size, hidden, bytes.len()))
+        }
+
+        let mut values = bytes[12..size - 4].chunks_exact(2).map(|pair| i16::from_le_bytes([pair[0], pair[1]]));
+        let feature_weights = values.by_ref().take(INPUTS * hidden).collect();
+        let feature_biases = values.by_ref().take(hidden).collect();
+        let output_weights = values.collect();
+        let output_bias = i32::from_le_bytes(bytes[size - 4..].try_into().unwrap());
+        Network::new(hidden, feature_weights, feature_biases, output_weights, output_bias)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(16 + 2 * (INPUTS + 3) * self.hidden);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(self.hidden as u32).to_le_bytes());
+        for value in self.feature_weights.iter().chain(&self.feature_biases).chain(&self.output_weights) {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.output_bias.to_le_bytes());
+        bytes
+    }
+
+    pub fn hidden(&self) -> usize {
+        self.hidden
+    }
+
+    fn column(&self, feature: usize) -> &[i16] {
+        &self.feature_weights[feature * self.hidden..(feature + 1) * self.hidden]
+    }
+
+    fn add_feature(&self, accumulator: &mut Accumulator, features: [usize; 2]) {
+        for (values, feature) in accumulator.values.iter_mut().zip(features) {
+            add(values, self.column(feature));
+        }
+    }
+
+    fn sub_feature(&self, accumulator: &mut Accumulator, features: [usize; 2]) {
+        for (values, feature) in accumulator.values.iter_mut().zip(features) {
+            sub(values, self.column(feature));
+        }
+    }
+
+    /// An accumulator with nothing in it yet, to be filled in by `refresh`
+    pub fn new_accumulator(&self) -> Accumulator {
+        Accumulator { values: [vec![0; self.hidden], vec![0; self.hidden]] }
+    }
+
+    /// Sums the accumulator for `board` from scratch
+    pub fn refresh(&self, accumulator: &mut Accumulator, board: &Board) {
+        for values in accumulator.values.iter_mut() {
+            values.copy_from_slice(&self.feature_biases);
+        }
+        for x in 0..8 {
+            for y in 0..8 {
+                if let Some(features) = features(board.piece_at((x, y)), x, y) {
+                    self.add_feature(accumulator, features);
+                }
+            }
+        }
+    }
+
+    /// Fills in `accumulator` for `after` from `parent`, the accumulator for `before`, by
+    /// patching in just the squares that changed. Works for any two positions, but it's only
+    /// cheaper than a refresh when they're a move apart.
+    pub fn update(&self, accumulator: &mut Accumulator, parent: &Accumulator, before: &Board, after: &Board) {
+        for (values, parent) in accumulator.values.iter_mut().zip(&parent.values) {
+            values.copy_from_slice(parent);
+        }
+        for x in 0..8 {
+            for y in 0..8 {
+                let (old, new) = (before.piece_at((x, y)), after.piece_at((x, y)));
+                if old == new {
+                    continue
+                }
+                if let Some(features) = features(old, x, y) {
+                    self.sub_feature(accumulator, features);
+                }
+                if let Some(features) = features(new, x, y) {
+                    self.add_feature(accumulator, features);
+                }
+            }
+        }
+    }
+
+    /// Evaluation in centipawns from the point of view of the side to move
+    pub fn evaluate(&self, accumulator: &Accumulator, white_to_move: bool) -> i32 {
+        let (ours, theirs) = if white_to_move { (0, 1) } else { (1, 0) };
+        let (out_ours, out_theirs) = self.output_weights.split_at(self.hidden);
+        let sum = crelu_dot(&accumulator.values[ours], out_ours)
+            + crelu_dot(&accumulator.values[theirs], out_theirs)
+            + self.output_bias;
+        (sum as i64 * SCALE as i64 / (QA * QB) as i64) as i32
+    }
+
+    /// `evaluate` for a board without an accumulator on hand
+    pub fn evaluate_board(&self, board: &Board) -> i32 {
+        let mut accumulator = self.new_accumulator();
+        self.refresh(&mut accumulator, board);
+        self.evaluate(&accumulator, board.is_whites_move())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::board::ChessMove;
+    use crate::nnue::{Network, INPUTS};
+    use crate::uci;
+
+    // a made up network, just something with a different weight everywhere
+    fn network() -> Network {
+        let hidden = 16;
+        let weight = |i: usize| ((i * 7919) % 61) as i16 - 30;
+        Network::new(
+            hidden,
+            (0..INPUTS * hidden).map(weight).collect(),
+            (0..hidden).map(|i| weight(i + 5) * 4).collect(),
+            (0..2 * hidden).map(|i| weight(i + 11)).collect(),
+            1234,
+        ).unwrap()
+    }
+
+    #[test]
+    fn test_updates_match_refresh() {
+        let network = network();
+        // castling, a double push then en passant, and a promotion with a capture
+        let mut board = uci::position_board(Some("r3k2r/1P3ppp/8/8/5p2/8/6PP/R3K2R w KQkq - 0 1"), &[]).unwrap();
+        let mut accumulator = network.new_accumulator();
+        network.refresh(&mut accumulator, &board);
+
+        for notation in ["e1g1", "e8c8", "g2g4", "f4g3", "b7a8q"] {
+            let child = board.apply_move(&ChessMove::from_long_algebraic_notation(notation));
+            let mut updated = network.new_accumulator();
+            network.update(&mut updated, &accumulator, &board, &child);
+
+            let mut refreshed = network.new_accumulator();
+            network.refresh(&mut refreshed, &child);
+            assert_eq!(updated, refreshed, "after {}", notation);
+            assert_eq!(network.evaluate(&updated, child.is_whites_move()), network.evaluate_board(&child));
+
+            board = child;
+            accumulator = updated;
+        }
+    }
+
+    #[test]
+    fn test_file_round_trip() {
+        let network = network();
+        let bytes = network.to_bytes();
+        assert_eq!(Network::from_bytes(&bytes), Ok(network));
+        assert!(Network::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Network::from_bytes(b"not a network").is_err());
+    }
+}
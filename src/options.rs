@@ -0,0 +1,241 @@
+use std::fmt;
+
+// The engine's UCI options. Each option has a type that decides how it's advertised in the `uci`
+// reply and what `setoption` accepts for it. Values are kept here as they were last set, and the
+// rest of the engine reads them back out through the typed getters.
+//
+// Option names are matched ignoring case like the protocol asks for, but are always reported
+// with the case they were registered with.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptionKind {
+    Spin { default: i64, min: i64, max: i64 },
+    Check { default: bool },
+    Combo { default: String, vars: Vec<String> },
+    String { default: String },
+    Button,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptionValue {
+    Spin(i64),
+    Check(bool),
+    Combo(String),
+    String(String),
+    Button,
+}
+
+#[derive(Debug, Clone)]
+pub struct UciOption {
+    name: String,
+    kind: OptionKind,
+    value: OptionValue,
+}
+
+impl UciOption {
+    pub fn new(name: &str, kind: OptionKind) -> UciOption {
+        let value = match &kind {
+            OptionKind::Spin { default, .. } => OptionValue::Spin(*default),
+            OptionKind::Check { default } => OptionValue::Check(*default),
+            OptionKind::Combo { default, .. } => OptionValue::Combo(default.clone()),
+            OptionKind::String { default } => OptionValue::String(default.clone()),
+            OptionKind::Button => OptionValue::Button,
+        };
+        UciOption { name: name.to_string(), kind, value }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &OptionValue {
+        &self.value
+    }
+
+    /// Parses and checks a value the way `setoption` sends it, without setting it
+    fn parse(&self, value: &str) -> std::result::Result<OptionValue, String> {
+        match &self.kind {
+            OptionKind::Spin { min, max, .. } => {
+                let n = value.parse::<i64>().map_err(|_| format!("{} needs a number, got '{}'", self.name, value))?;
+                if n < *min || n > *max {
+                    return Err(format!("{} must be between {} and {}, got {}", self.name, min, max, n))
+                }
+                Ok(OptionValue::Spin(n))
+            },
+            OptionKind::Check { .. } => match value {
+                "true" => Ok(OptionValue::Check(true)),
+                "false" => Ok(OptionValue::Check(false)),
+                _ => Err(format!("{} needs true or false, got '{}'", self.name, value)),
+            },
+            OptionKind::Combo { vars, .. } => vars.iter()
+                .find(|var| var.eq_ignore_ascii_case(value))
+                .map(|var| OptionValue::Combo(var.clone()))
+                .ok_or_else(|| format!("{} must be one of {}, got '{}'", self.name, vars.join(", "), value)),
+            // "<empty>" is how GUIs send back the empty default we advertise
+            OptionKind::String { .. } if value == "<empty>" => Ok(OptionValue::String(String::new())),
+            OptionKind::String { .. } => Ok(OptionValue::String(value.to_string())),
+            OptionKind::Button => Ok(OptionValue::Button),
+        }
+    }
+}
+
+impl fmt::Display for UciOption {
+    /// The option's line in the `uci` reply
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "option name {} type ", self.name)?;
+        match &self.kind {
+            OptionKind::Spin { default, min, max } => write!(f, "spin default {} min {} max {}", default, min, max),
+            OptionKind::Check { default } => write!(f, "check default {}", default),
+            OptionKind::Combo { default, vars } => {
+                write!(f, "combo default {}", default)?;
+                for var in vars {
+                    write!(f, " var {}", var)?;
+                }
+                Ok(())
+            },
+            OptionKind::String { default } if default.is_empty() => write!(f, "string default <empty>"),
+            OptionKind::String { default } => write!(f, "string default {}", default),
+            OptionKind::Button => write!(f, "button"),
+        }
+    }
+}
+
+/// Every option the engine supports, in the order they're advertised
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    options: Vec<UciOption>,
+}
+
+impl Options {
+    pub fn new() -> Options {
+        Options::default()
+    }
+
+    pub fn add(mut self, name: &str, kind: OptionKind) -> Options {
+        self.options.push(UciOption::new(name, kind));
+        self
+    }
+
+    pub fn spin(self, name: &str, default: i64, min: i64, max: i64) -> Options {
+        self.add(name, OptionKind::Spin { default, min, max })
+    }
+
+    pub fn check(self, name: &str, default: bool) -> Options {
+        self.add(name, OptionKind::Check { default })
+    }
+
+    pub fn combo(self, name: &str, default: &str, vars: &[&str]) -> Options {
+        let vars = vars.iter().map(|var| var.to_string()).collect();
+        self.add(name, OptionKind::Combo { default: default.to_string(), vars })
+    }
+
+    pub fn string(self, name: &str, default: &str) -> Options {
+        self.add(name, OptionKind::String { default: default.to_string() })
+    }
+
+    pub fn button(self, name: &str) -> Options {
+        self.add(name, OptionKind::Button)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &UciOption> {
+        self.options.iter()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&UciOption> {
+        self.options.iter().find(|option| option.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Sets an option from `setoption`, giving back its registered name so the caller can tell
+    /// which part of the engine needs to hear about it. Bad values leave the option as it was.
+    pub fn set(&mut self, name: &str, value: &str) -> std::result::Result<String, String> {
+        let option = self.options.iter_mut()
+            .find(|option| option.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("no such option: {}", name))?;
+        option.value = option.parse(value)?;
+        Ok(option.name.clone())
+    }
+
+    pub fn get_spin(&self, name: &str) -> i64 {
+        match self.get(name).map(|option| &option.value) {
+            Some(OptionValue::Spin(n)) => *n,
+            _ => panic!("{} isn't a spin option", name),
+        }
+    }
+
+    pub fn get_check(&self, name: &str) -> bool {
+        match self.get(name).map(|option| &option.value) {
+            Some(OptionValue::Check(b)) => *b,
+            _ => panic!("{} isn't a check option", name),
+        }
+    }
+
+    /// The value of a string or combo option
+    pub fn get_string(&self, name: &str) -> &str {
+        match self.get(name).map(|option| &option.value) {
+            Some(OptionValue::String(s)) | Some(OptionValue::Combo(s)) => s.as_str(),
+            _ => panic!("{} isn't a string option", name),
+        }
+    }
+}
+
+/// Splits `setoption name <name> [value <value>]` into the name and value, both of which may
+/// contain spaces. Buttons have no value, which comes back as an empty string.
+pub fn parse_setoption(parts: &[&str]) -> std::result::Result<(String, String), String> {
+    if parts.get(1) != Some(&"name") {
+        return Err(String::from("setoption needs a name"))
+    }
+    let value_index = parts.iter().position(|part| *part == "value").unwrap_or(parts.len());
+    let name = parts[2.min(value_index)..value_index].join(" ");
+    let value = parts[(value_index + 1).min(parts.len())..].join(" ");
+    if name.is_empty() {
+        return Err(String::from("setoption needs a name"))
+    }
+    Ok((name, value))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::options::{parse_setoption, Options};
+
+    fn options() -> Options {
+        Options::new()
+            .spin("Hash", 16, 1, 1024)
+            .check("OwnBook", false)
+            .combo("Book Selection", "Random", &["Random", "Best"])
+            .string("SyzygyPath", "")
+            .button("Clear Hash")
+    }
+
+    #[test]
+    fn test_uci_lines() {
+        let lines: Vec<String> = options().iter().map(|option| option.to_string()).collect();
+        assert_eq!(lines, vec![
+            "option name Hash type spin default 16 min 1 max 1024",
+            "option name OwnBook type check default false",
+            "option name Book Selection type combo default Random var Random var Best",
+            "option name SyzygyPath type string default <empty>",
+            "option name Clear Hash type button",
+        ]);
+    }
+
+    #[test]
+    fn test_setoption() {
+        let mut options = options();
+
+        let parts: Vec<&str> = "setoption name book selection value best".split(' ').collect();
+        let (name, value) = parse_setoption(&parts).unwrap();
+        assert_eq!(options.set(&name, &value), Ok(String::from("Book Selection")));
+        assert_eq!(options.get_string("Book Selection"), "Best");
+
+        assert!(options.set("Hash", "4096").is_err());
+        assert!(options.set("Hash", "lots").is_err());
+        assert_eq!(options.get_spin("Hash"), 16);
+        assert!(options.set("Hash", "64").is_ok());
+        assert_eq!(options.get_spin("Hash"), 64);
+
+        let parts: Vec<&str> = "setoption name Clear Hash".split(' ').collect();
+        assert_eq!(parse_setoption(&parts), Ok((String::from("Clear Hash"), String::new())));
+        assert!(options.set("Nonsense", "1").is_err());
+    }
+}
@@ -0,0 +1,219 @@
+use crate::{eval, king_safety, mobility, pawns};
+
+// Every evaluation weight in one struct, so they can be handled as one vector of numbers by the
+// tuner. The engine itself always plays with DEFAULT, which is just the constants from the eval
+// modules gathered up; tuned values go back in by pasting `to_rust`'s output over those constants.
+//
+// Left out on purpose: the endgame module's known-result scores and scale factors, which are
+// rules rather than weights, and the typical mobility counts and the king danger cap, which only
+// shift or bound other terms.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Params {
+    pub mg_value: [i32; 6],
+    pub eg_value: [i32; 6],
+    pub mg_tables: [[i32; 64]; 6],
+    pub eg_tables: [[i32; 64]; 6],
+
+    pub mobility_mg: [i32; 6],
+    pub mobility_eg: [i32; 6],
+
+    pub attack_weight: [i32; 6],
+    pub semi_open_file_mg: i32,
+    pub open_file_mg: i32,
+    pub storm_mg: [i32; 5],
+
+    pub passed_mg: [i32; 8],
+    pub passed_eg: [i32; 8],
+    pub passed_free_path_eg: [i32; 8],
+    pub connected_mg: [i32; 8],
+    pub connected_eg: [i32; 8],
+    pub doubled_mg: i32,
+    pub doubled_eg: i32,
+    pub isolated_mg: i32,
+    pub isolated_eg: i32,
+    pub backward_mg: i32,
+    pub backward_eg: i32,
+    pub shield_near_mg: i32,
+    pub shield_far_mg: i32,
+    pub shield_missing_mg: i32,
+}
+
+pub const DEFAULT: Params = Params {
+    mg_value: eval::MG_VALUE,
+    eg_value: eval::EG_VALUE,
+    mg_tables: [eval::MG_PAWN, eval::MG_KNIGHT, eval::MG_BISHOP, eval::MG_ROOK, eval::MG_QUEEN, eval::MG_KING],
+    eg_tables: [eval::EG_PAWN, eval::EG_KNIGHT, eval::EG_BISHOP, eval::EG_ROOK, eval::EG_QUEEN, eval::EG_KING],
+
+    mobility_mg: mobility::MOBILITY_MG,
+    mobility_eg: mobility::MOBILITY_EG,
+
+    attack_weight: king_safety::ATTACK_WEIGHT,
+    semi_open_file_mg: king_safety::SEMI_OPEN_FILE_MG,
+    open_file_mg: king_safety::OPEN_FILE_MG,
+    storm_mg: king_safety::STORM_MG,
+
+    passed_mg: pawns::PASSED_MG,
+    passed_eg: pawns::PASSED_EG,
+    passed_free_path_eg: pawns::PASSED_FREE_PATH_EG,
+    connected_mg: pawns::CONNECTED_MG,
+    connected_eg: pawns::CONNECTED_EG,
+    doubled_mg: pawns::DOUBLED_MG,
+    doubled_eg: pawns::DOUBLED_EG,
+    isolated_mg: pawns::ISOLATED_MG,
+    isolated_eg: pawns::ISOLATED_EG,
+    backward_mg: pawns::BACKWARD_MG,
+    backward_eg: pawns::BACKWARD_EG,
+    shield_near_mg: pawns::SHIELD_NEAR_MG,
+    shield_far_mg: pawns::SHIELD_FAR_MG,
+    shield_missing_mg: pawns::SHIELD_MISSING_MG,
+};
+
+impl Default for Params {
+    fn default() -> Self {
+        DEFAULT
+    }
+}
+
+impl Params {
+    /// Every field as a slice, named after the constant it comes from. This is the one place
+    /// that decides the order of the vector.
+    fn fields(&mut self) -> Vec<(&'static str, &mut [i32])> {
+        let [mg_pawn, mg_knight, mg_bishop, mg_rook, mg_queen, mg_king] = &mut self.mg_tables;
+        let [eg_pawn, eg_knight, eg_bishop, eg_rook, eg_queen, eg_king] = &mut self.eg_tables;
+        vec![
+            ("MG_VALUE", &mut self.mg_value[..]),
+            ("EG_VALUE", &mut self.eg_value[..]),
+            ("MG_PAWN", &mut mg_pawn[..]),
+            ("EG_PAWN", &mut eg_pawn[..]),
+            ("MG_KNIGHT", &mut mg_knight[..]),
+            ("EG_KNIGHT", &mut eg_knight[..]),
+            ("MG_BISHOP", &mut mg_bishop[..]),
+            ("EG_BISHOP", &mut eg_bishop[..]),
+            ("MG_ROOK", &mut mg_rook[..]),
+            ("EG_ROOK", &mut eg_rook[..]),
+            ("MG_QUEEN", &mut mg_queen[..]),
+            ("EG_QUEEN", &mut eg_queen[..]),
+            ("MG_KING", &mut mg_king[..]),
+            ("EG_KING", &mut eg_king[..]),
+            ("MOBILITY_MG", &mut self.mobility_mg[..]),
+            ("MOBILITY_EG", &mut self.mobility_eg[..]),
+            ("ATTACK_WEIGHT", &mut self.attack_weight[..]),
+            ("SEMI_OPEN_FILE_MG", std::slice::from_mut(&mut self.semi_open_file_mg)),
+            ("OPEN_FILE_MG", std::slice::from_mut(&mut self.open_file_mg)),
+            ("STORM_MG", &mut self.storm_mg[..]),
+            ("PASSED_MG", &mut self.passed_mg[..]),
+            ("PASSED_EG", &mut self.passed_eg[..]),
+            ("PASSED_FREE_PATH_EG", &mut self.passed_free_path_eg[..]),
+            ("CONNECTED_MG", &mut self.connected_mg[..]),
+            ("CONNECTED_EG", &mut self.connected_eg[..]),
+            ("DOUBLED_MG", std::slice::from_mut(&mut self.doubled_mg)),
+            ("DOUBLED_EG", std::slice::from_mut(&mut self.doubled_eg)),
+            ("ISOLATED_MG", std::slice::from_mut(&mut self.isolated_mg)),
+            ("ISOLATED_EG", std::slice::from_mut(&mut self.isolated_eg)),
+            ("BACKWARD_MG", std::slice::from_mut(&mut self.backward_mg)),
+            ("BACKWARD_EG", std::slice::from_mut(&mut self.backward_eg)),
+            ("SHIELD_NEAR_MG", std::slice::from_mut(&mut self.shield_near_mg)),
+            ("SHIELD_FAR_MG", std::slice::from_mut(&mut self.shield_far_mg)),
+            ("SHIELD_MISSING_MG", std::slice::from_mut(&mut self.shield_missing_mg)),
+        ]
+    }
+
+    pub fn to_vector(&self) -> Vec<i32> {
+        let mut copy = self.clone();
+        copy.fields().into_iter().flat_map(|(_, values)| values.to_vec()).collect()
+    }
+
+    /// The reverse of `to_vector`. Panics if `values` isn't the same length.
+    pub fn set_vector(&mut self, values: &[i32]) {
+        let mut rest = values;
+        for (name, field) in self.fields() {
+            assert!(rest.len() >= field.len(), "too few values for {}", name);
+            let (these, others) = rest.split_at(field.len());
+            field.copy_from_slice(these);
+            rest = others;
+        }
+        assert!(rest.is_empty(), "{} values left over", rest.len());
+    }
+
+    /// A name for every entry of the vector, like "MG_KNIGHT[27]"
+    pub fn names(&self) -> Vec<String> {
+        let mut copy = self.clone();
+        copy.fields().into_iter()
+            .flat_map(|(name, values)| {
+                let single = values.len() == 1;
+                (0..values.len()).map(move |i| if single { name.to_string() } else { format!("{}[{}]", name, i) })
+            })
+            .collect()
+    }
+
+    /// The weights as Rust constants, laid out (visibility included) like the ones in the eval
+    /// modules so the values can be copied straight over them.
+    pub fn to_rust(&self) -> String {
+        let mut copy = self.clone();
+        let mut res = String::new();
+        for (name, values) in copy.fields() {
+            let visibility = visibility(name);
+            if values.len() == 1 {
+                res.push_str(format!("{}const {}: i32 = {};\n\n", visibility, name, values[0]).as_str());
+            } else if values.len() == 64 {
+                res.push_str(format!("{}const {}: [i32; 64] = [\n", visibility, name).as_str());
+                // each column right aligned to its widest entry, and at least three wide
+                let widths: Vec<usize> = (0..8)
+                    .map(|file| values.iter().skip(file).step_by(8).map(|v| v.to_string().len()).fold(3, usize::max))
+                    .collect();
+                for row in values.chunks(8) {
+                    let row: Vec<String> = row.iter().zip(&widths).map(|(v, width)| format!("{:>1$}", v, width)).collect();
+                    res.push_str(format!("    {},\n", row.join(", ")).as_str());
+                }
+                res.push_str("];\n\n");
+            } else {
+                let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+                res.push_str(format!("{}const {}: [i32; {}] = [{}];\n\n", visibility, name, values.len(), values.join(", ")).as_str());
+            }
+        }
+        res
+    }
+}
+
+// how each constant is declared in its module; the piece values are used outside the crate too
+fn visibility(name: &str) -> &'static str {
+    match name {
+        "MG_VALUE" | "EG_VALUE" => "pub ",
+        _ => "pub(crate) ",
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::params::DEFAULT;
+
+    #[test]
+    fn test_vector_round_trip() {
+        let vector = DEFAULT.to_vector();
+        assert_eq!(vector.len(), DEFAULT.names().len());
+        assert_eq!(DEFAULT.names()[2 * 6 + 1], "MG_PAWN[1]");
+
+        let mut params = DEFAULT.clone();
+        let mut changed = vector.clone();
+        changed[0] += 1;
+        params.set_vector(&changed);
+        assert_eq!(params.mg_value[0], DEFAULT.mg_value[0] + 1);
+        assert_eq!(params.to_vector(), changed);
+        assert!(DEFAULT.to_rust().contains("pub(crate) const DOUBLED_MG: i32 = -10;"));
+    }
+
+    #[test]
+    fn test_to_rust_matches_source() {
+        // pasting the defaults back over the eval modules has to leave them exactly as they are
+        let sources = [
+            include_str!("eval.rs"), include_str!("king_safety.rs"), include_str!("mobility.rs"), include_str!("pawns.rs"),
+        ];
+        for declaration in DEFAULT.to_rust().split("\n\n").filter(|d| !d.is_empty()) {
+            let found = sources.iter().any(|source| source.lines().any(|line| line == declaration.lines().next().unwrap())
+                && source.contains(declaration));
+            assert!(found, "not in the source as written:\n{}", declaration);
+        }
+    }
+}
@@ -0,0 +1,306 @@
+use crate::board::{Board, EMPTY};
+use crate::params::{self, Params};
+use crate::zobrist;
+
+// Pawn structure. Everything that only depends on where the pawns are is worked out once per pawn
+// structure and kept in a PawnTable, keyed by the pawn key the board updates as moves are made;
+// the few terms that also depend on other pieces (is a passed pawn's path clear, is the king
+// behind its shield) are cheap to do from the cached bitboards.
+//
+// Bitboards have one bit per square, rank * 8 + column, so a1 is bit 0 and h8 is bit 63. Teams are
+// indexed [white, black].
+
+pub const DEFAULT_PAWN_TABLE_SIZE: usize = 1 << 14;
+
+// indexed by rank counted from the pawn owner's side, so 1 is the starting rank and 6 is the rank
+// before promoting
+pub(crate) const PASSED_MG: [i32; 8] = [0, 5, 10, 15, 30, 55, 90, 0];
+pub(crate) const PASSED_EG: [i32; 8] = [0, 10, 15, 25, 50, 90, 150, 0];
+pub(crate) const PASSED_FREE_PATH_EG: [i32; 8] = [0, 0, 5, 10, 20, 35, 60, 0];
+pub(crate) const CONNECTED_MG: [i32; 8] = [0, 3, 5, 8, 15, 25, 40, 0];
+pub(crate) const CONNECTED_EG: [i32; 8] = [0, 2, 4, 6, 12, 20, 35, 0];
+
+pub(crate) const DOUBLED_MG: i32 = -10;
+pub(crate) const DOUBLED_EG: i32 = -20;
+pub(crate) const ISOLATED_MG: i32 = -10;
+pub(crate) const ISOLATED_EG: i32 = -15;
+pub(crate) const BACKWARD_MG: i32 = -8;
+pub(crate) const BACKWARD_EG: i32 = -10;
+
+// pawn shield in front of a castled king, midgame only
+pub(crate) const SHIELD_NEAR_MG: i32 = 12;
+pub(crate) const SHIELD_FAR_MG: i32 = 6;
+pub(crate) const SHIELD_MISSING_MG: i32 = -15;
+
+pub const FILE_A: u64 = 0x0101_0101_0101_0101;
+pub const FILE_H: u64 = FILE_A << 7;
+
+pub fn bit(x: i8, y: i8) -> u64 {
+    if (0..8).contains(&x) && (0..8).contains(&y) {
+        1 << (y as u64 * 8 + x as u64)
+    } else {
+        0
+    }
+}
+
+pub fn file_mask(x: i8) -> u64 {
+    if (0..8).contains(&x) {
+        FILE_A << x
+    } else {
+        0
+    }
+}
+
+fn adjacent_files(x: i8) -> u64 {
+    file_mask(x - 1) | file_mask(x + 1)
+}
+
+/// every rank strictly in front of `y` from the point of view of `team`
+fn ranks_ahead(y: i8, team: usize) -> u64 {
+    if team == 0 {
+        if y >= 7 { 0 } else { !0u64 << ((y as u64 + 1) * 8) }
+    } else if y <= 0 {
+        0
+    } else {
+        !0u64 >> ((8 - y as u64) * 8)
+    }
+}
+
+pub fn relative_rank(y: i8, team: usize) -> usize {
+    if team == 0 { y as usize } else { 7 - y as usize }
+}
+
+pub fn forward(team: usize) -> i8 {
+    if team == 0 { 1 } else { -1 }
+}
+
+/// Every square a team's pawns attack, whether or not there's anything there to take
+pub fn pawn_attacks(pawns: u64, team: usize) -> u64 {
+    if team == 0 {
+        ((pawns << 7) & !FILE_H) | ((pawns << 9) & !FILE_A)
+    } else {
+        ((pawns >> 9) & !FILE_H) | ((pawns >> 7) & !FILE_A)
+    }
+}
+
+/// Everything the pawn structure alone says about a position. Scores are from white's side.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PawnEntry {
+    key: u64,
+    pub pawns: [u64; 2],
+    pub passed: [u64; 2],
+    pub mg: i32,
+    pub eg: i32,
+}
+
+/// Works out the cacheable pawn terms from scratch.
+pub fn analyse(pawns: [u64; 2]) -> PawnEntry {
+    analyse_with(pawns, &params::DEFAULT)
+}
+
+pub fn analyse_with(pawns: [u64; 2], params: &Params) -> PawnEntry {
+    let mut entry = PawnEntry {
+        key: zobrist::pawn_key(&pawns),
+        pawns,
+        ..Default::default()
+    };
+
+    for team in 0..2 {
+        let own = pawns[team];
+        let enemy = pawns[1 - team];
+        let dir = forward(team);
+        let sign = if team == 0 { 1 } else { -1 };
+
+        let mut bits = own;
+        while bits != 0 {
+            let sq = bits.trailing_zeros() as i8;
+            bits &= bits - 1;
+
+            let (x, y) = (sq % 8, sq / 8);
+            let rank = relative_rank(y, team);
+            let ahead = ranks_ahead(y, team);
+            let (mut mg, mut eg) = (0, 0);
+
+            let passed = enemy & (file_mask(x) | adjacent_files(x)) & ahead == 0;
+            if passed {
+                entry.passed[team] |= bit(x, y);
+                mg += params.passed_mg[rank];
+                eg += params.passed_eg[rank];
+            }
+
+            // only the rear pawn of a doubled pair gets the penalty
+            if own & file_mask(x) & ahead != 0 {
+                mg += params.doubled_mg;
+                eg += params.doubled_eg;
+            }
+
+            let supported = own & (bit(x - 1, y - dir) | bit(x + 1, y - dir)) != 0;
+            let phalanx = own & (bit(x - 1, y) | bit(x + 1, y)) != 0;
+            if supported || phalanx {
+                mg += params.connected_mg[rank];
+                eg += params.connected_eg[rank];
+            }
+
+            if own & adjacent_files(x) == 0 {
+                mg += params.isolated_mg;
+                eg += params.isolated_eg;
+            } else if !supported && !phalanx {
+                // backward: every neighbour has already gone past it, so it can never be
+                // defended by a pawn, and it can't step up to them without being taken
+                let level_or_behind = own & adjacent_files(x) & !ahead;
+                let stop_attacked = enemy & (bit(x - 1, y + 2 * dir) | bit(x + 1, y + 2 * dir)) != 0;
+                if level_or_behind == 0 && stop_attacked {
+                    mg += params.backward_mg;
+                    eg += params.backward_eg;
+                }
+            }
+
+            entry.mg += sign * mg;
+            entry.eg += sign * eg;
+        }
+    }
+
+    entry
+}
+
+/// The pawn terms that depend on more than just the pawns: passed pawns with nothing at all in
+/// their way, and the shield in front of each king. Returns (mg, eg) from white's side.
+pub fn dynamic_terms(board: &Board, entry: &PawnEntry, kings: [(i8, i8); 2], params: &Params) -> (i32, i32) {
+    let mut mg = 0;
+    let mut eg = 0;
+
+    for (team, &(kx, ky)) in kings.iter().enumerate() {
+        let dir = forward(team);
+        let sign = if team == 0 { 1 } else { -1 };
+
+        let mut bits = entry.passed[team];
+        while bits != 0 {
+            let sq = bits.trailing_zeros() as i8;
+            bits &= bits - 1;
+
+            let (x, mut y) = (sq % 8, sq / 8);
+            let rank = relative_rank(y, team);
+            let mut free = true;
+            while (1..7).contains(&y) {
+                y += dir;
+                if board.piece_at((x, y)) != EMPTY {
+                    free = false;
+                    break
+                }
+            }
+            if free {
+                eg += sign * params.passed_free_path_eg[rank];
+            }
+        }
+
+        // the shield only means anything while the king is still tucked in on its back ranks
+        if kx >= 0 && relative_rank(ky, team) <= 1 {
+            let own = entry.pawns[team];
+            for x in (kx - 1)..=(kx + 1) {
+                if !(0..8).contains(&x) {
+                    continue
+                }
+                if own & bit(x, ky + dir) != 0 {
+                    mg += sign * params.shield_near_mg;
+                } else if own & bit(x, ky + 2 * dir) != 0 {
+                    mg += sign * params.shield_far_mg;
+                } else {
+                    mg += sign * params.shield_missing_mg;
+                }
+            }
+        }
+    }
+
+    (mg, eg)
+}
+
+/// Cache of pawn structure evaluations, keyed by the pawn-only zobrist key.
+#[derive(Debug, Clone)]
+pub struct PawnTable {
+    entries: Vec<PawnEntry>,
+}
+
+impl Default for PawnTable {
+    fn default() -> Self {
+        Self::new(DEFAULT_PAWN_TABLE_SIZE)
+    }
+}
+
+impl PawnTable {
+    pub fn new(size: usize) -> PawnTable {
+        PawnTable {
+            entries: vec![PawnEntry::default(); size.max(1)],
+        }
+    }
+
+    /// The entry for `pawns`, whose key is `key` (the board keeps it up to date as it goes)
+    pub fn get(&mut self, key: u64, pawns: [u64; 2]) -> PawnEntry {
+        let index = key as usize % self.entries.len();
+
+        let entry = &self.entries[index];
+        if entry.key == key && entry.pawns == pawns {
+            return *entry
+        }
+
+        let entry = analyse(pawns);
+        self.entries[index] = entry;
+        entry
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::board::ChessMove;
+    use crate::pawns::{analyse, bit, PawnTable};
+    use crate::uci;
+    use crate::zobrist;
+
+    fn pawns(white: &[(i8, i8)], black: &[(i8, i8)]) -> [u64; 2] {
+        [
+            white.iter().fold(0, |bits, &(x, y)| bits | bit(x, y)),
+            black.iter().fold(0, |bits, &(x, y)| bits | bit(x, y)),
+        ]
+    }
+
+    #[test]
+    fn test_passed_pawns() {
+        // white a5 is passed, white e4 is blocked by e6, black h2 is passed
+        let entry = analyse(pawns(&[(0, 4), (4, 3)], &[(4, 5), (7, 1)]));
+        assert_eq!(entry.passed[0], bit(0, 4));
+        assert_eq!(entry.passed[1], bit(7, 1));
+    }
+
+    #[test]
+    fn test_structure_penalties_are_symmetric() {
+        // doubled and isolated c pawns for white, mirrored for black
+        let white = analyse(pawns(&[(2, 1), (2, 2), (5, 1), (6, 1)], &[(5, 6), (6, 6)]));
+        let black = analyse(pawns(&[(5, 1), (6, 1)], &[(2, 6), (2, 5), (5, 6), (6, 6)]));
+        assert!(white.mg < 0);
+        assert_eq!(white.mg, -black.mg);
+        assert_eq!(white.eg, -black.eg);
+    }
+
+    #[test]
+    fn test_table_matches_analyse() {
+        let structure = pawns(&[(0, 1), (1, 2), (4, 3)], &[(3, 6), (4, 4)]);
+        let mut table = PawnTable::new(16);
+        let fresh = analyse(structure);
+        for _ in 0..2 {
+            let cached = table.get(zobrist::pawn_key(&structure), structure);
+            assert_eq!((cached.mg, cached.eg, cached.passed), (fresh.mg, fresh.eg, fresh.passed));
+        }
+    }
+
+    #[test]
+    fn test_board_keeps_pawn_key_up_to_date() {
+        // a double push and en passant, castling, a pawn taking a piece, a piece taking a pawn,
+        // and a promotion with a capture
+        let mut board = uci::position_board(Some("r3k2r/1P3ppp/8/8/5p2/8/6PP/R3K2R w KQkq - 0 1"), &[]).unwrap();
+        for notation in ["g2g4", "f4g3", "e1g1", "g3h2", "g1h2", "e8c8", "b7a8q"] {
+            board = board.apply_move(&ChessMove::from_long_algebraic_notation(notation));
+            let fresh = uci::position_board(Some(board.to_fen().as_str()), &[]).unwrap();
+            assert_eq!(board.pawn_key(), fresh.pawn_key(), "after {}", notation);
+        }
+    }
+}
@@ -0,0 +1,433 @@
+use crate::board::{Board, ChessMove, GameResult, BISHOP, EMPTY, KING, KNIGHT, PAWN, QUEEN, ROOK, UNIT_MASK};
+
+// Just enough PGN to pull the main line out of games: tag pairs are kept, comments, variations,
+// NAGs and move numbers are thrown away, and the moves are left as SAN for parse_san to turn
+// into moves once there's a board to play them on.
+
+#[derive(Debug, Clone, Default)]
+pub struct PgnGame {
+    pub tags: Vec<(String, String)>,
+    pub moves: Vec<String>,
+    pub result: Option<GameResult>,
+}
+
+impl PgnGame {
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(tag, _)| tag == name).map(|(_, value)| value.as_str())
+    }
+
+    /// The position the game starts from, which is the standard one unless there's a FEN tag
+    pub fn start(&self) -> std::result::Result<Board, String> {
+        let fen = self.tag("FEN").unwrap_or("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        let parts: Vec<&str> = fen.split_whitespace().collect();
+        if parts.len() != 6 {
+            return Err(format!("bad FEN tag: {}", fen))
+        }
+        let mut arg: [&str; 6] = Default::default();
+        arg.copy_from_slice(&parts);
+        Board::from_fen(arg)
+    }
+
+    /// The game written out as PGN, tags first, ready to be appended to a file
+    pub fn to_pgn_string(&self) -> String {
+        let mut res = String::new();
+        for (name, value) in &self.tags {
+            res.push_str(format!("[{} \"{}\"]\n", name, value.replace('\\', "\\\\").replace('"', "\\\"")).as_str());
+        }
+        res.push('\n');
+
+        // move numbers carry on from the FEN, and a game starting with black needs "1..."
+        let (mut number, mut white) = match self.start() {
+            Ok(board) => (board.full_move_number(), board.is_whites_move()),
+            Err(_) => (1, true),
+        };
+        let mut line = String::new();
+        for (i, san) in self.moves.iter().enumerate() {
+            let token = if white {
+                format!("{}. {}", number, san)
+            } else if i == 0 {
+                format!("{}... {}", number, san)
+            } else {
+                san.clone()
+            };
+            if !white {
+                number += 1;
+            }
+            white = !white;
+
+            // keep lines under 80 characters like the export format asks
+            if !line.is_empty() && line.len() + token.len() + 1 > 79 {
+                res.push_str(line.as_str());
+                res.push('\n');
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(token.as_str());
+        }
+
+        let result = match self.result {
+            Some(GameResult::WhiteWin) => "1-0",
+            Some(GameResult::BlackWin) => "0-1",
+            Some(GameResult::Draw) => "1/2-1/2",
+            None => "*",
+        };
+        if !line.is_empty() && line.len() + result.len() + 1 > 79 {
+            res.push_str(line.as_str());
+            res.push('\n');
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(result);
+        res.push_str(line.as_str());
+        res.push_str("\n\n");
+        res
+    }
+
+    /// Plays the game out, returning every move as ours.
+    pub fn chess_moves(&self) -> std::result::Result<Vec<ChessMove>, String> {
+        let mut board = self.start()?;
+        let mut res = Vec::with_capacity(self.moves.len());
+        for san in &self.moves {
+            let chess_move = parse_san(&board, san)?;
+            board = board.apply_move(&chess_move);
+            res.push(chess_move);
+        }
+        Ok(res)
+    }
+}
+
+fn result_from_token(token: &str) -> Option<Option<GameResult>> {
+    match token {
+        "1-0" => Some(Some(GameResult::WhiteWin)),
+        "0-1" => Some(Some(GameResult::BlackWin)),
+        "1/2-1/2" => Some(Some(GameResult::Draw)),
+        "*" => Some(None),
+        _ => None,
+    }
+}
+
+/// Splits PGN text into its games. A game ends at its result token, or when the next one's tags
+/// start.
+pub fn parse_games(text: &str) -> Vec<PgnGame> {
+    let mut games = Vec::new();
+    let mut game = PgnGame::default();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '[' => {
+                // a tag after moves means we missed the last game's result
+                if !game.moves.is_empty() {
+                    games.push(std::mem::take(&mut game));
+                }
+                let tag: String = chars.by_ref().take_while(|&c| c != ']').collect();
+                let tag = tag.trim();
+                if let Some(space) = tag.find(char::is_whitespace) {
+                    let value = tag[space..].trim().trim_matches('"').replace("\\\"", "\"");
+                    game.tags.push((tag[..space].to_string(), value));
+                }
+            },
+            '{' => {
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        break
+                    }
+                }
+            },
+            ';' | '%' => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break
+                    }
+                }
+            },
+            '(' => {
+                // variations can hold comments with brackets in them and other variations
+                let mut depth = 1;
+                while depth > 0 {
+                    match chars.next() {
+                        Some('(') => depth += 1,
+                        Some(')') => depth -= 1,
+                        Some('{') => {
+                            for c in chars.by_ref() {
+                                if c == '}' {
+                                    break
+                                }
+                            }
+                        },
+                        Some(_) => {},
+                        None => break,
+                    }
+                }
+            },
+            c if c.is_whitespace() => {},
+            c => {
+                let mut token = c.to_string();
+                while let Some(&next) = chars.peek() {
+                    if next.is_whitespace() || "[]{}();".contains(next) {
+                        break
+                    }
+                    token.push(next);
+                    chars.next();
+                }
+
+                if let Some(result) = result_from_token(&token) {
+                    game.result = result;
+                    games.push(std::mem::take(&mut game));
+                    continue
+                }
+
+                // NAGs, and move numbers which may be glued onto the move ("1.e4")
+                if token.starts_with('$') {
+                    continue
+                }
+                let san = token.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+                if !san.is_empty() {
+                    game.moves.push(san.to_string());
+                }
+            },
+        }
+    }
+
+    if !game.moves.is_empty() {
+        games.push(game);
+    }
+    games
+}
+
+fn piece_for_letter(letter: char) -> Option<u8> {
+    match letter {
+        'N' => Some(KNIGHT),
+        'B' => Some(BISHOP),
+        'R' => Some(ROOK),
+        'Q' => Some(QUEEN),
+        'K' => Some(KING),
+        _ => None,
+    }
+}
+
+fn square(file: char, rank: char) -> Option<(i8, i8)> {
+    if ('a'..='h').contains(&file) && ('1'..='8').contains(&rank) {
+        Some((file as i8 - 'a' as i8, rank as i8 - '1' as i8))
+    } else {
+        None
+    }
+}
+
+/// Turns a move in standard algebraic notation ("Nbd7", "exd6", "e8=Q+", "O-O") into a move on
+/// this board. Anything that isn't one of the board's legal moves is an error.
+pub fn parse_san(board: &Board, san: &str) -> std::result::Result<ChessMove, String> {
+    let err = || format!("can't play {} here", san);
+    let clean: String = san.chars().filter(|c| !"+#!?".contains(*c)).collect();
+    let back_rank = if board.is_whites_move() { 0 } else { 7 };
+    let legal = board.clone().get_legal_moves();
+
+    let castle = match clean.as_str() {
+        "O-O" | "0-0" => Some(6),
+        "O-O-O" | "0-0-0" => Some(2),
+        _ => None,
+    };
+    if let Some(file) = castle {
+        let cm = ChessMove::new((4, back_rank), (file, back_rank), EMPTY);
+        return if board.piece_at((4, back_rank)) & UNIT_MASK == KING && legal.contains(&cm) { Ok(cm) } else { Err(err()) }
+    }
+
+    let mut chars: Vec<char> = clean.chars().collect();
+
+    // promotion, either "e8=Q" or "e8Q"
+    let mut promotion = EMPTY;
+    if let Some(&last) = chars.last() {
+        if let Some(piece) = piece_for_letter(last).filter(|&piece| piece != KING) {
+            promotion = piece;
+            chars.pop();
+            if chars.last() == Some(&'=') {
+                chars.pop();
+            }
+        }
+    }
+
+    if chars.len() < 2 {
+        return Err(err())
+    }
+    let to = square(chars[chars.len() - 2], chars[chars.len() - 1]).ok_or_else(err)?;
+    let piece = piece_for_letter(chars[0]).unwrap_or(PAWN);
+    let hints = &chars[if piece == PAWN { 0 } else { 1 }..chars.len() - 2];
+    let mut from_file = hints.iter().find(|c| ('a'..='h').contains(*c)).map(|&c| c as i8 - 'a' as i8);
+    let from_rank = hints.iter().find(|c| ('1'..='8').contains(*c)).map(|&c| c as i8 - '1' as i8);
+    if piece == PAWN && from_file.is_none() {
+        // a pawn move without a file is a push, so it stays on the file it's going to
+        from_file = Some(to.0);
+    }
+
+    let mut matching = legal.into_iter()
+        .filter(|cm| cm.to_ind() == to && board.piece_at(cm.from_ind()) & UNIT_MASK == piece)
+        .filter(|cm| cm.promotion() & UNIT_MASK == promotion)
+        .filter(|cm| from_file.is_none_or(|file| cm.from_ind().0 == file))
+        .filter(|cm| from_rank.is_none_or(|rank| cm.from_ind().1 == rank));
+
+    match (matching.next(), matching.next()) {
+        (Some(cm), None) => Ok(cm),
+        (Some(_), Some(_)) => Err(format!("{} is ambiguous", san)),
+        _ => Err(err()),
+    }
+}
+
+fn letter_for_piece(piece: u8) -> &'static str {
+    match piece & UNIT_MASK {
+        KNIGHT => "N",
+        BISHOP => "B",
+        ROOK => "R",
+        QUEEN => "Q",
+        KING => "K",
+        _ => "",
+    }
+}
+
+/// Writes a legal move on this board in standard algebraic notation, the reverse of parse_san.
+pub fn to_san(board: &Board, chess_move: &ChessMove) -> String {
+    let (from, to) = (chess_move.from_ind(), chess_move.to_ind());
+    let piece = board.piece_at(from) & UNIT_MASK;
+
+    let mut san = if piece == KING && (to.0 - from.0).abs() == 2 {
+        String::from(if to.0 == 6 { "O-O" } else { "O-O-O" })
+    } else {
+        let takes = board.piece_at(to) & UNIT_MASK != EMPTY || (piece == PAWN && from.0 != to.0);
+        let mut san = String::from(letter_for_piece(piece));
+
+        if piece == PAWN {
+            if takes {
+                san.push((b'a' + from.0 as u8) as char);
+            }
+        } else {
+            // only as much of the starting square as it takes to tell it apart from the others
+            let others: Vec<ChessMove> = board.clone().get_legal_moves().into_iter()
+                .filter(|cm| cm.to_ind() == to && cm.from_ind() != from && board.piece_at(cm.from_ind()) & UNIT_MASK == piece)
+                .collect();
+            if !others.is_empty() {
+                if others.iter().all(|cm| cm.from_ind().0 != from.0) {
+                    san.push((b'a' + from.0 as u8) as char);
+                } else if others.iter().all(|cm| cm.from_ind().1 != from.1) {
+                    san.push((b'1' + from.1 as u8) as char);
+                } else {
+                    san.push((b'a' + from.0 as u8) as char);
+                    san.push((b'1' + from.1 as u8) as char);
+                }
+            }
+        }
+
+        if takes {
+            san.push('x');
+        }
+        san.push((b'a' + to.0 as u8) as char);
+        san.push((b'1' + to.1 as u8) as char);
+        if chess_move.promotion() != EMPTY {
+            san.push('=');
+            san.push_str(letter_for_piece(chess_move.promotion()));
+        }
+        san
+    };
+
+    let mut after = board.apply_move(chess_move);
+    if after.in_check() {
+        san.push(if after.get_legal_moves().is_empty() { '#' } else { '+' });
+    }
+    san
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::board::{ChessMove, GameResult};
+    use crate::pgn::{parse_games, parse_san, to_san, PgnGame};
+    use crate::uci;
+
+    const PGN: &str = r#"
+[Event "Test"]
+[White "Someone"]
+[Black "Someone Else"]
+[Result "1-0"]
+
+1. e4 e5 2. Nf3 {the usual} Nc6 (2... d6 3. d4) 3. Bb5 a6 $1 4. Ba4 Nf6 5. O-O Be7 1-0
+
+[Event "Test"]
+[FEN "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1"]
+
+1. exd6 Kf7 2. d7 Ke7 3. d8=Q+ *
+"#;
+
+    #[test]
+    fn test_games_are_played_out() {
+        let games = parse_games(PGN);
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[0].tag("White"), Some("Someone"));
+        assert_eq!(games[0].result, Some(GameResult::WhiteWin));
+        assert_eq!(games[1].result, None);
+
+        let moves = games[0].chess_moves().unwrap();
+        assert_eq!(moves.len(), 10);
+        assert_eq!(moves[2], ChessMove::from_long_algebraic_notation("g1f3"));
+        assert_eq!(moves[8], ChessMove::from_long_algebraic_notation("e1g1"));
+
+        let moves = games[1].chess_moves().unwrap();
+        assert_eq!(moves[0], ChessMove::from_long_algebraic_notation("e5d6"));
+        assert_eq!(moves[4], ChessMove::from_long_algebraic_notation("d7d8q"));
+    }
+
+    #[test]
+    fn test_writing_san_and_pgn() {
+        // every move of both games should come back out the way it went in
+        for game in parse_games(PGN) {
+            let mut board = game.start().unwrap();
+            let mut written = PgnGame { tags: game.tags.clone(), moves: Vec::new(), result: game.result };
+            for chess_move in game.chess_moves().unwrap() {
+                written.moves.push(to_san(&board, &chess_move));
+                board = board.apply_move(&chess_move);
+            }
+            assert_eq!(written.moves, game.moves);
+
+            let reparsed = parse_games(&written.to_pgn_string());
+            assert_eq!(reparsed.len(), 1);
+            assert_eq!(reparsed[0].moves, game.moves);
+            assert_eq!(reparsed[0].tags, game.tags);
+        }
+    }
+
+    #[test]
+    fn test_illegal_moves_are_rejected() {
+        let cases = [
+            // castling without the right, through an attacked square, into a piece and out of check
+            ("r3k2r/8/8/8/8/8/8/R3K2R w - - 0 1", "O-O", None),
+            ("4kr2/8/8/8/8/8/8/R3K2R w KQ - 0 1", "O-O", None),
+            ("4kr2/8/8/8/8/8/8/R3K2R w KQ - 0 1", "O-O-O", Some("e1c1")),
+            ("4k3/8/8/8/8/8/8/RN2K2R w KQ - 0 1", "O-O-O", None),
+            ("4k3/8/8/8/8/8/8/RN2K2R w KQ - 0 1", "O-O", Some("e1g1")),
+            ("4k3/8/8/8/8/8/4r3/R3K2R w KQ - 0 1", "O-O", None),
+            ("r3k2r/8/8/8/8/8/8/4K3 b kq - 0 1", "O-O-O", Some("e8c8")),
+            // pawns pushing into something, two squares off their starting rank, taking nothing,
+            // taking en passant when the last move wasn't a double push, or stepping out of a pin
+            ("4k3/8/8/8/8/4n3/4P3/4K3 w - - 0 1", "e3", None),
+            ("4k3/8/8/8/8/4n3/4P3/4K3 w - - 0 1", "e4", None),
+            ("4k3/8/8/8/8/4P3/8/4K3 w - - 0 1", "e5", None),
+            ("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1", "exd3", None),
+            ("4k3/8/8/3pP3/8/8/8/4K3 w - - 0 1", "exd6", None),
+            ("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "exd6", Some("e5d6")),
+            ("4k3/8/8/8/b7/8/2P5/3K4 w - - 0 1", "c3", None),
+            ("4k3/8/8/8/b7/8/2P5/3K4 w - - 0 1", "c4", None),
+            // and a pawn reaching the last rank has to say what it becomes
+            ("4k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a8", None),
+            ("4k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a8=N", Some("a7a8n")),
+        ];
+        for &(fen, san, expected) in cases.iter() {
+            let board = uci::position_board(Some(fen), &[]).unwrap();
+            let parsed = parse_san(&board, san).map(|cm| cm.to_long_algebraic_notation());
+            match expected {
+                Some(lan) => assert_eq!(parsed, Ok(lan.to_string()), "{} in {}", san, fen),
+                None => assert!(parsed.is_err(), "{} in {} gave {:?}", san, fen, parsed),
+            }
+        }
+    }
+}
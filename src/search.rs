@@ -0,0 +1,949 @@
+use std::cmp;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::board::{Board, ChessMove, BoardSlot, UNIT_MASK, PAWN, KNIGHT, BISHOP, ROOK, QUEEN, KING};
+use crate::eval::Evaluator;
+use crate::nnue::{Accumulator, Network};
+use crate::syzygy::{Tablebases, Wdl};
+use crate::table::{TranspositionTable, TableEntry, Bound, DEFAULT_TABLE_SIZE};
+
+pub const INFINITY: i32 = 32_000;
+pub const MATE_SCORE: i32 = 31_000;
+const MATE_THRESHOLD: i32 = MATE_SCORE - 1_000;
+
+/// Score for a tablebase win, less the ply it was found at so nearer ones are preferred. Under
+/// the mate scores, since the tables don't know how far off the mate is.
+pub const TB_WIN: i32 = 20_000;
+const TB_WIN_THRESHOLD: i32 = TB_WIN - MAX_PLY as i32;
+
+const MAX_PLY: usize = 64;
+const DEFAULT_DEPTH: u32 = 5;
+pub const MAX_THREADS: usize = 64;
+pub const MAX_MULTI_PV: usize = 64;
+
+// aspiration windows only kick in once the scores have settled down a bit
+const ASPIRATION_MIN_DEPTH: u32 = 4;
+const ASPIRATION_WINDOW: i32 = 25;
+
+// how many nodes to visit between clock checks
+const CHECK_INTERVAL: u64 = 256;
+
+/// Limits given by the `go` command. Anything left as `None` is unbounded.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchLimits {
+    pub depth: Option<u32>,
+    pub nodes: Option<u64>,
+    pub movetime: Option<Duration>,
+    pub wtime: Option<Duration>,
+    pub btime: Option<Duration>,
+    pub winc: Option<Duration>,
+    pub binc: Option<Duration>,
+    pub movestogo: Option<u32>,
+    pub move_overhead: Duration,  // held back from every clock budget for lag between us and the gui
+    pub searchmoves: Vec<ChessMove>,  // if not empty, only these root moves are searched
+    pub infinite: bool,  // go infinite: bestmove has to wait for stop
+    pub ponder: bool,  // go ponder: bestmove has to wait for stop or ponderhit
+}
+
+impl SearchLimits {
+    /// How long we're willing to spend on this move, if there's a clock involved at all.
+    pub fn time_budget(&self, whites_move: bool) -> Option<Duration> {
+        if self.movetime.is_some() {
+            return self.movetime
+        }
+
+        let (time, inc) = if whites_move { (self.wtime, self.winc) } else { (self.btime, self.binc) };
+        time.map(|time| {
+            let moves_left = self.movestogo.unwrap_or(30).max(1);
+            let budget = time / moves_left + inc.unwrap_or_default() / 2;
+
+            // never burn more than half the clock on one move
+            cmp::min(budget, time / 2).saturating_sub(self.move_overhead)
+        })
+    }
+
+    fn max_depth(&self) -> u32 {
+        match self.depth {
+            Some(depth) => cmp::min(depth, MAX_PLY as u32 - 1),
+            None if self.infinite || self.ponder || self.time_budget(true).is_some() || self.time_budget(false).is_some() || self.nodes.is_some() => MAX_PLY as u32 - 1,
+            None => DEFAULT_DEPTH,
+        }
+    }
+}
+
+/// A progress report, sent once per finished iteration and once per aspiration window failure.
+#[derive(Debug, Clone)]
+pub struct SearchInfo {
+    pub depth: u32,
+    pub seldepth: u32,
+    pub score: i32,
+    pub bound: Bound,
+    pub nodes: u64,
+    pub tbhits: u64,
+    pub hashfull: u32,  // permille
+    pub time: Duration,
+    pub pv: Vec<ChessMove>,
+    pub multipv: Option<usize>,  // which line this is, 1 being the best, when showing more than one
+}
+
+impl SearchInfo {
+    pub fn to_uci_string(&self) -> String {
+        let mut msg = format!("info depth {} seldepth {}", self.depth, self.seldepth);
+        if let Some(line) = self.multipv {
+            msg.push_str(format!(" multipv {}", line).as_str());
+        }
+        msg.push_str(format!(" score {}", score_to_uci(self.score)).as_str());
+        match self.bound {
+            Bound::Lower => msg.push_str(" lowerbound"),
+            Bound::Upper => msg.push_str(" upperbound"),
+            Bound::Exact => {}
+        }
+
+        let millis = self.time.as_millis() as u64;
+        let nps = (self.nodes * 1000).checked_div(millis).unwrap_or(0);
+        msg.push_str(format!(" nodes {} nps {} tbhits {} hashfull {} time {}", self.nodes, nps, self.tbhits, self.hashfull, millis).as_str());
+
+        if !self.pv.is_empty() {
+            msg.push_str(" pv");
+            for cm in self.pv.iter() {
+                msg.push(' ');
+                msg.push_str(cm.to_long_algebraic_notation().as_str());
+            }
+        }
+
+        msg
+    }
+}
+
+/// Moves (not plies) until mate for scores near MATE_SCORE, negative when we're the one getting
+/// mated
+pub fn mate_in(score: i32) -> Option<i32> {
+    if score >= MATE_THRESHOLD {
+        Some((MATE_SCORE - score + 1) / 2)
+    } else if score <= -MATE_THRESHOLD {
+        Some(-(MATE_SCORE + score) / 2)
+    } else {
+        None
+    }
+}
+
+/// Scores near MATE_SCORE are reported as moves-to-mate rather than centipawns
+fn score_to_uci(score: i32) -> String {
+    match mate_in(score) {
+        Some(moves) => format!("mate {}", moves),
+        None => format!("cp {}", score),
+    }
+}
+
+// mate and tablebase scores are stored relative to the node rather than the root, so they stay
+// correct when the same position turns up at a different ply
+fn score_to_table(score: i32, ply: usize) -> i32 {
+    if score >= TB_WIN_THRESHOLD {
+        score + ply as i32
+    } else if score <= -TB_WIN_THRESHOLD {
+        score - ply as i32
+    } else {
+        score
+    }
+}
+
+fn score_from_table(score: i32, ply: usize) -> i32 {
+    if score >= TB_WIN_THRESHOLD {
+        score - ply as i32
+    } else if score <= -TB_WIN_THRESHOLD {
+        score + ply as i32
+    } else {
+        score
+    }
+}
+
+fn piece_order_value(slot: BoardSlot) -> i32 {
+    match slot & UNIT_MASK {
+        PAWN => 1,
+        KNIGHT => 3,
+        BISHOP => 3,
+        ROOK => 5,
+        QUEEN => 9,
+        KING => 10,
+        _ => 0,
+    }
+}
+
+/// Everything the search threads share while a `go` is running
+#[derive(Debug)]
+struct Shared {
+    stop: AtomicBool,
+    stop_signal: Arc<AtomicBool>,  // set from outside to end the search early
+    nodes: AtomicU64,
+    tbhits: AtomicU64,
+    root_moves: Vec<ChessMove>,  // if not empty, the only moves to look at from the root
+    multi_pv: usize,
+    start: Instant,
+    deadline: Option<Instant>,
+    node_limit: Option<u64>,
+}
+
+/// Lazy SMP: every thread runs its own iterative deepening over the same position, and they only
+/// cooperate through the transposition table. Helpers fill the table with results the main thread
+/// then gets for free; only the main thread reports and decides the move.
+#[derive(Debug, Clone)]
+pub struct Searcher {
+    table: Arc<TranspositionTable>,
+    tablebases: Arc<Tablebases>,
+    network: Option<Arc<Network>>,  // evaluates in place of the handcrafted eval when set
+    threads: usize,
+    multi_pv: usize,
+    stop_signal: Arc<AtomicBool>,
+}
+
+impl Default for Searcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Searcher {
+    pub fn new() -> Searcher {
+        Searcher {
+            table: Arc::new(TranspositionTable::new(DEFAULT_TABLE_SIZE)),
+            tablebases: Arc::new(Tablebases::default()),
+            network: None,
+            threads: 1,
+            multi_pv: 1,
+            stop_signal: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.clamp(1, MAX_THREADS);
+    }
+
+    /// How many of the best lines to search and report, rather than just the best one
+    pub fn set_multi_pv(&mut self, lines: usize) {
+        self.multi_pv = lines.clamp(1, MAX_MULTI_PV);
+    }
+
+    /// A flag another thread can set to end the search early, as if it had run out of time. It's
+    /// never cleared here, that's up to whoever sets it.
+    pub fn set_stop_signal(&mut self, stop_signal: Arc<AtomicBool>) {
+        self.stop_signal = stop_signal;
+    }
+
+    pub fn clear(&mut self) {
+        self.table.clear();
+    }
+
+    /// Replaces the transposition table with an empty one of about `megabytes` in size.
+    pub fn set_hash_size(&mut self, megabytes: usize) {
+        self.table = Arc::new(TranspositionTable::with_megabytes(megabytes));
+    }
+
+    pub fn tablebases(&self) -> &Tablebases {
+        &self.tablebases
+    }
+
+    /// Loads the Syzygy tables found under `path` in place of whatever was there before.
+    pub fn set_syzygy_path(&mut self, path: &str) {
+        self.tablebases = Arc::new(Tablebases::new(path));
+    }
+
+    /// Switches the evaluation over to `network`, or back to the handcrafted one with None
+    pub fn set_network(&mut self, network: Option<Arc<Network>>) {
+        self.network = network;
+    }
+
+    pub fn network(&self) -> Option<&Network> {
+        self.network.as_deref()
+    }
+
+    /// Runs the search on `threads` threads. Every finished iteration (and every aspiration window
+    /// failure) on the main thread is handed to `report` as it happens.
+    pub fn find_best_move<F>(&mut self, board: Board, limits: &SearchLimits, report: F) -> std::result::Result<ChessMove, String>
+        where F: FnMut(&SearchInfo) {
+        let start = Instant::now();
+
+        // with the position in the tablebases, only the moves that keep the best result are
+        // worth searching. If we've been told which moves to look at, stick to those, but still
+        // drop the ones the tables say are worse.
+        let mut root_moves = limits.searchmoves.clone();
+        let mut tbhits = 0;
+        if self.tablebases.can_probe(&board) {
+            if let Some(moves) = self.tablebases.root_moves(&board) {
+                tbhits = 1;
+                let best: Vec<ChessMove> = moves.iter()
+                    .filter(|cm| root_moves.is_empty() || root_moves.contains(cm))
+                    .cloned()
+                    .collect();
+                if !best.is_empty() {
+                    root_moves = best;
+                }
+            }
+        }
+
+        let shared = Shared {
+            stop: AtomicBool::new(false),
+            stop_signal: self.stop_signal.clone(),
+            nodes: AtomicU64::new(0),
+            tbhits: AtomicU64::new(tbhits),
+            root_moves,
+            multi_pv: self.multi_pv,
+            start,
+            deadline: limits.time_budget(board.is_whites_move()).map(|b| start + b),
+            node_limit: limits.nodes,
+        };
+        let table: &TranspositionTable = &self.table;
+        let tablebases: &Tablebases = &self.tablebases;
+        let network: Option<&Network> = self.network.as_deref();
+
+        thread::scope(|scope| {
+            for id in 1..self.threads {
+                let shared = &shared;
+                let board = board.clone();
+                scope.spawn(move || {
+                    // helpers just keep going deeper until the main thread is done with them
+                    let helper_limits = SearchLimits { depth: Some(MAX_PLY as u32 - 1), ..Default::default() };
+                    let _ = Worker::new(id, table, tablebases, network, shared).iterate(&board, &helper_limits, |_| {});
+                });
+            }
+
+            let res = Worker::new(0, table, tablebases, network, &shared).iterate(&board, limits, report);
+            shared.stop.store(true, Ordering::Relaxed);
+            res
+        })
+    }
+}
+
+/// A single search thread. Killers, the pv and the repetition path are all per-thread.
+struct Worker<'a> {
+    id: usize,
+    table: &'a TranspositionTable,
+    tablebases: &'a Tablebases,
+    shared: &'a Shared,
+    evaluator: Evaluator,
+    network: Option<&'a Network>,
+    accumulators: Vec<Accumulator>,  // the network's, indexed by ply; only filled in with a network
+
+    killers: Vec<[Option<ChessMove>; 2]>,
+    pv: Vec<Vec<ChessMove>>,  // triangular pv table, indexed by ply
+    excluded: Vec<ChessMove>,  // root moves already given their own line this iteration
+    path: Vec<u64>,  // board ids of the current line, for spotting repetitions
+
+    nodes: u64,  // visited by this thread
+    flushed: u64,  // how many of those have been added to the shared count
+    seldepth: u32,
+    stopped: bool,
+}
+
+impl<'a> Worker<'a> {
+    fn new(id: usize, table: &'a TranspositionTable, tablebases: &'a Tablebases, network: Option<&'a Network>, shared: &'a Shared) -> Worker<'a> {
+        Worker {
+            id,
+            table,
+            tablebases,
+            shared,
+            evaluator: Evaluator::new(),
+            network,
+            accumulators: network.map(|network| vec![network.new_accumulator(); MAX_PLY + 1]).unwrap_or_default(),
+            killers: vec![[None, None]; MAX_PLY],
+            pv: vec![Vec::new(); MAX_PLY + 1],
+            excluded: Vec::new(),
+            path: Vec::with_capacity(MAX_PLY),
+            nodes: 0,
+            flushed: 0,
+            seldepth: 0,
+            stopped: false,
+        }
+    }
+
+    /// Iterative deepening PVS. With MultiPV each depth is searched once per line, leaving out
+    /// the root moves of the lines already found, and the lines are reported best first once
+    /// they're all done.
+    fn iterate<F>(&mut self, board: &Board, limits: &SearchLimits, mut report: F) -> std::result::Result<ChessMove, String>
+        where F: FnMut(&SearchInfo) {
+        let budget = limits.time_budget(board.is_whites_move());
+        if let Some(network) = self.network {
+            network.refresh(&mut self.accumulators[0], board);
+        }
+
+        let mut best_move: Option<ChessMove> = None;
+        let mut lines: Vec<(i32, Vec<ChessMove>)> = Vec::new();  // (score, pv), from the last finished depth
+        let lines_wanted = self.lines_wanted(board);
+
+        // staggering the start depth of every other helper keeps the threads from all searching
+        // the exact same tree in lockstep
+        let first_depth = 1 + (self.id % 2) as u32;
+
+        for depth in first_depth..=limits.max_depth() {
+            let mut found: Vec<(i32, Vec<ChessMove>)> = Vec::with_capacity(lines_wanted);
+
+            for line in 0..lines_wanted {
+                self.excluded = found.iter().filter_map(|(_, pv)| pv.first().cloned()).collect();
+                let mut score = lines.get(line).map(|(score, _)| *score).unwrap_or(0);
+
+                let mut delta = ASPIRATION_WINDOW;
+                let (mut alpha, mut beta) = if depth >= ASPIRATION_MIN_DEPTH {
+                    (cmp::max(score - delta, -INFINITY), cmp::min(score + delta, INFINITY))
+                } else {
+                    (-INFINITY, INFINITY)
+                };
+
+                loop {
+                    let s = self.negamax(board, depth as i32, 0, alpha, beta);
+                    if self.stopped {
+                        break
+                    }
+
+                    if s <= alpha {
+                        // failed low, widen downwards and pull beta in towards the middle
+                        report(&self.info(depth, s, Bound::Upper, &self.pv[0], line));
+                        beta = (alpha + beta) / 2;
+                        alpha = cmp::max(s - delta, -INFINITY);
+                    } else if s >= beta {
+                        report(&self.info(depth, s, Bound::Lower, &self.pv[0], line));
+                        beta = cmp::min(s + delta, INFINITY);
+                    } else {
+                        score = s;
+                        break
+                    }
+
+                    delta += delta / 2;
+                }
+
+                if self.stopped || self.pv[0].is_empty() {
+                    break
+                }
+
+                // a later line can still come out ahead of an earlier one, so keep them in order
+                found.push((score, self.pv[0].clone()));
+                found.sort_by_key(|(score, _)| -score);
+                best_move = found[0].1.first().cloned();
+            }
+            self.excluded.clear();
+
+            if self.stopped {
+                break
+            }
+
+            for (line, (score, pv)) in found.iter().enumerate() {
+                report(&self.info(depth, *score, Bound::Exact, pv, line));
+            }
+            lines = found;
+
+            // another iteration takes a few times as long as this one did, so don't bother
+            // starting it if we've already used up half the budget
+            if let Some(budget) = budget {
+                if self.shared.start.elapsed() > budget / 2 {
+                    break
+                }
+            }
+        }
+
+        self.flush_nodes();
+
+        // if we ran out of time before even the first iteration finished, whatever the root has
+        // found so far is still better than nothing
+        best_move
+            .or_else(|| self.pv[0].first().cloned())
+            .ok_or_else(|| String::from("no legal moves"))
+    }
+
+    /// Number of lines to search from the root. Only the main thread does MultiPV, and there's
+    /// no point asking for more lines than there are moves.
+    fn lines_wanted(&self, board: &Board) -> usize {
+        if self.id != 0 || self.shared.multi_pv <= 1 {
+            return 1
+        }
+        let mut moves = board.clone().get_legal_moves();
+        if !self.shared.root_moves.is_empty() {
+            moves.retain(|cm| self.shared.root_moves.contains(cm));
+        }
+        self.shared.multi_pv.min(moves.len()).max(1)
+    }
+
+    fn info(&self, depth: u32, score: i32, bound: Bound, pv: &[ChessMove], line: usize) -> SearchInfo {
+        SearchInfo {
+            depth,
+            seldepth: cmp::max(self.seldepth, depth),
+            score,
+            bound,
+            nodes: self.shared.nodes.load(Ordering::Relaxed) + self.nodes - self.flushed,
+            tbhits: self.shared.tbhits.load(Ordering::Relaxed),
+            hashfull: self.table.hashfull(),
+            time: self.shared.start.elapsed(),
+            pv: pv.to_vec(),
+            multipv: if self.shared.multi_pv > 1 { Some(line + 1) } else { None },
+        }
+    }
+
+    fn negamax(&mut self, board: &Board, mut depth: i32, ply: usize, mut alpha: i32, beta: i32) -> i32 {
+        self.pv[ply].clear();
+
+        let in_check = board.in_check();
+        if in_check {
+            depth += 1;
+        }
+
+        if depth <= 0 || ply >= MAX_PLY - 1 {
+            return self.quiesce(board, ply, alpha, beta)
+        }
+
+        self.visit(ply);
+        if self.stopped {
+            return 0
+        }
+
+        let key = board.id();
+        if ply > 0 && self.path.contains(&key) {
+            return 0
+        }
+
+        // only nodes with an open window can be on the principal variation; everything else is
+        // searched with a zero window and is only trying to prove a fail high or low
+        let pv_node = beta - alpha > 1;
+
+        let mut table_move: Option<ChessMove> = None;
+        if let Some(entry) = self.table.probe(key) {
+            table_move = entry.best_move.clone();
+
+            if !pv_node && entry.depth >= depth as u32 {
+                let s = score_from_table(entry.score, ply);
+                match entry.bound {
+                    Bound::Exact => return s,
+                    Bound::Lower if s >= beta => return s,
+                    Bound::Upper if s <= alpha => return s,
+                    _ => {}
+                }
+            }
+        }
+
+        // the tables know the real result, so there's nothing to search unless it's a win or
+        // loss that doesn't settle the window. They don't know about the fifty move rule though,
+        // so only straight after a capture or pawn move, when the clock has just been reset,
+        // can a win by the tables be trusted to be a win over the board.
+        if ply > 0 && board.half_move_clock() == 0 && self.tablebases.can_probe(board) {
+            if let Some(wdl) = self.tablebases.probe_wdl(board) {
+                self.shared.tbhits.fetch_add(1, Ordering::Relaxed);
+
+                let (score, bound) = match wdl {
+                    Wdl::Win => (TB_WIN - ply as i32, Bound::Lower),
+                    Wdl::Loss => (-TB_WIN + ply as i32, Bound::Upper),
+                    _ => (0, Bound::Exact),
+                };
+                let cutoff = match bound {
+                    Bound::Exact => true,
+                    Bound::Lower => score >= beta,
+                    Bound::Upper => score <= alpha,
+                };
+                if cutoff {
+                    self.table.store(key, TableEntry {
+                        depth: cmp::min(depth as u32 + 6, MAX_PLY as u32 - 1),
+                        score: score_to_table(score, ply),
+                        bound,
+                        best_move: None,
+                    });
+                    return score
+                }
+            }
+        }
+
+        let mut moves = self.ordered_moves(board, table_move.as_ref(), ply);
+        if ply == 0 && !self.shared.root_moves.is_empty() {
+            moves.retain(|cm| self.shared.root_moves.contains(cm));
+        }
+        if ply == 0 && (!self.shared.root_moves.is_empty() || !self.excluded.is_empty()) {
+            moves.retain(|cm| !self.excluded.contains(cm));
+        }
+        let original_alpha = alpha;
+        let mut best_score = -INFINITY;
+        let mut best_move: Option<ChessMove> = None;
+        let mut legal_moves = 0;
+
+        self.path.push(key);
+        for cm in moves.into_iter() {
+            let child = board.apply_move(&cm);
+            if child.moved_into_check() {
+                continue
+            }
+            self.update_accumulator(ply, board, &child);
+            legal_moves += 1;
+
+            let score = if legal_moves == 1 {
+                -self.negamax(&child, depth - 1, ply + 1, -beta, -alpha)
+            } else {
+                // assume the first move was best and just try to prove this one is worse. If it
+                // isn't, we have to pay for a full re-search to get its real score
+                let s = -self.negamax(&child, depth - 1, ply + 1, -alpha - 1, -alpha);
+                if s > alpha && s < beta && !self.stopped {
+                    -self.negamax(&child, depth - 1, ply + 1, -beta, -alpha)
+                } else {
+                    s
+                }
+            };
+
+            if self.stopped {
+                self.path.pop();
+                return 0
+            }
+
+            if score > best_score {
+                best_score = score;
+                best_move = Some(cm.clone());
+
+                if score > alpha {
+                    alpha = score;
+
+                    let mut line = vec![cm.clone()];
+                    line.extend(self.pv[ply + 1].iter().cloned());
+                    self.pv[ply] = line;
+
+                    if alpha >= beta {
+                        if !board.move_is_take(&cm) {
+                            self.store_killer(ply, cm);
+                        }
+                        break
+                    }
+                }
+            }
+        }
+        self.path.pop();
+
+        if legal_moves == 0 {
+            // checkmate or stalemate
+            return if in_check { -MATE_SCORE + ply as i32 } else { 0 }
+        }
+
+        // with moves left out of the root, by searchmoves, the tablebases or an earlier MultiPV
+        // line, this isn't the root's real score, so don't keep it
+        if ply == 0 && (!self.shared.root_moves.is_empty() || !self.excluded.is_empty()) {
+            return best_score
+        }
+
+        let bound = if best_score >= beta {
+            Bound::Lower
+        } else if best_score > original_alpha {
+            Bound::Exact
+        } else {
+            Bound::Upper
+        };
+        self.table.store(key, TableEntry {
+            depth: depth as u32,
+            score: score_to_table(best_score, ply),
+            bound,
+            best_move,
+        });
+
+        best_score
+    }
+
+    /// Only look at captures until the position goes quiet, so we don't stop the search halfway
+    /// through a trade.
+    fn quiesce(&mut self, board: &Board, ply: usize, mut alpha: i32, beta: i32) -> i32 {
+        self.pv[ply].clear();
+
+        self.visit(ply);
+        if self.stopped {
+            return 0
+        }
+
+        let stand_pat = self.evaluate(board, ply);
+        if stand_pat >= beta || ply >= MAX_PLY - 1 {
+            return stand_pat
+        }
+        alpha = cmp::max(alpha, stand_pat);
+        let mut best_score = stand_pat;
+
+        let mut captures: Vec<ChessMove> = board.get_pseudo_legal_moves()
+            .into_iter()
+            .filter(|cm| board.move_is_take(cm))
+            .collect();
+        captures.sort_by_cached_key(|cm| -Worker::mvv_lva(board, cm));
+
+        for cm in captures.into_iter() {
+            let child = board.apply_move(&cm);
+            if child.moved_into_check() {
+                continue
+            }
+            self.update_accumulator(ply, board, &child);
+
+            let score = -self.quiesce(&child, ply + 1, -beta, -alpha);
+            if self.stopped {
+                return 0
+            }
+
+            if score > best_score {
+                best_score = score;
+                if score > alpha {
+                    alpha = score;
+                    if alpha >= beta {
+                        break
+                    }
+                }
+            }
+        }
+
+        best_score
+    }
+
+    /// Static evaluation for the side to move, by the network if there is one
+    fn evaluate(&mut self, board: &Board, ply: usize) -> i32 {
+        match self.network {
+            Some(network) => network.evaluate(&self.accumulators[ply], board.is_whites_move()),
+            None => self.evaluator.evaluate_relative(board),
+        }
+    }
+
+    /// Brings the network's accumulator for the next ply up to date with `child`. The board is
+    /// copy-make, so there's no unmake: going back up just means using the parent's entry again.
+    fn update_accumulator(&mut self, ply: usize, board: &Board, child: &Board) {
+        if let Some(network) = self.network {
+            let (done, next) = self.accumulators.split_at_mut(ply + 1);
+            network.update(&mut next[0], &done[ply], board, child);
+        }
+    }
+
+    /// Adds whatever this thread has visited since last time to the shared count, giving back
+    /// the new total
+    fn flush_nodes(&mut self) -> u64 {
+        let unflushed = self.nodes - self.flushed;
+        self.flushed = self.nodes;
+        self.shared.nodes.fetch_add(unflushed, Ordering::Relaxed) + unflushed
+    }
+
+    fn visit(&mut self, ply: usize) {
+        self.nodes += 1;
+        self.seldepth = cmp::max(self.seldepth, ply as u32);
+
+        // batch up the shared node count so the threads aren't all hammering the same counter
+        if self.nodes - self.flushed < CHECK_INTERVAL {
+            return
+        }
+        let total = self.flush_nodes();
+
+        let out_of_nodes = self.shared.node_limit.map(|limit| total >= limit).unwrap_or(false);
+        let out_of_time = self.shared.deadline.map(|deadline| Instant::now() >= deadline).unwrap_or(false);
+        let signalled = self.shared.stop_signal.load(Ordering::Relaxed);
+        if out_of_nodes || out_of_time || signalled {
+            self.shared.stop.store(true, Ordering::Relaxed);
+        }
+
+        if self.shared.stop.load(Ordering::Relaxed) {
+            self.stopped = true;
+        }
+    }
+
+    /// Table move first, then captures (most valuable victim, least valuable attacker), then
+    /// killers, then everything else.
+    fn ordered_moves(&self, board: &Board, table_move: Option<&ChessMove>, ply: usize) -> Vec<ChessMove> {
+        let killers = &self.killers[ply];
+        let mut moves = board.get_pseudo_legal_moves();
+        moves.sort_by_cached_key(|cm| {
+            let order = if Some(cm) == table_move {
+                1_000_000
+            } else if board.move_is_take(cm) {
+                100_000 + Worker::mvv_lva(board, cm)
+            } else if killers.iter().any(|k| k.as_ref() == Some(cm)) {
+                10_000
+            } else {
+                0
+            };
+            -order
+        });
+        moves
+    }
+
+    fn mvv_lva(board: &Board, cm: &ChessMove) -> i32 {
+        let victim = piece_order_value(board.piece_at(cm.to_ind()));
+        let attacker = piece_order_value(board.piece_at(cm.from_ind()));
+        victim * 16 - attacker
+    }
+
+    fn store_killer(&mut self, ply: usize, cm: ChessMove) {
+        let killers = &mut self.killers[ply];
+        if killers[0].as_ref() != Some(&cm) {
+            killers[1] = killers[0].take();
+            killers[0] = Some(cm);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::board::{Board, ChessMove};
+    use crate::search::{Searcher, SearchLimits, SearchInfo, Shared, Worker, MATE_SCORE, MAX_PLY, score_to_uci};
+    use crate::syzygy::{self, Tablebases};
+    use crate::table::{Bound, TranspositionTable};
+
+    fn search(fen: [&str; 6], depth: u32) -> (ChessMove, Vec<SearchInfo>) {
+        let board = Board::from_fen(fen).unwrap();
+        let limits = SearchLimits { depth: Some(depth), ..Default::default() };
+        let mut infos = Vec::new();
+        let cm = Searcher::new().find_best_move(board, &limits, |info| infos.push(info.clone())).unwrap();
+        (cm, infos)
+    }
+
+    #[test]
+    fn test_finds_back_rank_mate() {
+        let (cm, infos) = search(["6k1/5ppp/8/8/8/8/8/R5K1", "w", "-", "-", "0", "1"], 3);
+        assert_eq!(cm.to_long_algebraic_notation(), "a1a8");
+
+        let last = infos.last().unwrap();
+        assert_eq!(last.bound, Bound::Exact);
+        assert_eq!(last.score, MATE_SCORE - 1);
+        assert!(last.to_uci_string().contains("score mate 1"));
+    }
+
+    #[test]
+    fn test_takes_hanging_queen() {
+        let (cm, _) = search(["4k3/8/8/3q4/8/8/3R4/4K3", "w", "-", "-", "0", "1"], 2);
+        assert_eq!(cm.to_long_algebraic_notation(), "d2d5");
+    }
+
+    #[test]
+    fn test_helper_threads_count_nodes() {
+        let board = Board::from_fen(["6k1/5ppp/8/8/8/8/8/R5K1", "w", "-", "-", "0", "1"]).unwrap();
+        let limits = SearchLimits { depth: Some(3), ..Default::default() };
+        let mut searcher = Searcher::new();
+        searcher.set_threads(3);
+
+        let mut last: Option<SearchInfo> = None;
+        let cm = searcher.find_best_move(board.clone(), &limits, |info| last = Some(info.clone())).unwrap();
+        assert_eq!(cm.to_long_algebraic_notation(), "a1a8");
+        assert!(last.unwrap().nodes > 0);
+
+        // run a main thread and a helper by hand, so the main thread's own count is on hand
+        let shared = Shared {
+            stop: AtomicBool::new(false),
+            stop_signal: Arc::new(AtomicBool::new(false)),
+            nodes: AtomicU64::new(0),
+            tbhits: AtomicU64::new(0),
+            root_moves: Vec::new(),
+            multi_pv: 1,
+            start: Instant::now(),
+            deadline: None,
+            node_limit: None,
+        };
+        let table = TranspositionTable::with_megabytes(1);
+        let tablebases = Tablebases::new("");
+        let (main, helper) = thread::scope(|scope| {
+            let helper = scope.spawn(|| {
+                let mut helper = Worker::new(1, &table, &tablebases, None, &shared);
+                let helper_limits = SearchLimits { depth: Some(MAX_PLY as u32 - 1), ..Default::default() };
+                let _ = helper.iterate(&board, &helper_limits, |_| {});
+                helper.nodes
+            });
+            let mut main = Worker::new(0, &table, &tablebases, None, &shared);
+            main.iterate(&board, &limits, |_| {}).unwrap();
+            shared.stop.store(true, Ordering::Relaxed);
+            (main.nodes, helper.join().unwrap())
+        });
+        // every node either thread visited makes it into the count, not just the full batches
+        assert!(helper > 0);
+        assert_eq!(shared.nodes.load(Ordering::Relaxed), main + helper);
+    }
+
+    #[test]
+    fn test_multi_pv_lines_are_distinct() {
+        let board = Board::from_fen(["4k3/8/8/3q4/8/8/3R4/4K3", "w", "-", "-", "0", "1"]).unwrap();
+        let limits = SearchLimits { depth: Some(3), ..Default::default() };
+        let mut searcher = Searcher::new();
+        searcher.set_multi_pv(3);
+
+        let mut infos = Vec::new();
+        let cm = searcher.find_best_move(board, &limits, |info| infos.push(info.clone())).unwrap();
+        assert_eq!(cm.to_long_algebraic_notation(), "d2d5");
+
+        let last: Vec<&SearchInfo> = infos.iter().filter(|info| info.depth == 3 && info.bound == Bound::Exact).collect();
+        assert_eq!(last.len(), 3);
+        assert_eq!(last[0].multipv, Some(1));
+        assert_eq!(last[0].pv[0], cm);
+        assert!(last[0].score >= last[1].score && last[1].score >= last[2].score);
+        assert_ne!(last[1].pv[0], last[2].pv[0]);
+        assert!(last[2].to_uci_string().contains("multipv 3 score"));
+    }
+
+    #[test]
+    fn test_searchmoves_restricts_root() {
+        let board = Board::from_fen(["4k3/8/8/3q4/8/8/3R4/4K3", "w", "-", "-", "0", "1"]).unwrap();
+        let limits = SearchLimits {
+            depth: Some(2),
+            searchmoves: vec![ChessMove::from_long_algebraic_notation("e1f1"), ChessMove::from_long_algebraic_notation("e1e2")],
+            ..Default::default()
+        };
+        let mut searcher = Searcher::new();
+        let cm = searcher.find_best_move(board.clone(), &limits, |_| {}).unwrap();
+        assert!(limits.searchmoves.contains(&cm));
+
+        // the score is only the best of the moves it was given, so the root isn't kept in the
+        // table for a later search to pick up
+        assert!(searcher.table.probe(board.id()).is_none());
+    }
+
+    #[test]
+    fn test_infinite_search_runs_until_signalled() {
+        let infinite = SearchLimits { infinite: true, ..Default::default() };
+        assert_eq!(infinite.max_depth(), MAX_PLY as u32 - 1);
+        assert_eq!(SearchLimits { ponder: true, ..Default::default() }.max_depth(), MAX_PLY as u32 - 1);
+
+        let board = Board::from_fen(["6k1/5ppp/8/8/8/8/8/R5K1", "w", "-", "-", "0", "1"]).unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut searcher = Searcher::new();
+        searcher.set_stop_signal(stop.clone());
+
+        let mut depth = 0;
+        let cm = thread::scope(|scope| {
+            scope.spawn(|| {
+                thread::sleep(Duration::from_millis(200));
+                stop.store(true, Ordering::Relaxed);
+            });
+            searcher.find_best_move(board, &infinite, |info| depth = info.depth).unwrap()
+        });
+        assert_eq!(cm.to_long_algebraic_notation(), "a1a8");
+        assert!(depth > 1 && depth < MAX_PLY as u32 - 1);
+    }
+
+    #[test]
+    #[ignore = "needs the Syzygy tables in tests/fixtures/syzygy"]
+    fn test_tablebases_filter_root_and_cut_off_after_captures() {
+        let mut searcher = Searcher::new();
+        searcher.set_syzygy_path(syzygy::fixtures_path().as_str());
+        let limits = SearchLimits { depth: Some(3), ..Default::default() };
+
+        // the move played keeps the fastest win. Nothing below the root resets the fifty move
+        // clock, so the tables aren't trusted there and the root is the only probe.
+        let board = Board::from_fen(["4k3/8/8/8/8/8/8/3QK3", "w", "-", "-", "0", "1"]).unwrap();
+        let fastest = searcher.tablebases().root_moves(&board).unwrap();
+        let mut last: Option<SearchInfo> = None;
+        let cm = searcher.find_best_move(board, &limits, |info| last = Some(info.clone())).unwrap();
+        assert!(fastest.contains(&cm));
+        assert_eq!(last.unwrap().tbhits, 1);
+
+        // too many pieces for the tables at the root, but taking the rook gets down to KQ v KP,
+        // and straight after the capture the tables answer for it
+        searcher.clear();
+        let board = Board::from_fen(["4k3/7p/8/8/8/8/3r4/3QK3", "w", "-", "-", "0", "1"]).unwrap();
+        let mut last: Option<SearchInfo> = None;
+        let cm = searcher.find_best_move(board, &limits, |info| last = Some(info.clone())).unwrap();
+        assert!(["e1d2", "d1d2"].contains(&cm.to_long_algebraic_notation().as_str()));
+        assert!(last.unwrap().tbhits > 0);
+    }
+
+    #[test]
+    fn test_score_to_uci() {
+        assert_eq!(score_to_uci(35), "cp 35");
+        assert_eq!(score_to_uci(MATE_SCORE - 3), "mate 2");
+        assert_eq!(score_to_uci(-MATE_SCORE + 2), "mate -1");
+    }
+}
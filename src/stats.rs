@@ -0,0 +1,227 @@
+// Match statistics. Everything is from the first engine's point of view, and the error bars
+// come from the spread of the individual game results (treating them as independent), which is
+// what most testing tools report too.
+//
+// SPRT works on game pairs instead (the same opening played once with each colour), counting
+// how many pairs ended on each of 0, 1/2, 1, 3/2 and 2 points. Pairs are much closer to
+// independent than single games, since an unbalanced opening cancels itself out over the pair.
+// The log likelihood ratio is the usual normal approximation over the pair scores, with Elo in
+// the logistic sense, so it lines up with the Elo difference above.
+
+// two sided 95%
+const Z_95: f64 = 1.959964;
+
+// pseudo-count of pairs added to each pentanomial outcome, so a run where every pair has ended
+// the same way (all 2-0, say) still has some spread and can reach a decision
+const PRIOR: f64 = 0.25;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Score {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+impl Score {
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    /// Points per game, between 0 and 1
+    pub fn ratio(&self) -> f64 {
+        if self.games() == 0 {
+            return 0.5
+        }
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games() as f64
+    }
+
+    /// Standard deviation of a single game's result
+    fn deviation(&self) -> f64 {
+        let n = self.games() as f64;
+        if n == 0.0 {
+            return 0.0
+        }
+        let s = self.ratio();
+        let variance = (self.wins as f64 * (1.0 - s).powi(2)
+            + self.draws as f64 * (0.5 - s).powi(2)
+            + self.losses as f64 * s.powi(2)) / n;
+        variance.sqrt()
+    }
+
+    pub fn elo(&self) -> f64 {
+        elo_from_ratio(self.ratio())
+    }
+
+    /// Half the width of the 95% confidence interval around `elo`. Infinite until there's been
+    /// at least one of two different results.
+    pub fn elo_error(&self) -> f64 {
+        let n = self.games() as f64;
+        if self.deviation() == 0.0 {
+            return f64::INFINITY
+        }
+        let margin = Z_95 * self.deviation() / n.sqrt();
+        let (low, high) = (elo_from_ratio(self.ratio() - margin), elo_from_ratio(self.ratio() + margin));
+        (high - low) / 2.0
+    }
+
+    /// Draws as a fraction of the games played
+    pub fn draw_ratio(&self) -> f64 {
+        if self.games() == 0 {
+            return 0.0
+        }
+        self.draws as f64 / self.games() as f64
+    }
+}
+
+/// The Elo difference that gives this expected score
+pub fn elo_from_ratio(ratio: f64) -> f64 {
+    if ratio <= 0.0 {
+        f64::NEG_INFINITY
+    } else if ratio >= 1.0 {
+        f64::INFINITY
+    } else {
+        -400.0 * (1.0 / ratio - 1.0).log10()
+    }
+}
+
+/// The expected score against someone this many Elo weaker
+pub fn ratio_from_elo(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+/// Game pairs by how many points the first engine took from them, in halves: counts[0] is pairs
+/// lost twice, counts[4] pairs won twice.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Pentanomial {
+    pub counts: [u32; 5],
+}
+
+impl Pentanomial {
+    /// Adds a pair the first engine scored `points` from (0, 0.5, 1, 1.5 or 2)
+    pub fn add(&mut self, points: f64) {
+        let halves = (points * 2.0).round().clamp(0.0, 4.0) as usize;
+        self.counts[halves] += 1;
+    }
+
+    pub fn pairs(&self) -> u32 {
+        self.counts.iter().sum()
+    }
+
+    /// Mean and variance of the per game score over the pairs, with `PRIOR` of a pair added to
+    /// each count so that the variance is never 0
+    fn mean_and_variance(&self) -> (f64, f64) {
+        let counts = self.counts.map(|count| count as f64 + PRIOR);
+        let n = counts.iter().sum::<f64>();
+        let mean = counts.iter().enumerate()
+            .map(|(halves, count)| halves as f64 / 4.0 * count)
+            .sum::<f64>() / n;
+        let variance = counts.iter().enumerate()
+            .map(|(halves, count)| (halves as f64 / 4.0 - mean).powi(2) * count)
+            .sum::<f64>() / n;
+        (mean, variance)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hypothesis {
+    H0,
+    H1,
+}
+
+/// Tests H0, that the first engine is `elo0` stronger, against H1, that it's `elo1` stronger,
+/// with false positive rate `alpha` and false negative rate `beta`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sprt {
+    pub elo0: f64,
+    pub elo1: f64,
+    pub alpha: f64,
+    pub beta: f64,
+}
+
+impl Default for Sprt {
+    fn default() -> Self {
+        Sprt { elo0: 0.0, elo1: 5.0, alpha: 0.05, beta: 0.05 }
+    }
+}
+
+impl Sprt {
+    /// The LLR below which H0 is accepted, and above which H1 is
+    pub fn bounds(&self) -> (f64, f64) {
+        ((self.beta / (1.0 - self.alpha)).ln(), ((1.0 - self.beta) / self.alpha).ln())
+    }
+
+    pub fn llr(&self, pairs: &Pentanomial) -> f64 {
+        let (mean, variance) = pairs.mean_and_variance();
+        let (s0, s1) = (ratio_from_elo(self.elo0), ratio_from_elo(self.elo1));
+        pairs.pairs() as f64 * (s1 - s0) * (2.0 * mean - s0 - s1) / (2.0 * variance)
+    }
+
+    /// Which hypothesis has been accepted, if either has yet
+    pub fn decision(&self, pairs: &Pentanomial) -> Option<Hypothesis> {
+        let llr = self.llr(pairs);
+        let (lower, upper) = self.bounds();
+        if llr >= upper {
+            Some(Hypothesis::H1)
+        } else if llr <= lower {
+            Some(Hypothesis::H0)
+        } else {
+            None
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::stats::{elo_from_ratio, ratio_from_elo, Hypothesis, Pentanomial, Score, Sprt};
+
+    #[test]
+    fn test_elo() {
+        assert_eq!(Score::default().elo(), 0.0);
+        assert!((elo_from_ratio(0.75) - 190.85).abs() < 0.01);
+        assert!((ratio_from_elo(elo_from_ratio(0.3)) - 0.3).abs() < 1e-9);
+
+        let score = Score { wins: 40, draws: 20, losses: 40 };
+        assert_eq!(score.elo(), 0.0);
+        // 100 games at 0.5 with a per game deviation of sqrt(0.2) is about +/- 61 Elo
+        assert!((score.elo_error() - 61.0).abs() < 1.0, "{}", score.elo_error());
+        assert!(Score { wins: 3, draws: 0, losses: 0 }.elo_error().is_infinite());
+    }
+
+    #[test]
+    fn test_sprt() {
+        let sprt = Sprt::default();
+        let (lower, upper) = sprt.bounds();
+        assert!((lower + 2.944).abs() < 0.001 && (upper - 2.944).abs() < 0.001);
+
+        let mut pairs = Pentanomial::default();
+        for points in [0.0, 0.5, 1.0, 1.0, 1.5, 2.0] {
+            pairs.add(points);
+        }
+        assert_eq!(pairs.counts, [1, 1, 2, 1, 1]);
+        assert_eq!(sprt.decision(&pairs), None);
+
+        // a clear edge gets accepted, a clear deficit rejected
+        let winning = Pentanomial { counts: [20, 80, 200, 160, 60] };
+        assert_eq!(sprt.decision(&winning), Some(Hypothesis::H1));
+        let losing = Pentanomial { counts: [60, 160, 200, 80, 20] };
+        assert_eq!(sprt.decision(&losing), Some(Hypothesis::H0));
+    }
+
+    #[test]
+    fn test_sprt_one_sided() {
+        let sprt = Sprt::default();
+        assert_eq!(sprt.llr(&Pentanomial::default()), 0.0);
+
+        // nothing but 2-0 pairs has no spread of its own, but should still end up accepting H1
+        let mut pairs = Pentanomial::default();
+        let mut decision = None;
+        while decision.is_none() && pairs.pairs() < 100 {
+            pairs.add(2.0);
+            decision = sprt.decision(&pairs);
+        }
+        assert_eq!(decision, Some(Hypothesis::H1), "{:?}", pairs);
+        let losing = Pentanomial { counts: [100, 0, 0, 0, 0] };
+        assert_eq!(sprt.decision(&losing), Some(Hypothesis::H0));
+    }
+}
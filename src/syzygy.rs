@@ -0,0 +1,1324 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+
+use crate::board::{Board, BoardSlot, ChessMove, UNIT_MASK, TEAM_MASK, TEAM_WHITE, PAWN, KNIGHT, BISHOP, ROOK, QUEEN, KING};
+
+// Syzygy tablebase probing. A table covers one material configuration ("KQvKR") and comes as two
+// files: .rtbw holds win/draw/loss for every position, .rtbz the distance to the next capture or
+// pawn move (zeroing move) needed to keep a win within the fifty move rule. Both are compressed
+// with recursive pairing plus canonical huffman codes, and a position is turned into an index
+// into that compressed data by a fairly involved encoding that strips out mirrored positions.
+//
+// Tables are found by looking through the directories given to `Tablebases::new` for .rtbw
+// files, and only read into memory the first time a position needs them.
+//
+// Squares are rank * 8 + column, the same as everywhere else, and pieces use the tablebase codes:
+// pawn to king are 1 to 6 for white and 9 to 14 for black.
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xe8, 0x23, 0x5d];
+const DTZ_MAGIC: [u8; 4] = [0xd7, 0x66, 0x0c, 0xa5];
+
+// per table flags, all but the last only mean anything for DTZ tables
+const FLAG_STM: u8 = 1;
+const FLAG_MAPPED: u8 = 2;
+const FLAG_WIN_PLIES: u8 = 4;
+const FLAG_LOSS_PLIES: u8 = 8;
+const FLAG_WIDE: u8 = 16;
+const FLAG_SINGLE_VALUE: u8 = 128;
+
+const MAX_PIECES: usize = 7;
+
+// root moves are ranked so that faster wins and slower losses come first
+const MAX_DTZ: i32 = 1 << 18;
+
+// piece letters as they appear in table names, strongest first
+const PIECE_LETTERS: [(char, u8); 6] = [('K', 6), ('Q', 5), ('R', 4), ('B', 3), ('N', 2), ('P', 1)];
+
+/// Win, draw or loss for the side to move. Cursed wins and blessed losses would be wins and
+/// losses if it weren't for the fifty move rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Wdl {
+    Loss,
+    BlessedLoss,
+    Draw,
+    CursedWin,
+    Win,
+}
+
+impl Wdl {
+    fn from_value(value: i32) -> Wdl {
+        match value {
+            v if v <= -2 => Wdl::Loss,
+            -1 => Wdl::BlessedLoss,
+            0 => Wdl::Draw,
+            1 => Wdl::CursedWin,
+            _ => Wdl::Win,
+        }
+    }
+}
+
+//
+// Encoding tables, the same for every tablebase file
+//
+
+struct Encoding {
+    binomial: [[u64; 64]; MAX_PIECES],   // binomial[k][n] is n choose k
+    map_b1h1h7: [u64; 64],               // squares below the a1-h8 diagonal to 0..27
+    map_a1d1d4: [usize; 64],             // the a1-d1-d4 triangle to 0..9, diagonal last
+    map_kk: [[u64; 64]; 10],             // the 462 ways to place two kings, first in the triangle
+    map_pawns: [usize; 64],              // a2-h7 to 0..47, higher for pawns nearer the edge
+    lead_pawn_idx: [[u64; 64]; MAX_PIECES],
+    lead_pawns_size: [[u64; 4]; MAX_PIECES],
+}
+
+static ENCODING: OnceLock<Encoding> = OnceLock::new();
+
+fn off_a1h8(sq: usize) -> i32 {
+    (sq / 8) as i32 - (sq % 8) as i32
+}
+
+fn square_distance(a: usize, b: usize) -> usize {
+    let dx = ((a % 8) as i32 - (b % 8) as i32).unsigned_abs();
+    let dy = ((a / 8) as i32 - (b / 8) as i32).unsigned_abs();
+    dx.max(dy) as usize
+}
+
+fn encoding() -> &'static Encoding {
+    ENCODING.get_or_init(|| {
+        let mut e = Encoding {
+            binomial: [[0; 64]; MAX_PIECES],
+            map_b1h1h7: [0; 64],
+            map_a1d1d4: [0; 64],
+            map_kk: [[0; 64]; 10],
+            map_pawns: [0; 64],
+            lead_pawn_idx: [[0; 64]; MAX_PIECES],
+            lead_pawns_size: [[0; 4]; MAX_PIECES],
+        };
+
+        let mut code = 0;
+        for sq in 0..64 {
+            if off_a1h8(sq) < 0 {
+                e.map_b1h1h7[sq] = code;
+                code += 1;
+            }
+        }
+
+        let mut diagonal = Vec::new();
+        let mut code = 0;
+        for sq in 0..=27 {
+            if off_a1h8(sq) < 0 && sq % 8 <= 3 {
+                e.map_a1d1d4[sq] = code;
+                code += 1;
+            } else if off_a1h8(sq) == 0 && sq % 8 <= 3 {
+                diagonal.push(sq);
+            }
+        }
+        for sq in diagonal {
+            e.map_a1d1d4[sq] = code;
+            code += 1;
+        }
+
+        // if the first king is on the a1-d4 diagonal the other one can't be above the a1-h8 one,
+        // and positions with both on the diagonal go last
+        let mut both_on_diagonal = Vec::new();
+        let mut code = 0;
+        for idx in 0..10 {
+            for s1 in 0..=27 {
+                if e.map_a1d1d4[s1] != idx || (idx == 0 && s1 != 1) {
+                    continue
+                }
+                for s2 in 0..64 {
+                    let touching = square_distance(s1, s2) <= 1;
+                    if touching || (off_a1h8(s1) == 0 && off_a1h8(s2) > 0) {
+                        continue
+                    } else if off_a1h8(s1) == 0 && off_a1h8(s2) == 0 {
+                        both_on_diagonal.push((idx, s2));
+                    } else {
+                        e.map_kk[idx][s2] = code;
+                        code += 1;
+                    }
+                }
+            }
+        }
+        for (idx, s2) in both_on_diagonal {
+            e.map_kk[idx][s2] = code;
+            code += 1;
+        }
+
+        e.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..MAX_PIECES.min(n + 1) {
+                e.binomial[k][n] = if k > 0 { e.binomial[k - 1][n - 1] } else { 0 }
+                    + if k < n { e.binomial[k][n - 1] } else { 0 };
+            }
+        }
+
+        // a lead pawn on `sq` means no other pawn of its kind is nearer the edge or further back,
+        // which leaves 47 squares for a2 and two fewer for every step in
+        let mut available = 47;
+        for lead_count in 1..=5 {
+            for file in 0..4 {
+                let mut idx = 0;
+                for rank in 1..7 {
+                    let sq = rank * 8 + file;
+                    if lead_count == 1 {
+                        e.map_pawns[sq] = available;
+                        available = available.saturating_sub(1);
+                        e.map_pawns[sq ^ 7] = available;
+                        available = available.saturating_sub(1);
+                    }
+                    e.lead_pawn_idx[lead_count][sq] = idx;
+                    idx += e.binomial[lead_count - 1][e.map_pawns[sq]];
+                }
+                e.lead_pawns_size[lead_count][file] = idx;
+            }
+        }
+
+        e
+    })
+}
+
+//
+// Compressed tables
+//
+
+/// Everything needed to decompress one side / one lead pawn file of a table. Offsets are into
+/// the table's file data.
+#[derive(Debug, Clone, Default)]
+struct PairsData {
+    flags: u8,
+    min_sym_len: u8,
+    block_size: usize,
+    span: u64,
+    lowest_sym: usize,
+    btree: usize,
+    block_length: usize,
+    block_length_size: usize,
+    sparse_index: usize,
+    sparse_index_size: usize,
+    data: usize,
+    num_blocks: usize,
+    base64: Vec<u64>,
+    symlen: Vec<u8>,
+    pieces: [u8; MAX_PIECES],
+    group_idx: [u64; MAX_PIECES + 1],
+    group_len: [usize; MAX_PIECES + 1],
+    map_idx: [u16; 4],
+}
+
+fn read_u16(data: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_le_bytes([*data.get(pos)?, *data.get(pos + 1)?]))
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Option<u32> {
+    let bytes = data.get(pos..pos + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+// the huffman decoder reads ahead, so past the end of the file reads as zeros rather than
+// losing whatever is left of the last block
+fn read_u32_be(data: &[u8], pos: usize) -> u32 {
+    let byte = |i: usize| data.get(pos + i).copied().unwrap_or(0);
+    u32::from_be_bytes([byte(0), byte(1), byte(2), byte(3)])
+}
+
+impl PairsData {
+    fn left(&self, data: &[u8], sym: usize) -> usize {
+        let lr = &data[self.btree + 3 * sym..];
+        (((lr[1] & 0xf) as usize) << 8) | lr[0] as usize
+    }
+
+    fn right(&self, data: &[u8], sym: usize) -> usize {
+        let lr = &data[self.btree + 3 * sym..];
+        ((lr[2] as usize) << 4) | (lr[1] >> 4) as usize
+    }
+
+    fn block_len(&self, data: &[u8], block: usize) -> i64 {
+        read_u16(data, self.block_length + 2 * block).unwrap_or(0) as i64
+    }
+
+    /// Reads the sizes and huffman code for this table, returning where the next one starts
+    fn set_sizes(&mut self, data: &[u8], mut pos: usize) -> Option<usize> {
+        self.flags = *data.get(pos)?;
+        pos += 1;
+
+        if self.flags & FLAG_SINGLE_VALUE != 0 {
+            // every position has the same value, stored in place of the symbol length
+            self.span = 1;
+            self.min_sym_len = *data.get(pos)?;
+            return Some(pos + 1)
+        }
+
+        let groups = self.group_len.iter().position(|&len| len == 0)?;
+        let table_size = self.group_idx[groups];
+
+        self.block_size = 1 << *data.get(pos)?;
+        self.span = 1 << *data.get(pos + 1)?;
+        self.sparse_index_size = table_size.div_ceil(self.span) as usize;
+        let padding = *data.get(pos + 2)? as usize;
+        self.num_blocks = read_u32(data, pos + 3)? as usize;
+        self.block_length_size = self.num_blocks + padding;
+        let max_sym_len = *data.get(pos + 7)?;
+        self.min_sym_len = *data.get(pos + 8)?;
+        pos += 9;
+
+        if max_sym_len < self.min_sym_len || self.min_sym_len == 0 {
+            return None
+        }
+
+        // canonical huffman: longer codes have lower values, so the lowest code of each length
+        // padded out to 64 bits gives a table that can be walked down to find a code's length
+        self.lowest_sym = pos;
+        let lengths = (max_sym_len - self.min_sym_len) as usize + 1;
+        self.base64 = vec![0; lengths];
+        for i in (0..lengths - 1).rev() {
+            let lowest = read_u16(data, pos + 2 * i)? as u64;
+            let next_lowest = read_u16(data, pos + 2 * (i + 1))? as u64;
+            self.base64[i] = (self.base64[i + 1] + lowest).wrapping_sub(next_lowest) / 2;
+        }
+        for (i, base) in self.base64.iter_mut().enumerate() {
+            *base = base.checked_shl(64 - i as u32 - self.min_sym_len as u32).unwrap_or(0);
+        }
+        pos += 2 * lengths;
+
+        let symbols = read_u16(data, pos)? as usize;
+        pos += 2;
+        self.btree = pos;
+        data.get(pos..pos + 3 * symbols)?;
+
+        self.symlen = vec![0; symbols];
+        let mut visited = vec![false; symbols];
+        for sym in 0..symbols {
+            if !visited[sym] {
+                self.set_symlen(data, sym, &mut visited);
+            }
+        }
+
+        Some(pos + 3 * symbols + (symbols & 1))
+    }
+
+    /// How many values (less one) a symbol expands to, worked out from the pairing tree
+    fn set_symlen(&mut self, data: &[u8], sym: usize, visited: &mut [bool]) {
+        visited[sym] = true;
+        let right = self.right(data, sym);
+        if right == 0xfff {
+            self.symlen[sym] = 0;
+            return
+        }
+
+        let left = self.left(data, sym);
+        if left >= self.symlen.len() || right >= self.symlen.len() {
+            return
+        }
+        if !visited[left] {
+            self.set_symlen(data, left, visited);
+        }
+        if !visited[right] {
+            self.set_symlen(data, right, visited);
+        }
+        self.symlen[sym] = self.symlen[left].wrapping_add(self.symlen[right]).wrapping_add(1);
+    }
+
+    /// Splits the pieces into the groups they're encoded by, and works out where each group's
+    /// part of the index starts.
+    fn set_groups(&mut self, entry: &Entry, order: [u8; 2], file: usize) {
+        let e = encoding();
+
+        let mut n = 0;
+        let mut first_len: i32 = if entry.has_pawns { 0 } else if entry.has_unique_pieces { 3 } else { 2 };
+        self.group_len[0] = 1;
+        for i in 1..entry.piece_count {
+            first_len -= 1;
+            if first_len > 0 || self.pieces[i] == self.pieces[i - 1] {
+                self.group_len[n] += 1;
+            } else {
+                n += 1;
+                self.group_len[n] = 1;
+            }
+        }
+        n += 1;
+        self.group_len[n] = 0;
+
+        // the groups are encoded in the order the table says, not the order they come in
+        let both_pawns = entry.has_pawns && entry.pawn_count[1] > 0;
+        let mut next = if both_pawns { 2 } else { 1 };
+        let mut free = 64 - self.group_len[0] - if both_pawns { self.group_len[1] } else { 0 };
+        let mut idx: u64 = 1;
+
+        let mut k = 0;
+        while next < n || k == order[0] as usize || k == order[1] as usize {
+            if k == order[0] as usize {
+                self.group_idx[0] = idx;
+                idx *= if entry.has_pawns {
+                    e.lead_pawns_size[self.group_len[0]][file]
+                } else if entry.has_unique_pieces {
+                    31332
+                } else {
+                    462
+                };
+            } else if k == order[1] as usize {
+                self.group_idx[1] = idx;
+                idx *= e.binomial[self.group_len[1]][48 - self.group_len[0]];
+            } else {
+                self.group_idx[next] = idx;
+                idx *= e.binomial[self.group_len[next]][free];
+                free -= self.group_len[next];
+                next += 1;
+            }
+            k += 1;
+        }
+        self.group_idx[n] = idx;
+    }
+
+    /// Finds the value stored at `idx`
+    fn decompress(&self, data: &[u8], idx: u64) -> i32 {
+        if self.flags & FLAG_SINGLE_VALUE != 0 {
+            return self.min_sym_len as i32
+        }
+
+        // the sparse index points at a known position every `span` values; walk from the nearest
+        // one through the block lengths to the block that holds idx
+        let k = (idx / self.span) as usize;
+        let entry = self.sparse_index + 6 * k;
+        let mut block = read_u32(data, entry).unwrap_or(0) as usize;
+        let mut offset = read_u16(data, entry + 4).unwrap_or(0) as i64;
+        offset += (idx % self.span) as i64 - (self.span / 2) as i64;
+
+        while offset < 0 && block > 0 {
+            block -= 1;
+            offset += self.block_len(data, block) + 1;
+        }
+        while offset > self.block_len(data, block) && block + 1 < self.block_length_size {
+            offset -= self.block_len(data, block) + 1;
+            block += 1;
+        }
+
+        // then read symbols until we get to the one that covers our offset in the block
+        let mut ptr = self.data + block * self.block_size;
+        let mut buf64 = ((read_u32_be(data, ptr) as u64) << 32) | read_u32_be(data, ptr + 4) as u64;
+        ptr += 8;
+        let mut buf_size = 64;
+        let min = self.min_sym_len as u32;
+
+        let mut sym;
+        loop {
+            let mut len = 0;
+            while len + 1 < self.base64.len() && buf64 < self.base64[len] {
+                len += 1;
+            }
+
+            sym = ((buf64 - self.base64[len]) >> (64 - len as u32 - min)) as usize;
+            sym += read_u16(data, self.lowest_sym + 2 * len).unwrap_or(0) as usize;
+            if sym >= self.symlen.len() {
+                return 0
+            }
+
+            if offset < self.symlen[sym] as i64 + 1 {
+                break
+            }
+            offset -= self.symlen[sym] as i64 + 1;
+
+            let consumed = len as u32 + min;
+            buf64 <<= consumed;
+            buf_size -= consumed as i32;
+            if buf_size <= 32 {
+                buf_size += 32;
+                buf64 |= (read_u32_be(data, ptr) as u64) << (64 - buf_size);
+                ptr += 4;
+            }
+        }
+
+        // the symbol stands for a run of values; go down the pairing tree to the one we want
+        while self.symlen[sym] != 0 {
+            let left = self.left(data, sym);
+            if offset < self.symlen[left] as i64 + 1 {
+                sym = left;
+            } else {
+                offset -= self.symlen[left] as i64 + 1;
+                sym = self.right(data, sym);
+            }
+        }
+
+        self.left(data, sym) as i32
+    }
+}
+
+/// One loaded .rtbw or .rtbz file
+struct Table {
+    data: Vec<u8>,
+    pairs: Vec<Vec<PairsData>>,  // [side][lead pawn file]
+    map: usize,                  // DTZ value maps
+}
+
+impl Table {
+    fn load(entry: &Entry, path: &Path, dtz: bool) -> Option<Table> {
+        let data = fs::read(path).ok()?;
+        let magic = if dtz { DTZ_MAGIC } else { WDL_MAGIC };
+        if data.len() < 5 || data[..4] != magic {
+            return None
+        }
+
+        let flags = data[4];
+        if (flags & 2 != 0) != entry.has_pawns {
+            return None
+        }
+        let mut pos = 5;
+
+        let sides = if !dtz && entry.key != entry.key2 { 2 } else { 1 };
+        let files = if entry.has_pawns { 4 } else { 1 };
+        let both_pawns = entry.has_pawns && entry.pawn_count[1] > 0;
+        let mut pairs = vec![vec![PairsData::default(); files]; sides];
+
+        for file in 0..files {
+            let first = *data.get(pos)?;
+            let second = if both_pawns { *data.get(pos + 1)? } else { 0xff };
+            let order = [[first & 0xf, second & 0xf], [first >> 4, second >> 4]];
+            pos += 1 + both_pawns as usize;
+
+            for k in 0..entry.piece_count {
+                let byte = *data.get(pos)?;
+                for (side, side_pairs) in pairs.iter_mut().enumerate() {
+                    side_pairs[file].pieces[k] = if side == 1 { byte >> 4 } else { byte & 0xf };
+                }
+                pos += 1;
+            }
+
+            for (side, side_pairs) in pairs.iter_mut().enumerate() {
+                side_pairs[file].set_groups(entry, order[side], file);
+            }
+        }
+        pos += pos & 1;
+
+        for file in 0..files {
+            for side_pairs in pairs.iter_mut() {
+                pos = side_pairs[file].set_sizes(&data, pos)?;
+            }
+        }
+
+        let map = pos;
+        if dtz {
+            for d in pairs[0].iter_mut() {
+                if d.flags & FLAG_MAPPED == 0 {
+                    continue
+                }
+                for i in 0..4 {
+                    if d.flags & FLAG_WIDE != 0 {
+                        pos += pos & 1;
+                        d.map_idx[i] = ((pos - map) / 2 + 1) as u16;
+                        pos += 2 * read_u16(&data, pos)? as usize + 2;
+                    } else {
+                        d.map_idx[i] = (pos - map + 1) as u16;
+                        pos += *data.get(pos)? as usize + 1;
+                    }
+                }
+            }
+            pos += pos & 1;
+        }
+
+        for file in 0..files {
+            for side_pairs in pairs.iter_mut() {
+                side_pairs[file].sparse_index = pos;
+                pos += side_pairs[file].sparse_index_size * 6;
+            }
+        }
+        for file in 0..files {
+            for side_pairs in pairs.iter_mut() {
+                side_pairs[file].block_length = pos;
+                pos += side_pairs[file].block_length_size * 2;
+            }
+        }
+        for file in 0..files {
+            for side_pairs in pairs.iter_mut() {
+                pos = (pos + 0x3f) & !0x3f;
+                side_pairs[file].data = pos;
+                pos += side_pairs[file].num_blocks * side_pairs[file].block_size;
+            }
+        }
+
+        if pos > data.len() {
+            return None
+        }
+        Some(Table { data, pairs, map })
+    }
+
+    fn get(&self, side: usize, file: usize) -> &PairsData {
+        &self.pairs[side % self.pairs.len()][file]
+    }
+
+    /// DTZ tables only hold one side to move (or both, for symmetric pawnless ones)
+    fn has_side(&self, entry: &Entry, side: usize, file: usize) -> bool {
+        (self.get(side, file).flags & FLAG_STM) as usize == side
+            || (entry.key == entry.key2 && !entry.has_pawns)
+    }
+
+    /// Turns the stored DTZ value into plies
+    fn dtz_value(&self, file: usize, value: i32, wdl: i32) -> i32 {
+        let d = self.get(0, file);
+        let mut value = value;
+
+        if d.flags & FLAG_MAPPED != 0 {
+            let slot = [1, 3, 0, 2, 0][(wdl + 2) as usize];
+            let index = d.map_idx[slot] as usize + value as usize;
+            value = if d.flags & FLAG_WIDE != 0 {
+                read_u16(&self.data, self.map + 2 * index).unwrap_or(0) as i32
+            } else {
+                self.data.get(self.map + index).copied().unwrap_or(0) as i32
+            };
+        }
+
+        let in_moves = (wdl == 2 && d.flags & FLAG_WIN_PLIES == 0)
+            || (wdl == -2 && d.flags & FLAG_LOSS_PLIES == 0)
+            || wdl == 1
+            || wdl == -1;
+        if in_moves {
+            value *= 2;
+        }
+        value + 1
+    }
+}
+
+/// One material configuration, with its files and what the encoding needs to know about it
+struct Entry {
+    key: String,   // as in the file name, the side the table calls white first
+    key2: String,  // the same with the colours swapped
+    piece_count: usize,
+    has_pawns: bool,
+    has_unique_pieces: bool,
+    pawn_count: [usize; 2],  // leading colour first
+    wdl_path: PathBuf,
+    dtz_path: Option<PathBuf>,
+    wdl: OnceLock<Option<Table>>,
+    dtz: OnceLock<Option<Table>>,
+}
+
+impl Entry {
+    fn new(name: &str, wdl_path: PathBuf, dtz_path: Option<PathBuf>) -> Option<Entry> {
+        let (white, black) = name.split_once('v')?;
+        let valid = |side: &str| side.starts_with('K') && side.chars().all(|c| "KQRBNP".contains(c));
+        if !valid(white) || !valid(black) || white.len() + black.len() > MAX_PIECES {
+            return None
+        }
+
+        let count = |side: &str, letter: char| side.chars().filter(|&c| c == letter).count();
+        let has_unique_pieces = "QRBNP".chars().any(|letter| count(white, letter) == 1 || count(black, letter) == 1);
+
+        // the side with fewer pawns leads, as that compresses better
+        let (white_pawns, black_pawns) = (count(white, 'P'), count(black, 'P'));
+        let white_leads = black_pawns == 0 || (white_pawns > 0 && black_pawns >= white_pawns);
+        let pawn_count = if white_leads { [white_pawns, black_pawns] } else { [black_pawns, white_pawns] };
+
+        Some(Entry {
+            key: format!("{}v{}", white, black),
+            key2: format!("{}v{}", black, white),
+            piece_count: white.len() + black.len(),
+            has_pawns: white_pawns + black_pawns > 0,
+            has_unique_pieces,
+            pawn_count,
+            wdl_path,
+            dtz_path,
+            wdl: OnceLock::new(),
+            dtz: OnceLock::new(),
+        })
+    }
+
+    fn table(&self, dtz: bool) -> Option<&Table> {
+        if dtz {
+            self.dtz.get_or_init(|| self.dtz_path.as_ref().and_then(|path| Table::load(self, path, true))).as_ref()
+        } else {
+            self.wdl.get_or_init(|| Table::load(self, &self.wdl_path, false)).as_ref()
+        }
+    }
+}
+
+/// The pieces of a position the way the tables want them
+struct Position {
+    pieces: Vec<(usize, u8)>,  // square and tablebase piece code, by square
+    white_to_move: bool,
+    key: String,
+}
+
+fn piece_code(slot: BoardSlot) -> u8 {
+    let code = match slot & UNIT_MASK {
+        PAWN => 1,
+        KNIGHT => 2,
+        BISHOP => 3,
+        ROOK => 4,
+        QUEEN => 5,
+        KING => 6,
+        _ => 0,
+    };
+    if code == 0 || slot & TEAM_MASK == TEAM_WHITE { code } else { code | 8 }
+}
+
+fn piece_count(board: &Board) -> usize {
+    (0..64).filter(|sq| board.piece_at(((sq % 8) as i8, (sq / 8) as i8)) & UNIT_MASK != 0).count()
+}
+
+impl Position {
+    fn new(board: &Board) -> Position {
+        let mut pieces = Vec::with_capacity(MAX_PIECES);
+        for sq in 0..64 {
+            let code = piece_code(board.piece_at(((sq % 8) as i8, (sq / 8) as i8)));
+            if code != 0 {
+                pieces.push((sq, code));
+            }
+        }
+
+        let mut key = String::with_capacity(MAX_PIECES + 1);
+        for colour in [0, 8] {
+            if colour == 8 {
+                key.push('v');
+            }
+            for &(letter, code) in PIECE_LETTERS.iter() {
+                for _ in pieces.iter().filter(|&&(_, p)| p == code | colour) {
+                    key.push(letter);
+                }
+            }
+        }
+
+        Position { pieces, white_to_move: board.is_whites_move(), key }
+    }
+}
+
+/// Legal moves paired with the position after each
+fn legal_children(board: &Board) -> Vec<(ChessMove, Board)> {
+    board.get_pseudo_legal_moves()
+        .into_iter()
+        .map(|cm| {
+            let child = board.apply_move(&cm);
+            (cm, child)
+        })
+        .filter(|(_, child)| !child.moved_into_check())
+        .collect()
+}
+
+// the tables don't know about en passant, so those captures have to be searched like any other
+fn is_capture(board: &Board, cm: &ChessMove) -> bool {
+    let en_passant = board.piece_at(cm.from_ind()) & UNIT_MASK == PAWN && cm.from_ind().0 != cm.to_ind().0;
+    board.move_is_take(cm) || en_passant
+}
+
+fn is_zeroing(board: &Board, cm: &ChessMove) -> bool {
+    is_capture(board, cm) || board.piece_at(cm.from_ind()) & UNIT_MASK == PAWN
+}
+
+fn dtz_before_zeroing(wdl: i32) -> i32 {
+    match wdl {
+        2 => 1,
+        1 => 101,
+        -1 => -101,
+        -2 => -1,
+        _ => 0,
+    }
+}
+
+/// Every table found under the syzygy path
+#[derive(Default)]
+pub struct Tablebases {
+    entries: HashMap<String, Arc<Entry>>,
+    max_pieces: usize,
+}
+
+impl fmt::Debug for Tablebases {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Tablebases {{ tables: {}, max_pieces: {} }}", self.len(), self.max_pieces)
+    }
+}
+
+impl Tablebases {
+    /// Looks for tables in every directory of `paths`, separated the same way as in PATH. An
+    /// empty path or "<empty>" means no tables.
+    pub fn new(paths: &str) -> Tablebases {
+        let mut tablebases = Tablebases::default();
+        if paths.is_empty() || paths == "<empty>" {
+            return tablebases
+        }
+
+        for dir in env::split_paths(paths) {
+            let files = match fs::read_dir(&dir) {
+                Ok(files) => files,
+                Err(_) => continue,
+            };
+            for file in files.flatten() {
+                let path = file.path();
+                if path.extension().and_then(|ext| ext.to_str()) != Some("rtbw") {
+                    continue
+                }
+                let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+                    Some(name) => name.to_string(),
+                    None => continue,
+                };
+                let dtz_path = Some(path.with_extension("rtbz")).filter(|p| p.exists());
+                if let Some(entry) = Entry::new(&name, path, dtz_path) {
+                    tablebases.max_pieces = tablebases.max_pieces.max(entry.piece_count);
+                    let entry = Arc::new(entry);
+                    tablebases.entries.insert(entry.key2.clone(), entry.clone());
+                    tablebases.entries.insert(entry.key.clone(), entry);
+                }
+            }
+        }
+        tablebases
+    }
+
+    /// Number of material configurations found
+    pub fn len(&self) -> usize {
+        let mut keys: Vec<&String> = self.entries.values().map(|entry| &entry.key).collect();
+        keys.sort();
+        keys.dedup();
+        keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Most pieces (kings included) in any table found
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    /// True if the position is small enough that the tables might have it
+    pub fn can_probe(&self, board: &Board) -> bool {
+        self.max_pieces > 0 && !board.can_castle() && piece_count(board) <= self.max_pieces
+    }
+
+    /// Win/draw/loss for the side to move, if the tables cover the position
+    pub fn probe_wdl(&self, board: &Board) -> Option<Wdl> {
+        self.search(board, false).map(|(wdl, _)| Wdl::from_value(wdl))
+    }
+
+    /// Plies until the next zeroing move if the side to move plays it out perfectly. Positive if
+    /// the side to move wins, negative if it loses, 0 for draws.
+    pub fn probe_dtz(&self, board: &Board) -> Option<i32> {
+        let (wdl, zeroing_best) = self.search(board, true)?;
+        if wdl == 0 {
+            return Some(0)
+        }
+        if zeroing_best {
+            return Some(dtz_before_zeroing(wdl))
+        }
+
+        if let Some(dtz) = self.probe_table(&Position::new(board), true, wdl)? {
+            let cursed = if wdl.abs() == 1 { 100 } else { 0 };
+            return Some((dtz + cursed) * wdl.signum())
+        }
+
+        // the table only has the other side to move, so look one move further on
+        let mut min_dtz = i32::MAX;
+        for (cm, child) in legal_children(board) {
+            let zeroing = is_zeroing(board, &cm);
+            let mut dtz = if zeroing {
+                -dtz_before_zeroing(self.search(&child, false)?.0)
+            } else {
+                -self.probe_dtz(&child)?
+            };
+
+            if dtz == 1 && child.in_check() && legal_children(&child).is_empty() {
+                min_dtz = 1;
+            }
+            if !zeroing {
+                dtz += dtz.signum();
+            }
+            if dtz < min_dtz && dtz.signum() == wdl.signum() {
+                min_dtz = dtz;
+            }
+        }
+
+        Some(if min_dtz == i32::MAX { -1 } else { min_dtz })
+    }
+
+    /// The root moves that keep the best result the tables can promise: the quickest wins, every
+    /// draw, or the slowest losses.
+    pub fn root_moves(&self, board: &Board) -> Option<Vec<ChessMove>> {
+        let mut ranked = Vec::new();
+        for (cm, child) in legal_children(board) {
+            let mut dtz = if is_zeroing(board, &cm) {
+                dtz_before_zeroing(-self.search(&child, false)?.0)
+            } else {
+                let dtz = -self.probe_dtz(&child)?;
+                dtz + dtz.signum()
+            };
+
+            // make sure a mate counts as the fastest possible win
+            if child.in_check() && dtz == 2 && legal_children(&child).is_empty() {
+                dtz = 1;
+            }
+
+            let rank = if dtz > 0 {
+                MAX_DTZ - dtz
+            } else if dtz < 0 {
+                -MAX_DTZ - dtz
+            } else {
+                0
+            };
+            ranked.push((cm, rank));
+        }
+
+        let best = ranked.iter().map(|&(_, rank)| rank).max()?;
+        Some(ranked.into_iter().filter(|&(_, rank)| rank == best).map(|(cm, _)| cm).collect())
+    }
+
+    /// WDL with captures (and pawn moves, if `zeroing` is set) searched first, since the tables
+    /// don't hold the right value when the best move is one of those. Also says whether the best
+    /// move was a zeroing one.
+    fn search(&self, board: &Board, zeroing: bool) -> Option<(i32, bool)> {
+        let children = legal_children(board);
+        let total = children.len();
+        let mut searched = 0;
+        let mut best = -2;
+
+        for (cm, child) in children.iter() {
+            if !is_capture(board, cm) && (!zeroing || board.piece_at(cm.from_ind()) & UNIT_MASK != PAWN) {
+                continue
+            }
+            searched += 1;
+
+            let value = -self.search(child, false)?.0;
+            if value > best {
+                best = value;
+                if value >= 2 {
+                    return Some((value, true))
+                }
+            }
+        }
+
+        let no_more_moves = searched > 0 && searched == total;
+        let value = if no_more_moves {
+            best
+        } else {
+            self.probe_table(&Position::new(board), false, 0)??
+        };
+
+        if best >= value {
+            Some((best, best > 0 || no_more_moves))
+        } else {
+            Some((value, false))
+        }
+    }
+
+    /// Looks the position up in its table. `None` if it can't be probed at all, `Some(None)` if
+    /// this is a DTZ table that only holds the other side to move.
+    fn probe_table(&self, pos: &Position, dtz: bool, wdl: i32) -> Option<Option<i32>> {
+        if pos.pieces.len() == 2 {
+            return Some(Some(0))
+        }
+
+        let entry = self.entries.get(&pos.key)?;
+        let table = entry.table(dtz)?;
+        let e = encoding();
+
+        // symmetric tables only hold white to move, and tables only hold the stronger side as
+        // white, so otherwise the colours get swapped and the board flipped over
+        let flip = (entry.key == entry.key2 && !pos.white_to_move) || pos.key != entry.key;
+        let flip_colour = if flip { 8 } else { 0 };
+        let flip_squares = if flip { 56 } else { 0 };
+        let side = (flip as usize) ^ (!pos.white_to_move as usize);
+
+        let mut squares = [0usize; MAX_PIECES];
+        let mut pieces = [0u8; MAX_PIECES];
+        let mut size = 0;
+        let mut lead_count = 0;
+        let mut file = 0;
+
+        // tables with pawns are split by the file of the lead pawn, the one nearest the edge
+        // and furthest back of the pawns of the colour listed first
+        if entry.has_pawns {
+            let lead = table.get(0, 0).pieces[0] ^ flip_colour;
+            for &(sq, piece) in pos.pieces.iter() {
+                if piece == lead {
+                    squares[size] = sq ^ flip_squares;
+                    size += 1;
+                }
+            }
+            lead_count = size;
+
+            let max = (0..lead_count).max_by_key(|&i| e.map_pawns[squares[i]]).unwrap_or(0);
+            squares.swap(0, max);
+            file = (squares[0] % 8).min(7 - squares[0] % 8);
+        }
+
+        if dtz && !table.has_side(entry, side, file) {
+            return Some(None)
+        }
+
+        for &(sq, piece) in pos.pieces.iter() {
+            if entry.has_pawns && piece == table.get(0, 0).pieces[0] ^ flip_colour {
+                continue
+            }
+            squares[size] = sq ^ flip_squares;
+            pieces[size] = piece ^ flip_colour;
+            size += 1;
+        }
+
+        // put the pieces in the order the table lists them
+        let d = table.get(side, file);
+        for i in lead_count..size.saturating_sub(1) {
+            for j in (i + 1)..size {
+                if d.pieces[i] == pieces[j] {
+                    pieces.swap(i, j);
+                    squares.swap(i, j);
+                    break
+                }
+            }
+        }
+
+        let idx = index(entry, d, &mut squares, size, lead_count);
+        let value = d.decompress(&table.data, idx);
+        Some(Some(if dtz { table.dtz_value(file, value, wdl) } else { value - 2 }))
+    }
+}
+
+/// Index of the pieces on `squares` into the table `d` belongs to. The squares have to be in the
+/// order the table lists its pieces, with the lead pawns first when there are pawns.
+fn index(entry: &Entry, d: &PairsData, squares: &mut [usize; MAX_PIECES], size: usize, lead_count: usize) -> u64 {
+    let e = encoding();
+
+    // mirror so the lead piece is on the queen's side
+    if squares[0] % 8 > 3 {
+        for sq in squares[..size].iter_mut() {
+            *sq ^= 7;
+        }
+    }
+
+    let mut idx: u64;
+    if entry.has_pawns {
+        idx = e.lead_pawn_idx[lead_count][squares[0]];
+        squares[1..lead_count].sort_by_key(|&sq| e.map_pawns[sq]);
+        for (i, &sq) in squares.iter().enumerate().take(lead_count).skip(1) {
+            idx += e.binomial[i][e.map_pawns[sq]];
+        }
+    } else {
+        // without pawns the board can also be flipped top to bottom and along the diagonal,
+        // so the lead piece ends up in the a1-d1-d4 triangle
+        if squares[0] / 8 > 3 {
+            for sq in squares[..size].iter_mut() {
+                *sq ^= 56;
+            }
+        }
+        for i in 0..d.group_len[0] {
+            let off = off_a1h8(squares[i]);
+            if off == 0 {
+                continue
+            }
+            if off > 0 {
+                for sq in squares[i..size].iter_mut() {
+                    *sq = ((*sq >> 3) | (*sq << 3)) & 63;
+                }
+            }
+            break
+        }
+
+        idx = if entry.has_unique_pieces {
+            let s = &squares;
+            let adjust1 = (s[1] > s[0]) as u64;
+            let adjust2 = (s[2] > s[0]) as u64 + (s[2] > s[1]) as u64;
+            let rank = |sq: usize| (sq / 8) as u64;
+
+            if off_a1h8(s[0]) != 0 {
+                (e.map_a1d1d4[s[0]] as u64 * 63 + (s[1] as u64 - adjust1)) * 62 + s[2] as u64 - adjust2
+            } else if off_a1h8(s[1]) != 0 {
+                (6 * 63 + rank(s[0]) * 28 + e.map_b1h1h7[s[1]]) * 62 + s[2] as u64 - adjust2
+            } else if off_a1h8(s[2]) != 0 {
+                6 * 63 * 62 + 4 * 28 * 62
+                    + rank(s[0]) * 7 * 28
+                    + (rank(s[1]) - adjust1) * 28
+                    + e.map_b1h1h7[s[2]]
+            } else {
+                6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28
+                    + rank(s[0]) * 7 * 6
+                    + (rank(s[1]) - adjust1) * 6
+                    + (rank(s[2]) - adjust2)
+            }
+        } else {
+            e.map_kk[e.map_a1d1d4[squares[0]]][squares[1]]
+        };
+    }
+
+    // the rest of the groups, each as a combination of the squares left over
+    idx *= d.group_idx[0];
+    let mut start = d.group_len[0];
+    let mut remaining_pawns = entry.has_pawns && entry.pawn_count[1] > 0;
+    let mut next = 1;
+    while d.group_len[next] != 0 {
+        let len = d.group_len[next];
+        squares[start..start + len].sort_unstable();
+
+        let mut n = 0;
+        for i in 0..len {
+            let sq = squares[start + i];
+            let adjust = squares[..start].iter().filter(|&&earlier| sq > earlier).count();
+            n += e.binomial[i + 1][sq - adjust - if remaining_pawns { 8 } else { 0 }];
+        }
+
+        remaining_pawns = false;
+        idx += n * d.group_idx[next];
+        start += len;
+        next += 1;
+    }
+    idx
+}
+
+
+/// Where the tests look for tables. They aren't in the repo, so the tests that need them are
+/// ignored by default; drop the 3 and 4 piece files into tests/fixtures/syzygy and run them with
+/// `cargo test -- --ignored`.
+#[cfg(test)]
+pub(crate) fn fixtures_path() -> String {
+    let dir: std::path::PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "fixtures", "syzygy"].iter().collect();
+    let tablebases = Tablebases::new(dir.to_str().unwrap());
+    assert!(tablebases.max_pieces() >= 4, "the 3 and 4 piece Syzygy tables need to be in {}", dir.display());
+    dir.to_str().unwrap().to_string()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::path::PathBuf;
+
+    use crate::board::{Board, ChessMove};
+    use crate::endgame;
+    use crate::syzygy::{encoding, fixtures_path, index, is_capture, Entry, PairsData, Tablebases, Wdl, MAX_PIECES};
+
+    fn board(fen: &str, turn: &str) -> Board {
+        Board::from_fen([fen, turn, "-", "-", "0", "1"]).unwrap()
+    }
+
+    fn fixtures() -> Tablebases {
+        Tablebases::new(fixtures_path().as_str())
+    }
+
+    #[test]
+    fn test_encoding_tables() {
+        let e = encoding();
+        let mut codes: Vec<u64> = Vec::new();
+        for idx in 0..10 {
+            for sq in 0..64 {
+                if e.map_kk[idx][sq] != 0 {
+                    codes.push(e.map_kk[idx][sq]);
+                }
+            }
+        }
+        codes.sort_unstable();
+        codes.dedup();
+        // 462 king placements, and code 0 is left out above
+        assert_eq!(codes.len(), 461);
+        assert_eq!(*codes.last().unwrap(), 461);
+        assert_eq!(e.binomial[2][5], 10);
+        assert_eq!(e.map_pawns[8], 47);
+    }
+
+    // the eight ways a pawnless board can be turned over, and the one a board with pawns can
+    fn pawnless_symmetries(sq: usize) -> [usize; 8] {
+        let flip = |sq: usize| ((sq >> 3) | (sq << 3)) & 63;
+        [sq, sq ^ 7, sq ^ 56, sq ^ 63, flip(sq), flip(sq) ^ 7, flip(sq) ^ 56, flip(sq) ^ 63]
+    }
+
+    fn groups(name: &str, pieces: &[u8], order: [u8; 2], file: usize) -> (Entry, PairsData) {
+        let entry = Entry::new(name, PathBuf::new(), None).unwrap();
+        let mut d = PairsData::default();
+        d.pieces[..pieces.len()].copy_from_slice(pieces);
+        d.set_groups(&entry, order, file);
+        (entry, d)
+    }
+
+    #[test]
+    fn test_index_of_three_unique_pieces() {
+        // three different pieces can go on 64 * 63 * 62 placements, which the eight symmetries of
+        // the board cut down to 31332, the size of a KQvK table. Every placement has to land in
+        // there, the same as all its mirror images, and between them they have to fill it.
+        let (entry, d) = groups("KQvK", &[6, 5, 14], [0, 0xf], 0);
+        assert_eq!(d.group_idx[1], 31332);
+
+        let mut seen = HashSet::new();
+        for a in 0..64 {
+            for b in (0..64).filter(|&b| b != a) {
+                for c in (0..64).filter(|&c| c != a && c != b) {
+                    let (a8, b8, c8) = (pawnless_symmetries(a), pawnless_symmetries(b), pawnless_symmetries(c));
+                    let mut squares = [0; MAX_PIECES];
+                    squares[..3].copy_from_slice(&[a, b, c]);
+                    let idx = index(&entry, &d, &mut squares, 3, 0);
+                    assert!(idx < 31332);
+                    for i in 1..8 {
+                        let mut squares = [0; MAX_PIECES];
+                        squares[..3].copy_from_slice(&[a8[i], b8[i], c8[i]]);
+                        assert_eq!(index(&entry, &d, &mut squares, 3, 0), idx);
+                    }
+                    seen.insert(idx);
+                }
+            }
+        }
+        assert_eq!(seen.len(), 31332);
+    }
+
+    #[test]
+    fn test_index_with_a_pawn() {
+        // a pawn only mirrors left to right, so with it on files a to d there are 6 * 63 * 62
+        // ways to place it and two kings for each file, all of them different
+        let (entry, d) = groups("KPvK", &[1, 6, 14], [0, 0xf], 0);
+        assert_eq!(d.group_idx[d.group_len.iter().position(|&len| len == 0).unwrap()], 6 * 63 * 62);
+
+        for file in 0..4 {
+            let mut seen = HashSet::new();
+            for rank in 1..7 {
+                let pawn = rank * 8 + file;
+                for k in (0..64).filter(|&k| k != pawn) {
+                    for q in (0..64).filter(|&q| q != pawn && q != k) {
+                        let mut squares = [0; MAX_PIECES];
+                        squares[..3].copy_from_slice(&[pawn, k, q]);
+                        let idx = index(&entry, &d, &mut squares, 3, 1);
+                        let mut mirrored = [0; MAX_PIECES];
+                        mirrored[..3].copy_from_slice(&[pawn ^ 7, k ^ 7, q ^ 7]);
+                        assert_eq!(index(&entry, &d, &mut mirrored, 3, 1), idx);
+                        seen.insert(idx);
+                    }
+                }
+            }
+            assert_eq!(seen.len(), 6 * 63 * 62);
+            assert!(seen.iter().all(|&idx| idx < 6 * 63 * 62));
+        }
+    }
+
+    #[test]
+    fn test_decompress() {
+        // a small table put together by hand: four symbols with the canonical codes 000, 001, 01
+        // and 1. The first three stand for the values 1, 4 and 2, and the last for the pair
+        // "2 then 4".
+        let codes: [(u32, u32); 4] = [(0b000, 3), (0b001, 3), (0b01, 2), (0b1, 1)];
+        let expand = |sym: usize| -> Vec<i32> {
+            match sym {
+                0 => vec![1],
+                1 => vec![4],
+                2 => vec![2],
+                _ => vec![2, 4],
+            }
+        };
+        let stream = [3, 0, 1, 3, 2, 2, 0, 3, 1, 1, 3, 3, 0, 2, 1, 0, 3, 2, 1, 3, 0, 0, 2, 3, 1];
+
+        // two byte blocks, each holding as many whole symbols as fit
+        let mut blocks: Vec<(u16, Vec<i32>)> = vec![(0, Vec::new())];
+        let mut bits = 0;
+        for &sym in stream.iter() {
+            let (code, len) = codes[sym];
+            if bits + len > 16 {
+                blocks.push((0, Vec::new()));
+                bits = 0;
+            }
+            let block = blocks.last_mut().unwrap();
+            block.0 |= (code << (16 - bits - len)) as u16;
+            block.1.extend(expand(sym));
+            bits += len;
+        }
+        let values: Vec<i32> = blocks.iter().flat_map(|(_, values)| values.clone()).collect();
+
+        let mut data = vec![0, 1, 2, 0];  // flags, block size 2, span 4, no padding
+        data.extend_from_slice(&(blocks.len() as u32).to_le_bytes());
+        data.extend_from_slice(&[3, 1]);  // longest and shortest code
+        for lowest in [3u16, 2, 0] {
+            data.extend_from_slice(&lowest.to_le_bytes());
+        }
+        data.extend_from_slice(&4u16.to_le_bytes());
+        for (left, right) in [(1, 0xfff), (4, 0xfff), (2, 0xfff), (2, 1)] {
+            data.extend_from_slice(&[(left & 0xff) as u8, ((left >> 8) | ((right & 0xf) << 4)) as u8, (right >> 4) as u8]);
+        }
+
+        let mut d = PairsData::default();
+        d.group_len[0] = 1;
+        d.group_idx[1] = values.len() as u64;
+        let end = d.set_sizes(&data, 0).unwrap();
+        assert_eq!(end, data.len());
+        assert_eq!(d.symlen, vec![0, 0, 0, 1]);
+
+        // every fourth value gets a sparse index entry pointing at the block and offset of the
+        // value in the middle of its span
+        let starts: Vec<usize> = blocks.iter().scan(0, |start, (_, values)| {
+            let this = *start;
+            *start += values.len();
+            Some(this)
+        }).collect();
+        d.sparse_index = data.len();
+        for k in 0..d.sparse_index_size {
+            let middle = k * 4 + 2;
+            let block = starts.iter().rposition(|&start| start <= middle).unwrap();
+            data.extend_from_slice(&(block as u32).to_le_bytes());
+            data.extend_from_slice(&((middle - starts[block]) as u16).to_le_bytes());
+        }
+        d.block_length = data.len();
+        for (_, values) in blocks.iter() {
+            data.extend_from_slice(&(values.len() as u16 - 1).to_le_bytes());
+        }
+        d.data = data.len();
+        for (code, _) in blocks.iter() {
+            data.extend_from_slice(&code.to_be_bytes());
+        }
+
+        for (idx, &value) in values.iter().enumerate() {
+            assert_eq!(d.decompress(&data, idx as u64), value, "value {}", idx);
+        }
+    }
+
+    #[test]
+    fn test_en_passant_is_a_capture() {
+        let board = Board::from_fen(["4k3/8/8/3pP3/8/8/8/4K3", "w", "-", "d6", "0", "1"]).unwrap();
+        assert!(is_capture(&board, &ChessMove::from_long_algebraic_notation("e5d6")));
+        assert!(!is_capture(&board, &ChessMove::from_long_algebraic_notation("e5e6")));
+    }
+
+    #[test]
+    fn test_bare_kings_need_no_tables() {
+        let tablebases = Tablebases::new("");
+        assert!(!tablebases.can_probe(&board("4k3/8/8/8/8/8/8/4K3", "w")));
+        assert_eq!(Tablebases::default().probe_wdl(&board("4k3/8/8/8/8/8/8/4K3", "w")), Some(Wdl::Draw));
+    }
+
+    #[test]
+    #[ignore = "needs the Syzygy tables in tests/fixtures/syzygy"]
+    fn test_probe_fixtures() {
+        let tablebases = fixtures();
+
+        assert_eq!(tablebases.probe_wdl(&board("4k3/8/8/8/8/8/8/3QK3", "w")), Some(Wdl::Win));
+        assert_eq!(tablebases.probe_wdl(&board("4k3/8/8/8/8/8/8/3QK3", "b")), Some(Wdl::Loss));
+        assert_eq!(tablebases.probe_wdl(&board("3qk3/8/8/8/8/8/8/4K3", "w")), Some(Wdl::Loss));
+        assert_eq!(tablebases.probe_wdl(&board("4k3/8/8/8/8/8/8/2B1K3", "w")), Some(Wdl::Draw));
+
+        let dtz = tablebases.probe_dtz(&board("4k3/8/8/8/8/8/8/3QK3", "w")).unwrap();
+        assert!(dtz > 0);
+        let moves = tablebases.root_moves(&board("4k3/8/8/8/8/8/8/3QK3", "w")).unwrap();
+        assert!(!moves.is_empty());
+    }
+
+    #[test]
+    #[ignore = "needs the Syzygy tables in tests/fixtures/syzygy"]
+    fn test_kpvk_fixture_agrees_with_bitbase() {
+        let tablebases = fixtures();
+
+        let name = |sq: usize| format!("{}{}", (b'a' + (sq % 8) as u8) as char, sq / 8 + 1);
+        for (strong_king, pawn, weak_king) in [(20, 12, 60), (44, 36, 60), (9, 8, 56), (27, 19, 43), (0, 48, 58)] {
+            let fen_square = |sq: usize| (7 - sq / 8, sq % 8);
+            let mut rows = [['1'; 8]; 8];
+            let (r, c) = fen_square(strong_king);
+            rows[r][c] = 'K';
+            let (r, c) = fen_square(pawn);
+            rows[r][c] = 'P';
+            let (r, c) = fen_square(weak_king);
+            rows[r][c] = 'k';
+            let fen: Vec<String> = rows.iter().map(|row| row.iter().collect()).collect();
+            let fen = fen.join("/");
+
+            for (turn, strong_to_move) in [("w", true), ("b", false)] {
+                let wdl = tablebases.probe_wdl(&board(&fen, turn)).unwrap();
+                let win = endgame::kpk_win(true, strong_to_move, strong_king, pawn, weak_king);
+                let expected = match (win, strong_to_move) {
+                    (true, true) => Wdl::Win,
+                    (true, false) => Wdl::Loss,
+                    _ => Wdl::Draw,
+                };
+                assert_eq!(wdl, expected, "{} {} ({})", fen, turn, name(pawn));
+            }
+        }
+    }
+}
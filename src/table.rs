@@ -0,0 +1,215 @@
+use std::fmt;
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::board::{BoardSlot, ChessMove, EMPTY, UNIT_MASK, KNIGHT, BISHOP, ROOK, QUEEN};
+
+pub const DEFAULT_TABLE_SIZE: usize = 1 << 20;
+
+/// What a stored score means relative to the window it was searched with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bound {
+    Exact,
+    Lower,  // failed high, real score is at least this
+    Upper,  // failed low, real score is at most this
+}
+
+#[derive(Debug, Clone)]
+pub struct TableEntry {
+    pub depth: u32,
+    pub score: i32,
+    pub bound: Bound,
+    pub best_move: Option<ChessMove>,
+}
+
+// Each slot is two words: the packed entry, and the board key xor'd with it. A reader that catches
+// a slot halfway through being written by another thread gets a key that doesn't match and just
+// treats it as a miss, so no locking is needed.
+#[derive(Default)]
+struct Slot {
+    check: AtomicU64,
+    data: AtomicU64,
+}
+
+/// Transposition table shared between all search threads.
+pub struct TranspositionTable {
+    slots: Vec<Slot>,
+}
+
+impl fmt::Debug for TranspositionTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TranspositionTable {{ slots: {} }}", self.slots.len())
+    }
+}
+
+impl Default for TranspositionTable {
+    fn default() -> Self {
+        Self::new(DEFAULT_TABLE_SIZE)
+    }
+}
+
+impl TranspositionTable {
+    pub fn new(size: usize) -> TranspositionTable {
+        TranspositionTable {
+            slots: (0..size.max(1)).map(|_| Slot::default()).collect()
+        }
+    }
+
+    pub fn with_megabytes(megabytes: usize) -> TranspositionTable {
+        TranspositionTable::new(megabytes * 1024 * 1024 / mem::size_of::<Slot>())
+    }
+
+    pub fn clear(&self) {
+        for slot in self.slots.iter() {
+            slot.check.store(0, Ordering::Relaxed);
+            slot.data.store(0, Ordering::Relaxed);
+        }
+    }
+
+    pub fn probe(&self, key: u64) -> Option<TableEntry> {
+        let slot = &self.slots[key as usize % self.slots.len()];
+        let data = slot.data.load(Ordering::Relaxed);
+        let check = slot.check.load(Ordering::Relaxed);
+
+        if data == 0 || check ^ data != key {
+            None
+        } else {
+            Some(unpack(data))
+        }
+    }
+
+    /// How full the table is in permille, guessed from the first thousand slots
+    pub fn hashfull(&self) -> u32 {
+        let sample = &self.slots[..self.slots.len().min(1000)];
+        let used = sample.iter().filter(|slot| slot.data.load(Ordering::Relaxed) != 0).count();
+        (used * 1000 / sample.len()) as u32
+    }
+
+    pub fn store(&self, key: u64, entry: TableEntry) {
+        let slot = &self.slots[key as usize % self.slots.len()];
+
+        // prefer keeping deeper results for the same position, but always replace other positions
+        let old_data = slot.data.load(Ordering::Relaxed);
+        let old_check = slot.check.load(Ordering::Relaxed);
+        if old_check ^ old_data == key && unpack(old_data).depth > entry.depth {
+            return
+        }
+
+        let data = pack(&entry);
+        slot.data.store(data, Ordering::Relaxed);
+        slot.check.store(key ^ data, Ordering::Relaxed);
+    }
+}
+
+//
+// Packing. Bits are laid out as
+//   0..6 from square, 6..12 to square, 12..15 promotion, 16..32 score, 32..40 depth, 40..42 bound
+//
+
+fn pack(entry: &TableEntry) -> u64 {
+    let cm = entry.best_move.as_ref().map(pack_move).unwrap_or(0);
+    let bound = match entry.bound {
+        Bound::Exact => 1,
+        Bound::Lower => 2,
+        Bound::Upper => 3,
+    };
+
+    cm
+        | ((entry.score as i16 as u16 as u64) << 16)
+        | ((entry.depth.min(255) as u64) << 32)
+        | (bound << 40)
+}
+
+fn unpack(data: u64) -> TableEntry {
+    let bound = match (data >> 40) & 3 {
+        2 => Bound::Lower,
+        3 => Bound::Upper,
+        _ => Bound::Exact,
+    };
+
+    TableEntry {
+        depth: ((data >> 32) & 255) as u32,
+        score: ((data >> 16) & 0xffff) as u16 as i16 as i32,
+        bound,
+        best_move: unpack_move(data & 0x7fff),
+    }
+}
+
+fn pack_move(cm: &ChessMove) -> u64 {
+    let square = |(x, y): (i8, i8)| (y as u64) * 8 + x as u64;
+    let promotion = match cm.promotion() & UNIT_MASK {
+        KNIGHT => 1,
+        BISHOP => 2,
+        ROOK => 3,
+        QUEEN => 4,
+        _ => 0,
+    };
+    square(cm.from_ind()) | (square(cm.to_ind()) << 6) | (promotion << 12)
+}
+
+fn unpack_move(bits: u64) -> Option<ChessMove> {
+    if bits == 0 {
+        // a1a1 isn't a move, so all zeros means there wasn't one
+        return None
+    }
+
+    let ind = |sq: u64| ((sq % 8) as i8, (sq / 8) as i8);
+    let promotion: BoardSlot = match (bits >> 12) & 7 {
+        1 => KNIGHT,
+        2 => BISHOP,
+        3 => ROOK,
+        4 => QUEEN,
+        _ => EMPTY,
+    };
+    Some(ChessMove::new(ind(bits & 63), ind((bits >> 6) & 63), promotion))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use crate::board::{ChessMove, BISHOP, KNIGHT, QUEEN, ROOK};
+    use crate::search::MATE_SCORE;
+    use crate::table::{pack, Bound, TableEntry, TranspositionTable};
+
+    #[test]
+    fn test_entries_come_back_as_stored() {
+        let table = TranspositionTable::new(64);
+        let promote = |piece| ChessMove::new((4, 6), (4, 7), piece);
+        let entries = [
+            (0x1234_5678_9abc_def0, 7, 35, Bound::Exact, Some(ChessMove::from_long_algebraic_notation("g1f3"))),
+            (0x0fed_cba9_8765_4321, 1, -412, Bound::Lower, None),
+            (0x1111_2222_3333_4444, 255, MATE_SCORE - 3, Bound::Upper, Some(promote(QUEEN))),
+            (0x5555_6666_7777_8888, 12, -MATE_SCORE + 8, Bound::Exact, Some(promote(KNIGHT))),
+            (0x9999_aaaa_bbbb_cccc, 3, 0, Bound::Lower, Some(promote(ROOK))),
+            (0xdddd_eeee_ffff_0000, 4, -1, Bound::Upper, Some(promote(BISHOP))),
+            (0x0123_4567_89ab_cdef, 9, 5, Bound::Exact, Some(ChessMove::from_long_algebraic_notation("h8a1"))),
+        ];
+
+        for (key, depth, score, bound, best_move) in entries.iter().cloned() {
+            table.clear();
+            table.store(key, TableEntry { depth, score, bound, best_move: best_move.clone() });
+            let entry = table.probe(key).unwrap();
+            assert_eq!(entry.depth, depth);
+            assert_eq!(entry.score, score);
+            assert_eq!(entry.bound, bound);
+            assert_eq!(entry.best_move, best_move);
+
+            // only the key it was stored under finds it
+            assert!(table.probe(key ^ (1 << 40)).is_none());
+        }
+    }
+
+    #[test]
+    fn test_torn_write_is_a_miss() {
+        let table = TranspositionTable::new(1);
+        let key = 0xdead_beef_0bad_f00d;
+        table.store(key, TableEntry { depth: 5, score: 120, bound: Bound::Exact, best_move: None });
+        assert!(table.probe(key).is_some());
+
+        // another thread has written its data but not its check yet
+        let other = TableEntry { depth: 9, score: -30, bound: Bound::Lower, best_move: None };
+        table.slots[0].data.store(pack(&other), Ordering::Relaxed);
+        assert!(table.probe(key).is_none());
+    }
+}
@@ -0,0 +1,592 @@
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::board::{Board, ChessMove, GameResult};
+use crate::pgn::{self, PgnGame};
+use crate::stats::Score;
+use crate::uci;
+
+// Engine against engine. Each engine is a separate process we talk UCI to over pipes, so this
+// works just as well for two builds of this crate as for anything else that speaks UCI.
+//
+// Games are adjudicated here rather than trusting either engine: mate, stalemate, the fifty move
+// rule, threefold repetition and bare kings all end the game by the rules, and optionally a game
+// can be resigned when both engines agree it's lost, or drawn once it gets too long. An illegal
+// move, running out of time, or the engine falling over all lose the game.
+
+// how long an engine gets to answer anything that isn't a move
+const READY_TIMEOUT: Duration = Duration::from_secs(10);
+// and how long past its flag we wait for a move before giving up on it
+const MOVE_GRACE: Duration = Duration::from_secs(1);
+
+// mate scores are turned into something bigger than any resign threshold
+const MATE_CP: i32 = 100_000;
+
+/// A UCI engine running in its own process
+pub struct Engine {
+    name: String,
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<String>,
+}
+
+/// What an engine said when asked for a move
+#[derive(Debug, Clone)]
+pub struct Reply {
+    pub best: String,
+    pub score: Option<i32>,  // centipawns from the engine's side, from the last info it sent
+    pub elapsed: Duration,
+}
+
+impl Engine {
+    /// Starts `command` (a program and its arguments, split on whitespace) and gets it ready to
+    /// play, setting `options` on the way.
+    pub fn start(command: &str, options: &[(String, String)]) -> std::result::Result<Engine, String> {
+        let mut parts = command.split_whitespace();
+        let program = parts.next().ok_or_else(|| String::from("empty engine command"))?;
+        let mut child = Command::new(program)
+            .args(parts)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|err| format!("can't start {}: {}", command, err))?;
+
+        let stdin = child.stdin.take().ok_or_else(|| String::from("no stdin"))?;
+        let stdout = child.stdout.take().ok_or_else(|| String::from("no stdout"))?;
+
+        // reading happens on its own thread so that waiting on the engine can time out
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                match line {
+                    Ok(line) => if sender.send(line).is_err() { break },
+                    Err(_) => break,
+                }
+            }
+        });
+
+        let mut engine = Engine { name: command.to_string(), child, stdin, lines };
+        engine.send("uci")?;
+        let deadline = Instant::now() + READY_TIMEOUT;
+        loop {
+            let line = engine.recv(deadline)?;
+            if let Some(name) = line.strip_prefix("id name ") {
+                engine.name = name.trim().to_string();
+            } else if line.trim() == "uciok" {
+                break
+            }
+        }
+        for (name, value) in options {
+            engine.send(format!("setoption name {} value {}", name, value).as_str())?;
+        }
+        engine.wait_ready()?;
+        Ok(engine)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn send(&mut self, line: &str) -> std::result::Result<(), String> {
+        writeln!(self.stdin, "{}", line)
+            .and_then(|_| self.stdin.flush())
+            .map_err(|err| format!("{} stopped listening: {}", self.name, err))
+    }
+
+    fn recv(&mut self, deadline: Instant) -> std::result::Result<String, String> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        self.lines.recv_timeout(timeout).map_err(|err| match err {
+            RecvTimeoutError::Timeout => format!("{} didn't answer in time", self.name),
+            RecvTimeoutError::Disconnected => format!("{} exited", self.name),
+        })
+    }
+
+    /// isready and wait for readyok. Anything else the engine says in the meantime is dropped,
+    /// which also clears out a late bestmove from a game it lost on time.
+    fn wait_ready(&mut self) -> std::result::Result<(), String> {
+        self.send("isready")?;
+        let deadline = Instant::now() + READY_TIMEOUT;
+        while self.recv(deadline)?.trim() != "readyok" {}
+        Ok(())
+    }
+
+    pub fn new_game(&mut self) -> std::result::Result<(), String> {
+        self.send("ucinewgame")?;
+        self.wait_ready()
+    }
+
+    /// Sends the position and the clocks, and waits for a move until `time_left` (plus a little
+    /// grace) runs out.
+    pub fn go(&mut self, position: &str, go: &str, time_left: Duration) -> std::result::Result<Reply, String> {
+        self.send(position)?;
+        self.send(go)?;
+
+        let start = Instant::now();
+        let deadline = start + time_left + MOVE_GRACE;
+        let mut score = None;
+        loop {
+            let line = self.recv(deadline)?;
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            match tokens.first() {
+                Some(&"info") => {
+                    if let Some(i) = tokens.iter().position(|token| *token == "score") {
+                        let value = tokens.get(i + 2).and_then(|n| n.parse::<i32>().ok());
+                        score = match (tokens.get(i + 1), value) {
+                            (Some(&"cp"), Some(cp)) => Some(cp),
+                            (Some(&"mate"), Some(moves)) => Some(if moves > 0 { MATE_CP } else { -MATE_CP }),
+                            _ => score,
+                        };
+                    }
+                },
+                Some(&"bestmove") => {
+                    let best = tokens.get(1).map(|best| best.to_string()).unwrap_or_default();
+                    return Ok(Reply { best, score, elapsed: start.elapsed() })
+                },
+                _ => {},
+            }
+        }
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        // give it a moment to leave by itself before pulling the plug
+        let deadline = Instant::now() + Duration::from_millis(500);
+        while Instant::now() < deadline {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// The engine in `slot` told about a new game, or a fresh one started from `command` if it's
+/// not there or has stopped answering.
+pub fn ready_engine<'a>(slot: &'a mut Option<Engine>, command: &str, options: &[(String, String)]) -> std::result::Result<&'a mut Engine, String> {
+    let ready = slot.as_mut().map(|engine| engine.new_game().is_ok()).unwrap_or(false);
+    if !ready {
+        *slot = None;
+        let mut started = Engine::start(command, options)?;
+        started.new_game()?;
+        *slot = Some(started);
+    }
+    slot.as_mut().ok_or_else(|| String::from("no engine"))
+}
+
+/// Base time plus an increment per move, written "40+0.4" in seconds like most tools do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeControl {
+    pub base: Duration,
+    pub increment: Duration,
+}
+
+impl TimeControl {
+    pub fn parse(text: &str) -> std::result::Result<TimeControl, String> {
+        let seconds = |s: &str| s.parse::<f64>().ok()
+            .filter(|s| s.is_finite() && *s >= 0.0)
+            .map(Duration::from_secs_f64)
+            .ok_or_else(|| format!("bad time control: {}", text));
+        match text.split_once('+') {
+            Some((base, increment)) => Ok(TimeControl { base: seconds(base)?, increment: seconds(increment)? }),
+            None => Ok(TimeControl { base: seconds(text)?, increment: Duration::ZERO }),
+        }
+    }
+}
+
+/// When to end a game early
+#[derive(Debug, Clone, Default)]
+pub struct Adjudication {
+    /// resign for a side once its own score has been at or under minus this, with the other side
+    /// agreeing, for `resign_moves` of its moves in a row
+    pub resign_score: Option<i32>,
+    pub resign_moves: usize,
+    /// call it a draw after this many plies
+    pub max_plies: Option<usize>,
+}
+
+/// A starting position, and moves to play from it before the engines take over
+#[derive(Debug, Clone)]
+pub struct Opening {
+    pub fen: String,
+    pub moves: Vec<ChessMove>,
+}
+
+impl Default for Opening {
+    fn default() -> Self {
+        Opening { fen: String::from(uci::START_FEN), moves: Vec::new() }
+    }
+}
+
+/// Reads an opening suite: PGN games (cut to `plies` moves) from a .pgn file, or else one FEN or
+/// EPD position per line.
+pub fn load_openings(path: &str, plies: usize) -> std::result::Result<Vec<Opening>, String> {
+    let text = fs::read_to_string(path).map_err(|err| format!("can't read {}: {}", path, err))?;
+    let mut openings = Vec::new();
+
+    if path.ends_with(".pgn") {
+        for game in pgn::parse_games(&text) {
+            let start = game.start()?;
+            let mut moves = game.chess_moves()?;
+            moves.truncate(plies);
+            openings.push(Opening { fen: start.to_fen(), moves });
+        }
+    } else {
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+            // epd has operations after the first four fields, which aren't any use here
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let fen = if fields.len() >= 6 && fields[4].parse::<u32>().is_ok() && fields[5].parse::<u32>().is_ok() {
+                fields[..6].join(" ")
+            } else if fields.len() >= 4 {
+                fields[..4].join(" ")
+            } else {
+                return Err(format!("bad opening: {}", line))
+            };
+            let board = uci::position_board(Some(&fen), &[])?;
+            openings.push(Opening { fen: board.to_fen(), moves: Vec::new() });
+        }
+    }
+
+    if openings.is_empty() {
+        return Err(format!("no openings in {}", path))
+    }
+    Ok(openings)
+}
+
+#[derive(Debug, Clone)]
+pub struct GameRecord {
+    pub white: String,
+    pub black: String,
+    pub opening: Opening,
+    pub moves: Vec<ChessMove>,  // after the opening
+    pub result: GameResult,
+    pub reason: String,
+}
+
+impl GameRecord {
+    /// What the game was worth to one side: 1 for a win, 1/2 for a draw
+    pub fn points(&self, white: bool) -> f64 {
+        match (self.result, white) {
+            (GameResult::Draw, _) => 0.5,
+            (GameResult::WhiteWin, true) | (GameResult::BlackWin, false) => 1.0,
+            _ => 0.0,
+        }
+    }
+
+    pub fn to_pgn(&self, event: &str, round: usize) -> PgnGame {
+        let result = match self.result {
+            GameResult::WhiteWin => "1-0",
+            GameResult::BlackWin => "0-1",
+            GameResult::Draw => "1/2-1/2",
+        };
+        let mut tags: Vec<(String, String)> = [
+            ("Event", event), ("Site", "?"), ("Date", "????.??.??"), ("Round", round.to_string().as_str()),
+            ("White", self.white.as_str()), ("Black", self.black.as_str()), ("Result", result),
+        ].iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+        if self.opening.fen != uci::START_FEN {
+            tags.push((String::from("SetUp"), String::from("1")));
+            tags.push((String::from("FEN"), self.opening.fen.clone()));
+        }
+        tags.push((String::from("Termination"), self.reason.clone()));
+
+        let mut moves = Vec::with_capacity(self.opening.moves.len() + self.moves.len());
+        if let Ok(mut board) = uci::position_board(Some(&self.opening.fen), &[]) {
+            for chess_move in self.opening.moves.iter().chain(self.moves.iter()) {
+                moves.push(pgn::to_san(&board, chess_move));
+                board = board.apply_move(chess_move);
+            }
+        }
+        PgnGame { tags, moves, result: Some(self.result) }
+    }
+}
+
+/// The result if the game is over by the rules. `history` holds the ids of every position so
+/// far, including this one.
+pub fn adjudicate(board: &Board, history: &[u64]) -> Option<(GameResult, &'static str)> {
+    if let Some(result) = board.game_result() {
+        return Some(result)
+    }
+    if history.iter().filter(|id| **id == board.id()).count() >= 3 {
+        return Some((GameResult::Draw, "threefold repetition"))
+    }
+    None
+}
+
+fn loss_for(white: bool) -> GameResult {
+    if white { GameResult::BlackWin } else { GameResult::WhiteWin }
+}
+
+/// Plays one game out. Both engines should already have been told about the new game.
+pub fn play_game(white: &mut Engine, black: &mut Engine, opening: &Opening, time_control: &TimeControl, adjudication: &Adjudication) -> GameRecord {
+    let mut record = GameRecord {
+        white: white.name().to_string(),
+        black: black.name().to_string(),
+        opening: opening.clone(),
+        moves: Vec::new(),
+        result: GameResult::Draw,
+        reason: String::new(),
+    };
+
+    let mut board = match uci::position_board(Some(&opening.fen), &[]) {
+        Ok(board) => board,
+        Err(err) => {
+            record.reason = err;
+            return record
+        },
+    };
+    let mut history = vec![board.id()];
+    for chess_move in &opening.moves {
+        board = board.apply_move(chess_move);
+        history.push(board.id());
+    }
+
+    // [white, black]
+    let mut clocks = [time_control.base, time_control.base];
+    let mut scores: [Option<i32>; 2] = [None, None];
+    let mut resign_counts = [0, 0];
+    let inc = time_control.increment.as_millis();
+
+    let (result, reason) = loop {
+        if let Some((result, reason)) = adjudicate(&board, &history) {
+            break (result, reason.to_string())
+        }
+        if adjudication.max_plies.is_some_and(|plies| record.moves.len() >= plies) {
+            break (GameResult::Draw, String::from("adjudicated draw"))
+        }
+
+        let side = if board.is_whites_move() { 0 } else { 1 };
+        let engine = if side == 0 { &mut *white } else { &mut *black };
+
+        let mut position = format!("position fen {} moves", opening.fen);
+        for chess_move in opening.moves.iter().chain(record.moves.iter()) {
+            position.push(' ');
+            position.push_str(chess_move.to_long_algebraic_notation().as_str());
+        }
+        let go = format!("go wtime {} btime {} winc {} binc {}", clocks[0].as_millis(), clocks[1].as_millis(), inc, inc);
+
+        let reply = match engine.go(&position, &go, clocks[side]) {
+            Ok(reply) => reply,
+            Err(err) => break (loss_for(side == 0), err),
+        };
+        if reply.elapsed > clocks[side] {
+            break (loss_for(side == 0), format!("{} lost on time", engine.name()))
+        }
+        clocks[side] = clocks[side] - reply.elapsed + time_control.increment;
+
+        let chess_move = if uci::looks_like_move(&reply.best) {
+            let chess_move = ChessMove::from_long_algebraic_notation(&reply.best);
+            Some(uci::castle_from_king_takes_rook(&board, &chess_move))
+        } else {
+            None
+        };
+        let chess_move = match chess_move.filter(|cm| board.clone().get_legal_moves().contains(cm)) {
+            Some(chess_move) => chess_move,
+            None => break (loss_for(side == 0), format!("{} played an illegal move: {}", engine.name(), reply.best)),
+        };
+
+        // a side resigns when it's thought it was lost for long enough and the other side agrees
+        scores[side] = reply.score;
+        if let Some(threshold) = adjudication.resign_score {
+            let lost = scores[side].is_some_and(|score| score <= -threshold);
+            let won = scores[1 - side].is_some_and(|score| score >= threshold);
+            resign_counts[side] = if lost && won { resign_counts[side] + 1 } else { 0 };
+            if resign_counts[side] >= adjudication.resign_moves.max(1) {
+                break (loss_for(side == 0), format!("{} resigns", engine.name()))
+            }
+        }
+
+        board = board.apply_move(&chess_move);
+        history.push(board.id());
+        record.moves.push(chess_move);
+    };
+
+    record.result = result;
+    record.reason = reason;
+    record
+}
+
+
+/// What match and sprt both take on the command line: the two engines, how to play the games,
+/// and where to write them
+#[derive(Debug, Clone)]
+pub struct MatchSettings {
+    pub commands: [Option<String>; 2],
+    pub time_control: TimeControl,
+    pub openings_path: Option<String>,
+    pub plies: usize,
+    pub pgn_path: Option<String>,
+    pub options: Vec<(String, String)>,
+    pub adjudication: Adjudication,
+}
+
+impl Default for MatchSettings {
+    fn default() -> Self {
+        MatchSettings {
+            commands: [None, None],
+            time_control: TimeControl { base: Duration::from_secs(10), increment: Duration::from_millis(100) },
+            openings_path: None,
+            plies: 8,
+            pgn_path: None,
+            options: Vec::new(),
+            adjudication: Adjudication::default(),
+        }
+    }
+}
+
+impl MatchSettings {
+    /// Takes `arg`, and the values after it from `args`, if it's one of --engine1, --engine2,
+    /// --tc, --openings, --plies, --pgn, --option, --resign or --max-plies. False means it's
+    /// none of those, and is for the caller to make sense of.
+    pub fn parse_arg(&mut self, arg: &str, args: &mut impl Iterator<Item = String>) -> std::result::Result<bool, String> {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        let number = |text: String| text.parse::<usize>().map_err(|_| format!("bad number for {}: {}", arg, text));
+        match arg {
+            "--engine1" => self.commands[0] = Some(value()?),
+            "--engine2" => self.commands[1] = Some(value()?),
+            "--tc" => self.time_control = TimeControl::parse(&value()?)?,
+            "--openings" => self.openings_path = Some(value()?),
+            "--plies" => self.plies = number(value()?)?,
+            "--pgn" => self.pgn_path = Some(value()?),
+            "--option" => {
+                let option = value()?;
+                let (name, value) = option.split_once('=').ok_or_else(|| format!("bad option: {}", option))?;
+                self.options.push((name.to_string(), value.to_string()));
+            },
+            "--resign" => {
+                self.adjudication.resign_score = Some(number(value()?)? as i32);
+                self.adjudication.resign_moves = number(value()?)?;
+            },
+            "--max-plies" => self.adjudication.max_plies = Some(number(value()?)?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+/// Plays the games of a match one after another: every opening twice with the colours swapped,
+/// the first engine white in the first game of each pair. Engines are restarted if the last game
+/// killed them, and games are appended to the PGN file if there is one.
+pub struct Match {
+    commands: [String; 2],
+    settings: MatchSettings,
+    openings: Vec<Opening>,
+    engines: [Option<Engine>; 2],
+    event: String,
+    /// from the first engine's point of view
+    pub score: Score,
+}
+
+impl Match {
+    pub fn new(settings: MatchSettings, event: &str) -> std::result::Result<Match, String> {
+        let commands = match &settings.commands {
+            [Some(first), Some(second)] => [first.clone(), second.clone()],
+            _ => return Err(String::from("both --engine1 and --engine2 are needed")),
+        };
+        let openings = match &settings.openings_path {
+            Some(path) => load_openings(path, settings.plies)?,
+            None => vec![Opening::default()],
+        };
+        Ok(Match { commands, settings, openings, engines: [None, None], event: event.to_string(), score: Score::default() })
+    }
+
+    /// Plays the next game, and says whether the first engine had white in it
+    pub fn play_next(&mut self) -> std::result::Result<(GameRecord, bool), String> {
+        let game = self.score.games() as usize;
+        let opening = &self.openings[(game / 2) % self.openings.len()];
+        let first_white = game.is_multiple_of(2);
+
+        let [first, second] = &mut self.engines;
+        let first = ready_engine(first, &self.commands[0], &self.settings.options)?;
+        let second = ready_engine(second, &self.commands[1], &self.settings.options)?;
+        let (time_control, adjudication) = (&self.settings.time_control, &self.settings.adjudication);
+        let record = if first_white {
+            play_game(first, second, opening, time_control, adjudication)
+        } else {
+            play_game(second, first, opening, time_control, adjudication)
+        };
+
+        match (record.result, first_white) {
+            (GameResult::Draw, _) => self.score.draws += 1,
+            (GameResult::WhiteWin, true) | (GameResult::BlackWin, false) => self.score.wins += 1,
+            _ => self.score.losses += 1,
+        }
+
+        if let Some(path) = &self.settings.pgn_path {
+            let pgn = record.to_pgn(&self.event, game + 1).to_pgn_string();
+            OpenOptions::new().create(true).append(true).open(path)
+                .and_then(|mut file| file.write_all(pgn.as_bytes()))
+                .map_err(|err| format!("can't write {}: {}", path, err))?;
+        }
+        Ok((record, first_white))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::board::{ChessMove, GameResult};
+    use crate::tournament::{adjudicate, MatchSettings, TimeControl};
+    use crate::uci::position_board;
+
+    #[test]
+    fn test_adjudicate() {
+        let board = |fen: &str| position_board(Some(fen), &[]).unwrap();
+
+        let mated = board("R5k1/5ppp/8/8/8/8/8/6K1 b - - 0 1");
+        assert_eq!(adjudicate(&mated, &[mated.id()]), Some((GameResult::WhiteWin, "checkmate")));
+        let stalemate = board("7k/5Q2/8/8/8/8/8/6K1 b - - 0 1");
+        assert_eq!(adjudicate(&stalemate, &[]).map(|(result, _)| result), Some(GameResult::Draw));
+        let bare = board("7k/8/8/8/8/8/2N5/6K1 w - - 0 1");
+        assert_eq!(adjudicate(&bare, &[]), Some((GameResult::Draw, "insufficient material")));
+
+        // knights out and back twice comes round to the start a third time
+        let moves: Vec<ChessMove> = ["g1f3", "g8f6", "f3g1", "f6g8", "g1f3", "g8f6", "f3g1", "f6g8"].iter()
+            .map(|m| ChessMove::from_long_algebraic_notation(m))
+            .collect();
+        let history: Vec<u64> = (0..=moves.len()).map(|i| position_board(None, &moves[..i]).unwrap().id()).collect();
+        let start = position_board(None, &[]).unwrap();
+        assert_eq!(adjudicate(&start, &history[..5]), None);
+        assert_eq!(adjudicate(&start, &history), Some((GameResult::Draw, "threefold repetition")));
+    }
+
+    #[test]
+    fn test_time_control() {
+        assert_eq!(TimeControl::parse("10+0.1"), Ok(TimeControl { base: Duration::from_secs(10), increment: Duration::from_millis(100) }));
+        assert_eq!(TimeControl::parse("60"), Ok(TimeControl { base: Duration::from_secs(60), increment: Duration::ZERO }));
+        assert!(TimeControl::parse("fast").is_err());
+    }
+
+    #[test]
+    fn test_match_settings() {
+        let mut settings = MatchSettings::default();
+        let mut args = ["--engine1", "./new", "--tc", "5+0.05", "--option", "Hash=64", "--resign", "600", "3", "--games", "20"]
+            .iter().map(|arg| arg.to_string());
+        let mut unknown = Vec::new();
+        while let Some(arg) = args.next() {
+            if !settings.parse_arg(&arg, &mut args).unwrap() {
+                unknown.push(arg);
+            }
+        }
+        assert_eq!(unknown, ["--games", "20"]);
+        assert_eq!(settings.commands, [Some(String::from("./new")), None]);
+        assert_eq!(settings.time_control, TimeControl { base: Duration::from_secs(5), increment: Duration::from_millis(50) });
+        assert_eq!(settings.options, [(String::from("Hash"), String::from("64"))]);
+        assert_eq!((settings.adjudication.resign_score, settings.adjudication.resign_moves), (Some(600), 3));
+        assert_eq!(settings.plies, 8);
+
+        let mut args = std::iter::empty();
+        assert!(settings.parse_arg("--engine2", &mut args).is_err());
+        let mut args = std::iter::once(String::from("Hash"));
+        assert!(settings.parse_arg("--option", &mut args).is_err());
+    }
+}
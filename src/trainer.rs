@@ -0,0 +1,432 @@
+use std::convert::TryInto;
+use std::fs;
+use std::thread;
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+
+use crate::board::GameResult;
+use crate::datagen::{self, Record};
+use crate::nnue::{self, Network, INPUTS, QA, QB, SCALE};
+
+// Trains networks for the nnue module on the CPU. The network is the same one, kept in floats:
+// a clipped ReLU of 1.0 here is QA in the engine, so the weights only need multiplying out by
+// the quantisation factors on export, and a raw output of 1.0 is SCALE centipawns.
+//
+// Each position is trained towards a blend of what the search thought of it and how its game
+// ended, both from the side to move's view:
+//
+//   target = lambda * sigmoid(score / SCALE) + (1 - lambda) * result
+//
+// with the loss the squared difference between that and sigmoid(output), averaged over the
+// batch, and Adam doing the updates. Batches are cut into pieces of a fixed size, whatever the
+// thread count, and the threads work out the gradient of a piece each. The pieces are then
+// summed in order, so a run is the same however many threads it has.
+//
+// Checkpoints keep everything needed to pick up where training left off, little endian:
+//   magic            4 bytes, "CNTR"
+//   version          u32, 1
+//   hidden           u32
+//   epoch            u32, epochs done
+//   step             u64, Adam steps done
+//   weights          f32 x n, laid out like the network file: feature weights, feature biases,
+//                    output weights, output bias
+//   first moments    f32 x n
+//   second moments   f32 x n
+
+const MAGIC: &[u8; 4] = b"CNTR";
+const VERSION: u32 = 1;
+
+const BETA1: f32 = 0.9;
+const BETA2: f32 = 0.999;
+const EPSILON: f32 = 1e-8;
+
+// keeps the transformer small enough that a full board of features still fits an i16
+// accumulator once it's quantised
+const MAX_FEATURE_WEIGHT: f32 = 1.98;
+
+// samples in each piece of a batch that gets its gradient worked out on its own
+const PIECE: usize = 256;
+
+/// A training position, as the features each side sees (the side to move's first) and the
+/// score and result from the side to move's view
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    features: [Vec<u16>; 2],
+    score: f32,
+    result: f32,
+}
+
+impl Sample {
+    pub fn from_record(record: &Record) -> Sample {
+        let mut features = [Vec::with_capacity(32), Vec::with_capacity(32)];
+        for x in 0..8 {
+            for y in 0..8 {
+                if let Some([white, black]) = nnue::features(record.board.piece_at((x, y)), x, y) {
+                    features[0].push(white as u16);
+                    features[1].push(black as u16);
+                }
+            }
+        }
+        let result = match record.result {
+            GameResult::WhiteWin => 1.0,
+            GameResult::Draw => 0.5,
+            GameResult::BlackWin => 0.0,
+        };
+        if record.board.is_whites_move() {
+            Sample { features, score: record.score as f32, result }
+        } else {
+            features.swap(0, 1);
+            Sample { features, score: -record.score as f32, result: 1.0 - result }
+        }
+    }
+
+    fn target(&self, lambda: f32) -> f32 {
+        lambda * sigmoid(self.score / SCALE as f32) + (1.0 - lambda) * self.result
+    }
+}
+
+/// Every position in a datagen file, text or binary
+pub fn load(path: &str) -> std::result::Result<Vec<Sample>, String> {
+    Ok(datagen::load(path)?.iter().map(Sample::from_record).collect())
+}
+
+fn sigmoid(x: f32) -> f32 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    pub learning_rate: f32,
+    pub batch_size: usize,
+    /// How much of the target is the search score rather than the result
+    pub lambda: f32,
+    pub threads: usize,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings { learning_rate: 0.001, batch_size: 16384, lambda: 0.5, threads: 1 }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Trainer {
+    hidden: usize,
+    weights: Vec<f32>,
+    moments: [Vec<f32>; 2],
+    epoch: u32,
+    step: u64,
+}
+
+impl Trainer {
+    /// A network with random weights, scaled by how many inputs each layer has
+    pub fn new(hidden: usize, seed: u64) -> std::result::Result<Trainer, String> {
+        if hidden == 0 || !hidden.is_multiple_of(16) {
+            return Err(format!("the hidden layer has to be a multiple of 16, not {}", hidden))
+        }
+        let mut rng = StdRng::seed_from_u64(seed);
+        // about 30 of the inputs are on at a time
+        let feature_range = 1.0 / 30f32.sqrt();
+        let output_range = 1.0 / (2.0 * hidden as f32).sqrt();
+
+        let mut weights = Vec::with_capacity(size(hidden));
+        weights.extend((0..INPUTS * hidden).map(|_| rng.gen_range(-feature_range..feature_range)));
+        weights.extend((0..hidden).map(|_| rng.gen_range(0.0..feature_range)));
+        weights.extend((0..2 * hidden).map(|_| rng.gen_range(-output_range..output_range)));
+        weights.push(0.0);
+
+        let n = weights.len();
+        Ok(Trainer { hidden, weights, moments: [vec![0.0; n], vec![0.0; n]], epoch: 0, step: 0 })
+    }
+
+    pub fn hidden(&self) -> usize {
+        self.hidden
+    }
+
+    /// Epochs trained so far
+    pub fn epoch(&self) -> u32 {
+        self.epoch
+    }
+
+    pub fn load_checkpoint(path: &str) -> std::result::Result<Trainer, String> {
+        let bytes = fs::read(path).map_err(|err| format!("can't read {}: {}", path, err))?;
+        Trainer::from_bytes(&bytes).map_err(|err| format!("{}: {}", path, err))
+    }
+
+    pub fn save_checkpoint(&self, path: &str) -> std::result::Result<(), String> {
+        fs::write(path, self.to_bytes()).map_err(|err| format!("can't write {}: {}", path, err))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> std::result::Result<Trainer, String> {
+        if bytes.len() < 24 || &bytes[..4] != MAGIC {
+            return Err(String::from("not a checkpoint"))
+        }
+        let version = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+        if version != VERSION {
+            return Err(format!("unknown checkpoint version {}", version))
+        }
+        let hidden = u32::from_le_bytes(bytes[8..12].try_into().unwrap()) as usize;
+        let epoch = u32::from_le_bytes(bytes[12..16].try_into().unwrap());
+        let step = u64::from_le_bytes(bytes[16..24].try_into().unwrap());
+        let n = size(hidden);
+        if hidden == 0 || !hidden.is_multiple_of(16) || bytes.len() != 24 + 3 * 4 * n {
+            return Err(format!("wrong size for a hidden layer of {}", hidden))
+        }
+
+        let mut values = bytes[24..].chunks_exact(4).map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()));
+        let weights = values.by_ref().take(n).collect();
+        let first = values.by_ref().take(n).collect();
+        let second = values.collect();
+        Ok(Trainer { hidden, weights, moments: [first, second], epoch, step })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(24 + 3 * 4 * self.weights.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(self.hidden as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.epoch.to_le_bytes());
+        bytes.extend_from_slice(&self.step.to_le_bytes());
+        for value in self.weights.iter().chain(&self.moments[0]).chain(&self.moments[1]) {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// The weights quantised for the engine
+    pub fn to_network(&self) -> Network {
+        let h = self.hidden;
+        let quantise = |values: &[f32], factor: i32| -> Vec<i16> {
+            values.iter().map(|value| (value * factor as f32).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16).collect()
+        };
+        let bias = (self.weights[size(h) - 1] * (QA * QB) as f32).round() as i32;
+        Network::new(
+            h,
+            quantise(&self.weights[..INPUTS * h], QA),
+            quantise(&self.weights[INPUTS * h..(INPUTS + 1) * h], QA),
+            quantise(&self.weights[(INPUTS + 1) * h..(INPUTS + 3) * h], QB),
+            bias,
+        ).unwrap()
+    }
+
+    /// Raw output for a sample, filling in the accumulators on the way
+    fn forward(&self, sample: &Sample, accumulators: &mut [Vec<f32>; 2]) -> f32 {
+        let h = self.hidden;
+        let biases = &self.weights[INPUTS * h..(INPUTS + 1) * h];
+        let outputs = &self.weights[(INPUTS + 1) * h..(INPUTS + 3) * h];
+
+        let mut out = self.weights[size(h) - 1];
+        for ((values, features), weights) in accumulators.iter_mut().zip(&sample.features).zip(outputs.chunks_exact(h)) {
+            values.copy_from_slice(biases);
+            for &feature in features {
+                let column = &self.weights[feature as usize * h..(feature as usize + 1) * h];
+                for (value, weight) in values.iter_mut().zip(column) {
+                    *value += weight;
+                }
+            }
+            out += values.iter().zip(weights).map(|(value, weight)| value.clamp(0.0, 1.0) * weight).sum::<f32>();
+        }
+        out
+    }
+
+    /// Evaluation in centipawns for the side to move, before quantising
+    pub fn evaluate(&self, sample: &Sample) -> f32 {
+        let mut accumulators = [vec![0.0; self.hidden], vec![0.0; self.hidden]];
+        self.forward(sample, &mut accumulators) * SCALE as f32
+    }
+
+    /// Adds the loss gradient over `samples` to `gradient`, returning the summed loss
+    fn backward(&self, samples: &[Sample], lambda: f32, gradient: &mut [f32]) -> f64 {
+        let h = self.hidden;
+        let outputs = &self.weights[(INPUTS + 1) * h..(INPUTS + 3) * h];
+        let (feature_grads, rest) = gradient.split_at_mut(INPUTS * h);
+        let (bias_grads, rest) = rest.split_at_mut(h);
+        let (output_grads, out_grad) = rest.split_at_mut(2 * h);
+
+        let mut accumulators = [vec![0.0; h], vec![0.0; h]];
+        let mut deltas = vec![0.0; h];
+        let mut loss = 0.0;
+        for sample in samples {
+            let predicted = sigmoid(self.forward(sample, &mut accumulators));
+            let error = predicted - sample.target(lambda);
+            loss += (error * error) as f64;
+            let delta = 2.0 * error * predicted * (1.0 - predicted);
+
+            out_grad[0] += delta;
+            let views = accumulators.iter().zip(&sample.features).zip(outputs.chunks_exact(h)).zip(output_grads.chunks_exact_mut(h));
+            for (((values, features), weights), weight_grads) in views {
+                for i in 0..h {
+                    let active = values[i] > 0.0 && values[i] < 1.0;
+                    weight_grads[i] += delta * values[i].clamp(0.0, 1.0);
+                    deltas[i] = if active { delta * weights[i] } else { 0.0 };
+                    bias_grads[i] += deltas[i];
+                }
+                for &feature in features {
+                    let column = &mut feature_grads[feature as usize * h..(feature as usize + 1) * h];
+                    for (grad, delta) in column.iter_mut().zip(&deltas) {
+                        *grad += delta;
+                    }
+                }
+            }
+        }
+        loss
+    }
+
+    /// Mean loss over `samples`, without training on them
+    pub fn loss(&self, samples: &[Sample], lambda: f32, threads: usize) -> f64 {
+        if samples.is_empty() {
+            return 0.0
+        }
+        let pieces: Vec<&[Sample]> = samples.chunks(PIECE).collect();
+        let per_thread = pieces.len().div_ceil(threads.max(1));
+        let sums: Vec<f64> = thread::scope(|scope| {
+            let handles: Vec<_> = pieces.chunks(per_thread)
+                .map(|pieces| scope.spawn(move || {
+                    let mut accumulators = [vec![0.0; self.hidden], vec![0.0; self.hidden]];
+                    pieces.iter()
+                        .map(|piece| piece.iter()
+                            .map(|sample| (sigmoid(self.forward(sample, &mut accumulators)) - sample.target(lambda)).powi(2) as f64)
+                            .sum::<f64>())
+                        .collect::<Vec<f64>>()
+                }))
+                .collect();
+            handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+        });
+        sums.iter().sum::<f64>() / samples.len() as f64
+    }
+
+    fn adam(&mut self, gradient: &[f32], learning_rate: f32) {
+        self.step += 1;
+        let correction1 = 1.0 - BETA1.powi(self.step.min(i32::MAX as u64) as i32);
+        let correction2 = 1.0 - BETA2.powi(self.step.min(i32::MAX as u64) as i32);
+        let [first, second] = &mut self.moments;
+        let clipped = (INPUTS + 1) * self.hidden;
+
+        for (i, ((weight, grad), (m, v))) in self.weights.iter_mut().zip(gradient).zip(first.iter_mut().zip(second.iter_mut())).enumerate() {
+            *m = BETA1 * *m + (1.0 - BETA1) * grad;
+            *v = BETA2 * *v + (1.0 - BETA2) * grad * grad;
+            *weight -= learning_rate * (*m / correction1) / ((*v / correction2).sqrt() + EPSILON);
+            if i < clipped {
+                *weight = weight.clamp(-MAX_FEATURE_WEIGHT, MAX_FEATURE_WEIGHT);
+            }
+        }
+    }
+
+    /// One pass over `samples` in a random order, returning the mean training loss
+    pub fn train_epoch(&mut self, samples: &mut [Sample], settings: &Settings, rng: &mut StdRng) -> f64 {
+        samples.shuffle(rng);
+        let n = self.weights.len();
+        let threads = settings.threads.max(1);
+        let mut total = 0.0;
+
+        for batch in samples.chunks(settings.batch_size.max(1)) {
+            let mut gradient = vec![0.0; n];
+            let pieces: Vec<&[Sample]> = batch.chunks(PIECE).collect();
+            // as many pieces at a time as there are threads, which keeps only that many partial
+            // gradients around
+            for wave in pieces.chunks(threads) {
+                let this = &*self;
+                let parts: Vec<(f64, Vec<f32>)> = thread::scope(|scope| {
+                    let handles: Vec<_> = wave.iter()
+                        .map(|&piece| scope.spawn(move || {
+                            let mut gradient = vec![0.0; n];
+                            let loss = this.backward(piece, settings.lambda, &mut gradient);
+                            (loss, gradient)
+                        }))
+                        .collect();
+                    handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+                });
+
+                for (loss, part) in parts {
+                    total += loss;
+                    for (sum, grad) in gradient.iter_mut().zip(part) {
+                        *sum += grad / batch.len() as f32;
+                    }
+                }
+            }
+            self.adam(&gradient, settings.learning_rate);
+        }
+
+        self.epoch += 1;
+        total / samples.len().max(1) as f64
+    }
+}
+
+/// Number of weights for a hidden layer of `hidden`
+fn size(hidden: usize) -> usize {
+    (INPUTS + 3) * hidden + 1
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use crate::datagen::Record;
+    use crate::trainer::{Sample, Settings, Trainer};
+
+    #[test]
+    fn test_training_fits_and_exports() {
+        let lines = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1;20;0.5",
+            "rnb1kbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1;900;1.0",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNB1KBNR b KQkq - 0 1;-900;0.0",
+            "4k3/8/8/8/8/8/PPPPPPPP/4K3 b - - 0 1;-600;1.0",
+        ];
+        let mut samples: Vec<Sample> = lines.iter().map(|line| Sample::from_record(&Record::parse(line).unwrap())).collect();
+        // the same position from black's side looks the same as from white's
+        let sorted = |sample: &Sample| sample.features.clone().map(|mut features| { features.sort(); features });
+        assert_eq!(sorted(&samples[1]), sorted(&samples[2]));
+        assert_eq!((samples[1].score, samples[1].result), (samples[2].score, samples[2].result));
+
+        let settings = Settings { learning_rate: 0.01, batch_size: 2, threads: 2, ..Default::default() };
+        let mut trainer = Trainer::new(16, 1).unwrap();
+        let mut rng = StdRng::seed_from_u64(1);
+        let before = trainer.loss(&samples, settings.lambda, 1);
+        for _ in 0..50 {
+            trainer.train_epoch(&mut samples, &settings, &mut rng);
+        }
+        assert!(trainer.loss(&samples, settings.lambda, 1) < before / 2.0);
+        assert_eq!(trainer.epoch(), 50);
+
+        let restored = Trainer::from_bytes(&trainer.to_bytes()).unwrap();
+        assert_eq!(restored.to_bytes(), trainer.to_bytes());
+
+        // quantising shouldn't move the evaluation by much
+        let network = trainer.to_network();
+        for line in lines {
+            let record = Record::parse(line).unwrap();
+            let float = trainer.evaluate(&Sample::from_record(&record));
+            let quantised = network.evaluate_board(&record.board) as f32;
+            assert!((float - quantised).abs() < 10.0, "{} vs {}", float, quantised);
+        }
+    }
+
+    #[test]
+    fn test_threads_give_the_same_run() {
+        let lines = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1;20;0.5",
+            "rnb1kbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1;900;1.0",
+            "4k3/8/8/8/8/8/PPPPPPPP/4K3 b - - 0 1;-600;1.0",
+        ];
+        // enough for a batch to be cut into several pieces
+        let samples: Vec<Sample> = lines.iter().cycle().take(700)
+            .map(|line| Sample::from_record(&Record::parse(line).unwrap()))
+            .collect();
+
+        let run = |threads: usize| {
+            let settings = Settings { batch_size: 700, threads, ..Default::default() };
+            let mut trainer = Trainer::new(16, 1).unwrap();
+            let mut rng = StdRng::seed_from_u64(1);
+            let mut samples = samples.clone();
+            let loss = trainer.train_epoch(&mut samples, &settings, &mut rng);
+            (loss, trainer.loss(&samples, settings.lambda, threads), trainer.to_bytes())
+        };
+        let single = run(1);
+        assert_eq!(run(2), single);
+        assert_eq!(run(3), single);
+    }
+}
@@ -0,0 +1,223 @@
+use std::fs;
+use std::thread;
+
+use crate::board::Board;
+use crate::endgame::{self, Material};
+use crate::eval;
+use crate::params::Params;
+use crate::uci;
+
+// Texel tuning: fitting the evaluation weights to game results. Every position in the dataset is
+// labelled with how its game ended, and the static evaluation is turned into an expected result
+// with a sigmoid,
+//
+//   expected = 1 / (1 + 10^(-K * eval / 400))
+//
+// K is fitted once to the starting weights and then held still while the weights are moved to
+// bring down the mean squared error between expected and actual results. The moving is a plain
+// local search: each weight in turn is nudged up, then down, and left wherever the error got
+// smaller, with passes over all of them until a pass where none of them helps.
+//
+// The positions want to be quiet ones, since the evaluation can't see a piece that's about to
+// be taken. The usual datasets already are; positions in check get dropped here, along with the
+// ones the endgame module scores by itself, which no weight can change.
+
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub board: Board,
+    /// 1 for a white win, 0.5 for a draw and 0 for a black win
+    pub result: f64,
+}
+
+impl Sample {
+    /// A FEN, with or without the move clocks, followed somewhere on the line by the result as
+    /// "[1.0]"/"[0.5]"/"[0.0]", "1-0"/"1/2-1/2"/"0-1", or in an EPD operation like c9 "1-0";
+    /// Lines written by datagen, "FEN;score;result", work too.
+    pub fn parse(line: &str) -> std::result::Result<Sample, String> {
+        let parts: Vec<&str> = line.split(';').collect();
+        if let [fen, score, result] = parts[..] {
+            if let (Ok(_), Some(result)) = (score.trim().parse::<i32>(), parse_result(result.trim())) {
+                let board = uci::position_board(Some(fen), &[])?;
+                return Ok(Sample { board, result })
+            }
+        }
+
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.len() < 5 {
+            return Err(format!("not a labelled position: {}", line))
+        }
+        let clocks = tokens.len() >= 7 && tokens[4..6].iter().all(|token| token.parse::<u32>().is_ok());
+        let fields = if clocks { 6 } else { 4 };
+
+        let board = uci::position_board(Some(tokens[..fields].join(" ").as_str()), &[])?;
+        let result = tokens[fields..].iter()
+            .find_map(|token| parse_result(token))
+            .ok_or_else(|| format!("no result: {}", line))?;
+        Ok(Sample { board, result })
+    }
+
+    /// Whether the weights have any say in how this position is scored
+    fn usable(&self) -> bool {
+        !self.board.in_check() && endgame::evaluate(&self.board, &Material::from_board(&self.board)).is_none()
+    }
+}
+
+fn parse_result(token: &str) -> Option<f64> {
+    match token.trim_matches(|c| c == '[' || c == ']' || c == '"' || c == ';') {
+        "1-0" | "1.0" => Some(1.0),
+        "1/2-1/2" | "0.5" => Some(0.5),
+        "0-1" | "0.0" => Some(0.0),
+        _ => None,
+    }
+}
+
+/// Every usable position in a dataset file. Blank lines and lines starting with # are skipped.
+pub fn load(path: &str) -> std::result::Result<Vec<Sample>, String> {
+    let text = fs::read_to_string(path).map_err(|err| format!("can't read {}: {}", path, err))?;
+    let mut samples = Vec::new();
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+        let sample = Sample::parse(line)?;
+        if sample.usable() {
+            samples.push(sample);
+        }
+    }
+    Ok(samples)
+}
+
+/// The expected result for white of a position white's evaluation gives `score`
+pub fn sigmoid(score: f64, k: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-k * score / 400.0))
+}
+
+fn squared_error(samples: &[Sample], params: &Params, k: f64) -> f64 {
+    samples.iter()
+        .map(|sample| (sample.result - sigmoid(eval::evaluate_with_params(&sample.board, params) as f64, k)).powi(2))
+        .sum()
+}
+
+/// Mean squared error of the evaluation's predictions over the samples, split over `threads`
+pub fn error(samples: &[Sample], params: &Params, k: f64, threads: usize) -> f64 {
+    if samples.is_empty() {
+        return 0.0
+    }
+    let total: f64 = if threads <= 1 {
+        squared_error(samples, params, k)
+    } else {
+        let size = samples.len().div_ceil(threads);
+        thread::scope(|scope| {
+            let handles: Vec<_> = samples.chunks(size)
+                .map(|chunk| scope.spawn(move || squared_error(chunk, params, k)))
+                .collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).sum()
+        })
+    };
+    total / samples.len() as f64
+}
+
+/// The K that makes `params` fit the samples best
+pub fn fit_k(samples: &[Sample], params: &Params) -> f64 {
+    // the evaluations don't depend on K, so they only need working out once
+    let scored: Vec<(f64, f64)> = samples.iter()
+        .map(|sample| (eval::evaluate_with_params(&sample.board, params) as f64, sample.result))
+        .collect();
+    let error = |k: f64| scored.iter().map(|(score, result)| (result - sigmoid(*score, k)).powi(2)).sum::<f64>();
+
+    // the error only has the one minimum in K, so a ternary search finds it
+    let (mut low, mut high) = (0.0, 10.0);
+    for _ in 0..100 {
+        let third = (high - low) / 3.0;
+        if error(low + third) < error(high - third) {
+            high -= third;
+        } else {
+            low += third;
+        }
+    }
+    (low + high) / 2.0
+}
+
+#[derive(Debug)]
+pub struct Tuner {
+    samples: Vec<Sample>,
+    params: Params,
+    k: f64,
+    error: f64,
+    threads: usize,
+}
+
+impl Tuner {
+    /// Fits K to the starting weights, which is what takes the time here
+    pub fn new(samples: Vec<Sample>, params: Params, threads: usize) -> Tuner {
+        let k = fit_k(&samples, &params);
+        let error = error(&samples, &params, k, threads);
+        Tuner { samples, params, k, error, threads }
+    }
+
+    pub fn params(&self) -> &Params {
+        &self.params
+    }
+
+    pub fn k(&self) -> f64 {
+        self.k
+    }
+
+    /// Mean squared error with the weights as they are now
+    pub fn error(&self) -> f64 {
+        self.error
+    }
+
+    /// One pass of the local search, moving each weight by `step`. `progress` is called with
+    /// how many weights have been tried out of how many there are. Returns how many changed.
+    pub fn pass<F>(&mut self, step: i32, mut progress: F) -> usize
+        where F: FnMut(usize, usize) {
+        let mut vector = self.params.to_vector();
+        let mut changed = 0;
+
+        for i in 0..vector.len() {
+            let start = vector[i];
+            for delta in [step, -step] {
+                vector[i] = start + delta;
+                self.params.set_vector(&vector);
+                let error = error(&self.samples, &self.params, self.k, self.threads);
+                if error < self.error {
+                    self.error = error;
+                    changed += 1;
+                    break
+                }
+                vector[i] = start;
+            }
+            self.params.set_vector(&vector);
+            progress(i + 1, vector.len());
+        }
+
+        changed
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::params::DEFAULT;
+    use crate::tune::{error, Sample, Tuner};
+
+    #[test]
+    fn test_parse_and_tune() {
+        let lines = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 [0.5]",
+            "4k3/8/8/8/8/8/3PPP2/3QK3 w - - 1-0",
+            "4k3/pppppppp/8/8/8/8/8/4K3 b - - c9 \"0-1\";",
+            "4k3/pppppppp/8/8/8/8/8/4K3 w - - 0 1;-250;0.0",
+        ];
+        let samples: Vec<Sample> = lines.iter().map(|line| Sample::parse(line).unwrap()).collect();
+        assert_eq!(samples.iter().map(|sample| sample.result).collect::<Vec<_>>(), vec![0.5, 1.0, 0.0, 0.0]);
+        assert!(Sample::parse("4k3/8/8/8/8/8/8/4K3 w - - hmvc 0;").is_err());
+
+        let mut tuner = Tuner::new(samples.clone(), DEFAULT.clone(), 2);
+        assert!(tuner.k() > 0.0);
+        assert!((tuner.error() - error(&samples, &DEFAULT, tuner.k(), 1)).abs() < 1e-12);
+
+        let before = tuner.error();
+        assert!(tuner.pass(1, |_, _| ()) > 0);
+        assert!(tuner.error() < before);
+        assert!((tuner.error() - error(&samples, tuner.params(), tuner.k(), 1)).abs() < 1e-12);
+    }
+}
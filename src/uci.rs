@@ -0,0 +1,289 @@
+use std::time::Duration;
+
+use crate::board::{Board, ChessMove, EMPTY, KING, ROOK, TEAM_MASK, UNIT_MASK};
+use crate::options;
+use crate::search::SearchLimits;
+
+// Turns lines from the GUI into commands. Commands are split on any run of whitespace, and
+// anything wrong with a line comes back as an error message for the caller to pass on as an
+// `info string`, rather than taking the engine down.
+//
+// Like the protocol asks, unknown words at the start of a line are skipped, so "joho debug on"
+// is still "debug on".
+
+pub const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UciCommand {
+    Uci,
+    Debug(bool),
+    IsReady,
+    SetOption { name: String, value: String },
+    UciNewGame,
+    /// `fen` is None for startpos
+    Position { fen: Option<String>, moves: Vec<ChessMove> },
+    Go(SearchLimits),
+    Stop,
+    PonderHit,
+    Quit,
+    /// not UCI, starts the search monitor, on the default address if none is given
+    Server { address: Option<String> },
+    /// not UCI, `server stop` shuts the search monitor down
+    ServerStop,
+    /// not UCI, loads a test position and prints it
+    Playground,
+}
+
+/// Parses one line from the GUI. Blank lines come back as None.
+pub fn parse(line: &str) -> std::result::Result<Option<UciCommand>, String> {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    if tokens.is_empty() {
+        return Ok(None)
+    }
+
+    let start = tokens.iter()
+        .position(|token| command_word(token))
+        .ok_or_else(|| format!("unknown command: {}", line.trim()))?;
+    let tokens = &tokens[start..];
+    let args = &tokens[1..];
+
+    let command = match tokens[0] {
+        "uci" => UciCommand::Uci,
+        "debug" => match args.first() {
+            Some(&"on") => UciCommand::Debug(true),
+            Some(&"off") => UciCommand::Debug(false),
+            _ => return Err(String::from("debug needs on or off")),
+        },
+        "isready" => UciCommand::IsReady,
+        "setoption" => {
+            let (name, value) = options::parse_setoption(tokens)?;
+            UciCommand::SetOption { name, value }
+        },
+        "ucinewgame" => UciCommand::UciNewGame,
+        "position" => parse_position(args)?,
+        "go" => UciCommand::Go(parse_go(args)?),
+        "stop" => UciCommand::Stop,
+        "ponderhit" => UciCommand::PonderHit,
+        "quit" | "exit" => UciCommand::Quit,
+        "server" => match args {
+            [] => UciCommand::Server { address: None },
+            ["stop"] => UciCommand::ServerStop,
+            [address] => UciCommand::Server { address: Some(address.to_string()) },
+            _ => return Err(String::from("server takes an address or stop")),
+        },
+        "t" => UciCommand::Playground,
+        _ => unreachable!(),
+    };
+    Ok(Some(command))
+}
+
+fn command_word(token: &str) -> bool {
+    matches!(token, "uci" | "debug" | "isready" | "setoption" | "ucinewgame" | "position" | "go"
+        | "stop" | "ponderhit" | "quit" | "exit" | "server" | "t")
+}
+
+/// e2e4 or e7e8q
+pub fn looks_like_move(arg: &str) -> bool {
+    let chars: Vec<char> = arg.chars().collect();
+    (chars.len() == 4 || chars.len() == 5)
+        && ('a'..='h').contains(&chars[0]) && ('1'..='8').contains(&chars[1])
+        && ('a'..='h').contains(&chars[2]) && ('1'..='8').contains(&chars[3])
+        && (chars.len() == 4 || "nbrq".contains(chars[4]))
+}
+
+fn parse_moves(args: &[&str]) -> std::result::Result<Vec<ChessMove>, String> {
+    args.iter()
+        .map(|arg| if looks_like_move(arg) {
+            Ok(ChessMove::from_long_algebraic_notation(arg))
+        } else {
+            Err(format!("not a move: {}", arg))
+        })
+        .collect()
+}
+
+/// `position startpos [moves ...]` or `position fen <fen> [moves ...]`
+fn parse_position(args: &[&str]) -> std::result::Result<UciCommand, String> {
+    let moves_index = args.iter().position(|arg| *arg == "moves").unwrap_or(args.len());
+    let moves = parse_moves(&args[(moves_index + 1).min(args.len())..])?;
+
+    let fen = match args.first() {
+        Some(&"startpos") if moves_index == 1 => None,
+        Some(&"fen") if moves_index > 1 => Some(args[1..moves_index].join(" ")),
+        Some(&"startpos") | Some(&"fen") => return Err(String::from("malformed position command")),
+        _ => return Err(String::from("position needs startpos or fen")),
+    };
+    Ok(UciCommand::Position { fen, moves })
+}
+
+fn parse_number<T: std::str::FromStr>(name: &str, arg: Option<&&str>) -> std::result::Result<T, String> {
+    arg.and_then(|arg| arg.parse::<T>().ok())
+        .ok_or_else(|| format!("go {} needs a number", name))
+}
+
+fn parse_millis(name: &str, arg: Option<&&str>) -> std::result::Result<Option<Duration>, String> {
+    // some GUIs send negative clock times when they're very low, treat those as none left
+    parse_number::<i64>(name, arg).map(|n| Some(Duration::from_millis(n.max(0) as u64)))
+}
+
+fn parse_go(args: &[&str]) -> std::result::Result<SearchLimits, String> {
+    let mut limits = SearchLimits::default();
+    let mut iter = args.iter().peekable();
+
+    while let Some(&arg) = iter.next() {
+        match arg {
+            "depth" => limits.depth = Some(parse_number(arg, iter.next())?),
+            "nodes" => limits.nodes = Some(parse_number(arg, iter.next())?),
+            "movetime" => limits.movetime = parse_millis(arg, iter.next())?,
+            "wtime" => limits.wtime = parse_millis(arg, iter.next())?,
+            "btime" => limits.btime = parse_millis(arg, iter.next())?,
+            "winc" => limits.winc = parse_millis(arg, iter.next())?,
+            "binc" => limits.binc = parse_millis(arg, iter.next())?,
+            "movestogo" => limits.movestogo = Some(parse_number(arg, iter.next())?),
+            "searchmoves" => {
+                // runs until the next thing that isn't a move
+                while let Some(&&arg) = iter.peek().filter(|arg| looks_like_move(arg)) {
+                    limits.searchmoves.push(ChessMove::from_long_algebraic_notation(arg));
+                    iter.next();
+                }
+            },
+            "infinite" => limits.infinite = true,
+            "ponder" => limits.ponder = true,
+            "mate" => {
+                parse_number::<u32>(arg, iter.next())?;
+            },
+            _ => return Err(format!("unknown go argument: {}", arg)),
+        }
+    }
+
+    Ok(limits)
+}
+
+/// Sets up the board a `position` command asks for, checking every move is legal on the way.
+pub fn position_board(fen: Option<&str>, moves: &[ChessMove]) -> std::result::Result<Board, String> {
+    let fen = fen.unwrap_or(START_FEN);
+    let mut parts: Vec<&str> = fen.split_whitespace().collect();
+    // the move clocks are often left off
+    if parts.len() == 4 {
+        parts.extend_from_slice(&["0", "1"]);
+    }
+    if parts.len() != 6 {
+        return Err(format!("bad fen: {}", fen))
+    }
+    let mut arg: [&str; 6] = Default::default();
+    arg.copy_from_slice(&parts);
+    let board = Board::from_fen(arg).map_err(|err| format!("bad fen: {}", err))?;
+    play_moves(board, moves, 0)
+}
+
+/// Plays moves from a `position` command onto a board, checking every one is legal first.
+/// `already_played` is how many of the command's moves the board has had played on it already,
+/// so an illegal move is reported by where it is in the whole command.
+pub fn play_moves(mut board: Board, moves: &[ChessMove], already_played: usize) -> std::result::Result<Board, String> {
+    for (i, chess_move) in moves.iter().enumerate() {
+        let chess_move = castle_from_king_takes_rook(&board, chess_move);
+        if !board.clone().get_legal_moves().contains(&chess_move) {
+            return Err(format!("illegal move {}: {}", already_played + i + 1, chess_move.to_long_algebraic_notation()))
+        }
+        board = board.apply_move(&chess_move);
+    }
+    Ok(board)
+}
+
+/// Some GUIs write castling as the king taking its own rook (e1h1 rather than e1g1), which is
+/// turned into the king's two square move here. Everything else comes back as it was.
+pub fn castle_from_king_takes_rook(board: &Board, chess_move: &ChessMove) -> ChessMove {
+    let (from, to) = (chess_move.from_ind(), chess_move.to_ind());
+    let king = board.piece_at(from);
+    let target = board.piece_at(to);
+
+    let own_rook = king & UNIT_MASK == KING && target & UNIT_MASK == ROOK && target & TEAM_MASK == king & TEAM_MASK;
+    if own_rook && from.0 == 4 && from.1 == to.1 && (to.0 == 0 || to.0 == 7) {
+        ChessMove::new(from, (if to.0 == 7 { 6 } else { 2 }, to.1), EMPTY)
+    } else {
+        chess_move.clone()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::board::ChessMove;
+    use crate::search::SearchLimits;
+    use crate::uci::{parse, play_moves, position_board, UciCommand};
+
+    // hand written sessions in the style of what cutechess and Arena send, engine side only
+    const TRANSCRIPTS: [&str; 2] = [
+        include_str!("../tests/fixtures/uci/cutechess.txt"),
+        include_str!("../tests/fixtures/uci/arena.txt"),
+    ];
+    const MALFORMED: &str = include_str!("../tests/fixtures/uci/malformed.txt");
+
+    #[test]
+    fn test_gui_transcripts() {
+        for transcript in TRANSCRIPTS.iter() {
+            for line in transcript.lines() {
+                let command = parse(line).unwrap_or_else(|err| panic!("{}: {}", line, err));
+                if let Some(UciCommand::Position { fen, moves }) = command {
+                    position_board(fen.as_deref(), &moves).unwrap_or_else(|err| panic!("{}: {}", line, err));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_malformed_input_is_an_error() {
+        for line in MALFORMED.lines().filter(|line| !line.is_empty()) {
+            let res = parse(line).and_then(|command| match command {
+                Some(UciCommand::Position { fen, moves }) => position_board(fen.as_deref(), &moves).map(|_| ()),
+                _ => Ok(()),
+            });
+            assert!(res.is_err(), "{} should have been rejected", line);
+        }
+    }
+
+    #[test]
+    fn test_playing_on_matches_replaying() {
+        let moves: Vec<ChessMove> = ["e2e4", "e7e5", "g1f3", "b8c6", "f1c4", "g8f6", "e1h1"].iter()
+            .map(|m| ChessMove::from_long_algebraic_notation(m))
+            .collect();
+        let replayed = position_board(None, &moves).unwrap();
+        let played_on = play_moves(position_board(None, &moves[..4]).unwrap(), &moves[4..], 4).unwrap();
+        assert_eq!(played_on.id(), replayed.id());
+
+        // a bad move is counted from the start of the whole command
+        let bad = play_moves(replayed, &[ChessMove::from_long_algebraic_notation("e4e5")], moves.len());
+        assert_eq!(bad.err().as_deref(), Some("illegal move 8: e4e5"));
+    }
+
+    #[test]
+    fn test_whitespace_and_junk() {
+        assert_eq!(parse("  \t "), Ok(None));
+        assert_eq!(parse("joho  debug\ton"), Ok(Some(UciCommand::Debug(true))));
+        assert_eq!(parse("server 0.0.0.0:9000"), Ok(Some(UciCommand::Server { address: Some(String::from("0.0.0.0:9000")) })));
+        assert_eq!(parse("server  stop"), Ok(Some(UciCommand::ServerStop)));
+        assert_eq!(
+            parse("position   startpos  moves e2e4\te7e5"),
+            Ok(Some(UciCommand::Position {
+                fen: None,
+                moves: vec![ChessMove::from_long_algebraic_notation("e2e4"), ChessMove::from_long_algebraic_notation("e7e5")],
+            })),
+        );
+        assert_eq!(
+            parse("go wtime 1000  btime -20 searchmoves e2e4 depth 3"),
+            Ok(Some(UciCommand::Go(SearchLimits {
+                depth: Some(3),
+                wtime: Some(Duration::from_millis(1000)),
+                btime: Some(Duration::from_millis(0)),
+                searchmoves: vec![ChessMove::from_long_algebraic_notation("e2e4")],
+                ..Default::default()
+            }))),
+        );
+        assert_eq!(parse("go infinite"), Ok(Some(UciCommand::Go(SearchLimits { infinite: true, ..Default::default() }))));
+        assert_eq!(
+            parse("go ponder movetime 100"),
+            Ok(Some(UciCommand::Go(SearchLimits { ponder: true, movetime: Some(Duration::from_millis(100)), ..Default::default() }))),
+        );
+    }
+}
@@ -1,426 +1,483 @@
-use std::cell::{RefCell, RefMut, Ref};
-use std::collections::{HashMap, VecDeque, HashSet};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::mem;
 use std::rc::Rc;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::board::{Board, ChessMove, GameResult, EMPTY};
+use crate::eval::{self, Evaluator};
+use crate::search::{SearchInfo, SearchLimits};
+use crate::table::Bound;
+
+// Monte Carlo tree search, as an alternative to the alpha-beta searcher. The positions make up a
+// graph keyed by board id like before, so a position reached by two move orders is one node and
+// everything learnt about it is shared (a DAG rather than a tree). Each playout:
+//
+//  - walks down from the root picking the edge with the best PUCT score, the child's average
+//    value plus an exploration bonus that's large for moves with a high prior and few visits
+//  - expands the first node it gets to that hasn't been, giving every legal move a prior from a
+//    cheap policy (captures of valuable pieces first) and linking in the children
+//  - values that node, either straight from the static evaluation or by playing it out with
+//    moves drawn from the same policy, and falls back on the evaluation if that goes on too long
+//  - backs the value up the path it came down, flipping it at every ply
+//
+// Values are the side to move's expected result, 1 a win and 0 a loss, with the evaluation put
+// on that scale the same way the tuner does it. Coming back round to a position already on the
+// path counts as a draw. The move played is the root's most visited one.
+//
+// The graph is kept to about the size of the Hash option. Once it's full, leaves are still
+// valued but no longer expanded, so later playouts only sharpen what's already there.
+
+const C_PUCT: f64 = 1.5;
+// unvisited children are assumed a little worse than their parent
+const FPU_REDUCTION: f64 = 0.2;
+// capture gain, in centipawns, that makes a move e times as likely under the policy
+const POLICY_TEMPERATURE: f64 = 100.0;
+const ROLLOUT_PLIES: usize = 40;
+const DEFAULT_PLAYOUTS: u64 = 20_000;
+// same as the Hash option's default
+const DEFAULT_MEGABYTES: usize = 16;
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How a newly expanded node gets its value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeafValue {
+    Evaluation,
+    Rollout,
+}
 
-use crate::board::{Board, ChessMove};
-use crate::echo_client::log;
-use std::any::Any;
-use std::{cmp, mem};
-use std::borrow::BorrowMut;
+struct Edge {
+    chess_move: ChessMove,
+    child: Rc<RefCell<Node>>,
+    prior: f64,
+    visits: u32,
+}
 
-#[derive(Debug, Clone)]
 struct Node {
     board: Board,
-    best_score: (Option<ChessMove>, f32),  // best known move from this node
-
-    explored: bool,  // whether all children have been visited or not
-    is_valid: bool,  // either board is illegal state OR no known valid path to get here
-
-    parents: Vec<(ChessMove, Rc<RefCell<Node>>)>,  // (move to get from parent to here, parent)
-    children: Vec<(ChessMove, Rc<RefCell<Node>>)>  // (move to get from here to child, child)
+    visits: u32,
+    value: f64,  // summed over the visits, from the side to move's view
+    terminal: Option<f64>,  // the value, if the game is over here
+    expanded: bool,
+    edges: Vec<Edge>,
 }
 
 impl Node {
-    pub fn new(board: Board) -> Node {
-        Node{
-            board: board,
-            is_valid: false,
-            best_score: (None, 0.0),
-            explored: false,
-            parents: vec![],
-            children: vec![]
-        }
+    fn new(board: Board) -> Node {
+        Node { board, visits: 0, value: 0.0, terminal: None, expanded: false, edges: Vec::new() }
     }
 
-    pub fn update_score(&mut self, score: f32, cm: &ChessMove, depth: i32) {
-        let mut update_parents = false;
+    fn average(&self) -> f64 {
+        if self.visits == 0 { 0.5 } else { self.value / self.visits as f64 }
+    }
 
-        if self.board.is_whites_move() {
-            // whites move, look for high score
-            if self.best_score.0.is_none() || score > self.best_score.1 {
-                self.best_score.0 = Some(cm.clone());
-                self.best_score.1 = score;
-                update_parents = true;
-            } else {
-                // if the new score move is the same as the current score move, then we
-                // have to find the new best move, because we may no longer be the best
-
-                // check if the move is the same as the current best
-                let same_move = self.best_score.0.clone().map(|bcm| return bcm == *cm).unwrap_or(false);
-                if same_move {
-                    // find the new best move
-                    let mut current_best: Option<(&ChessMove, f32)> = None;
-                    for (cm, child) in self.children.iter() {
-                        let n = match child.try_borrow() {
-                            Err(_) => continue,
-                            Ok(n) => n
-                        };
-
-                        current_best = current_best.map(|(best_move, best_score)| {
-                            if n.best_score.1 >= best_score {
-                                Some((cm, n.best_score.1))
-                            } else {
-                                Some((best_move, best_score))
-                            }
-                        }).unwrap_or(Some((cm, n.best_score.1)));
-                    }
-                    self.best_score = current_best.map(|(cm, s)| (Some(cm.clone()), s)).unwrap_or((None, 0 as f32));
-                }
-            }
-        } else {
-            // blacks move, look for low score
-            if self.best_score.0.is_none() || score < self.best_score.1 {
-                self.best_score.0 = Some(cm.clone());
-                self.best_score.1 = score;
-                update_parents = true;
-            } else {
-                // if the new score move is the same as the current score move, then we
-                // have to find the new best move
-
-                // check if the move is the same as the current best
-                let same_move = self.best_score.0.clone().map(|bcm| return bcm == *cm).unwrap_or(false);
-                if same_move {
-                    // find the new best move
-                    let mut current_best: Option<(&ChessMove, f32)> = None;
-                    for (cm, child) in self.children.iter() {
-                        let n = match child.try_borrow() {
-                            Err(_) => continue,
-                            Ok(n) => n
-                        };
-
-                        current_best = current_best.map(|(best_move, best_score)| {
-                            if n.best_score.1 <= best_score {
-                                Some((cm, n.best_score.1))
-                            } else {
-                                Some((best_move, best_score))
-                            }
-                        }).unwrap_or(Some((cm, n.best_score.1)));
-                    }
-                    self.best_score = current_best.map(|(cm, s)| (Some(cm.clone()), s)).unwrap_or((None, 0 as f32));
-                }
+    /// The edge PUCT would follow from here, out of `only` if that isn't empty
+    fn select(&self, only: &[ChessMove]) -> usize {
+        let total = self.edges.iter().map(|edge| edge.visits).sum::<u32>() as f64;
+        let unvisited = (self.average() - FPU_REDUCTION).max(0.0);
+
+        let mut best = (0, f64::NEG_INFINITY);
+        for (i, edge) in self.edges.iter().enumerate().filter(|(_, edge)| allowed(edge, only)) {
+            let child = edge.child.borrow();
+            // the child's value is for the other side
+            let q = if child.visits == 0 { unvisited } else { 1.0 - child.average() };
+            let u = C_PUCT * edge.prior * (total + 1.0).sqrt() / (1.0 + edge.visits as f64);
+            if q + u > best.1 {
+                best = (i, q + u);
             }
         }
+        best.0
+    }
 
-        if update_parents {
-            for rp in self.parents.iter_mut() {
-                // if we have a loop in the graph, then the borrow will fail. This is fine since
-                // we've therefore already visited the node.
-                match rp.1.try_borrow_mut() {
-                    Ok(mut n) => n.update_score(score, &rp.0, depth + 1),
-                    Err(_) => {}
-                }
-            }
-        }
+    fn most_visited(&self, only: &[ChessMove]) -> Option<&Edge> {
+        self.edges.iter().filter(|edge| allowed(edge, only)).max_by_key(|edge| edge.visits)
     }
+}
 
-    pub fn clear(&mut self) {
-        self.parents = vec![];
-        let children = mem::take(&mut self.children);
+fn allowed(edge: &Edge, only: &[ChessMove]) -> bool {
+    only.is_empty() || only.contains(&edge.chess_move)
+}
 
-        for (_cm, rnode) in children.into_iter() {
-            rnode.try_borrow_mut().map(|mut child| child.clear());
-        }
-    }
+/// Expected result for the side to move given their evaluation
+fn win_chance(centipawns: i32) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-centipawns as f64 / 400.0))
+}
+
+/// The reverse of `win_chance`, for reporting
+fn centipawns(value: f64) -> i32 {
+    let value = value.clamp(0.001, 0.999);
+    (400.0 * (value / (1.0 - value)).log10()).round() as i32
+}
 
-    pub fn as_debug_string(&self) -> String {
-        let mut msg = String::with_capacity(10);  // y not
-        msg.push_str("\n<Node>\n");
-        msg.push_str(format!("valid:         {}\n", self.is_valid).as_str());
-        msg.push_str(
-            format!("node score: {}, {}\n",
-            self.best_score.1,
-            self.best_score.0.as_ref().map_or(String::from("None"), |cm| cm.to_long_algebraic_notation())
-            ).as_str()
-        );
-        msg.push_str(format!("parents / children: {} / {}\n", self.parents.len(), self.children.len()).as_str());
-        msg.push_str(self.board.as_debug_string().as_str());
-        msg.push_str("</Node>\n");
-        return msg
+/// The value of a finished game for whoever's turn it is
+fn result_value(result: GameResult, whites_move: bool) -> f64 {
+    match (result, whites_move) {
+        (GameResult::Draw, _) => 0.5,
+        (GameResult::WhiteWin, true) | (GameResult::BlackWin, false) => 1.0,
+        _ => 0.0,
     }
 }
 
-#[derive(Debug, Clone)]
+/// Prior probabilities for `moves`, a softmax over how much each one captures or promotes
+fn policy(board: &Board, moves: &[ChessMove]) -> Vec<f64> {
+    let gain = |cm: &ChessMove| {
+        let victim = eval::piece_index(board.piece_at(cm.to_ind())).map(|index| eval::MG_VALUE[index]).unwrap_or(0);
+        let promotion = if cm.promotion() == EMPTY { 0 } else { eval::MG_VALUE[4] };
+        (victim + promotion) as f64 / POLICY_TEMPERATURE
+    };
+    let gains: Vec<f64> = moves.iter().map(gain).collect();
+    let max = gains.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let weights: Vec<f64> = gains.iter().map(|g| (g - max).exp()).collect();
+    let total: f64 = weights.iter().sum();
+    weights.iter().map(|w| w / total).collect()
+}
+
+/// Rough memory per position in the graph: the node, the edge leading to it, its map entry and
+/// the board's move lists
+fn node_bytes() -> usize {
+    mem::size_of::<Node>() + mem::size_of::<Edge>() + 2 * mem::size_of::<u64>() + 64 * mem::size_of::<ChessMove>()
+}
+
+#[derive(Clone)]
 pub struct Manager {
-    boards: Rc<RefCell<HashMap<u64, Rc<RefCell<Node>>>>>, // TODO use diff hash algo?
+    nodes: HashMap<u64, Rc<RefCell<Node>>>,
+    max_nodes: usize,
+    evaluator: Evaluator,
+    rng: StdRng,
+    leaf: LeafValue,
+    stop_signal: Arc<AtomicBool>,  // set from outside to end the search early
 }
 
+impl fmt::Debug for Manager {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Manager").field("nodes", &self.nodes.len()).field("leaf", &self.leaf).finish()
+    }
+}
+
+impl Default for Manager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Manager {
     pub fn new() -> Manager {
-        Manager{
-            boards: Rc::new(RefCell::new(HashMap::new()))
+        Manager {
+            nodes: HashMap::new(),
+            max_nodes: DEFAULT_MEGABYTES * 1024 * 1024 / node_bytes(),
+            evaluator: Evaluator::new(),
+            rng: StdRng::seed_from_u64(0),
+            leaf: LeafValue::Evaluation,
+            stop_signal: Arc::new(AtomicBool::new(false)),
         }
     }
 
-    pub fn clear(&mut self) {
-        let mut rhash = mem::take(&mut self.boards);
+    pub fn set_leaf_value(&mut self, leaf: LeafValue) {
+        self.leaf = leaf;
+    }
 
-        let hash = *rhash;
+    /// Like the alpha-beta searcher's, a flag another thread can set to stop the playouts early
+    pub fn set_stop_signal(&mut self, stop_signal: Arc<AtomicBool>) {
+        self.stop_signal = stop_signal;
+    }
 
-        for (_key, rnode) in hash.borrow_mut().iter() {
-            *rnode.borrow_mut().clear();
+    /// Caps the graph at about `megabytes`. A graph that's already bigger is cleared.
+    pub fn set_hash_size(&mut self, megabytes: usize) {
+        self.max_nodes = (megabytes * 1024 * 1024 / node_bytes()).max(1);
+        if self.nodes.len() > self.max_nodes {
+            self.clear();
         }
+    }
 
-        self.boards = Rc::new(RefCell::new(HashMap::new()));
+    /// Number of positions in the graph
+    pub fn len(&self) -> usize {
+        self.nodes.len()
     }
 
-    pub fn lookup_highest_move(&self, key: u64) -> std::result::Result<ChessMove, String> {
-        log(format!("HM size: {}", self.boards.borrow().len()).as_str());
-
-        match self.boards.borrow().get(&key) {
-            None => Err(String::from("no board found in boards map")),
-            Some(n) => {
-                // println!("current board: {}", n.borrow().as_debug_string());
-                // println!("TOTAL CHILDREN: {}", n.borrow().children.len());
-                // for (cm, p) in n.borrow().children.iter() {
-                //     println!("\n{}{}", cm.to_long_algebraic_notation(), p.borrow().as_debug_string());
-                // }
-                println!("Rc<RefCell<Node>>={}, usize={}, Node={}, Board={}, Vec<(ChessMove, Rc<RefCell<Node>>)>={}",
-                         mem::size_of_val(&Rc::new(RefCell::new(Node::new(Board::new())))),
-                         mem::size_of::<usize>(),
-                         mem::size_of::<Node>(),
-                         mem::size_of::<Board>(),
-                         mem::size_of::<Vec<(ChessMove, Rc<RefCell<Node>>)>>(),
-                );
-
-                match &n.borrow().best_score.0 {
-                    None => {
-                        Err(String::from("no move found for board"))
-                    },
-                    Some(cm) => Ok(cm.clone())
-                }
-            }
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        // repetitions make cycles, so the links have to be cut for anything to be freed
+        for node in self.nodes.values() {
+            node.borrow_mut().edges.clear();
         }
+        self.nodes.clear();
     }
 
-    pub fn find_best_move(&mut self, board: Board, iters: usize) -> std::result::Result<ChessMove, String> {
-        let key = board.id();
-        let queue = {
-            let mut bm = self.boards.borrow_mut();
-            match bm.get(&key) {
-                Some(rnode) => {
-                    println!("NODE EXISTS WOAH, explored: {}, #child: {}", rnode.borrow().explored, rnode.borrow().children.len());
-                    // TODO can free all nodes that are non-children of the requested one
-                    // TODO grab node, grab it's children, find node with no children to actually explore
-
-                    if !rnode.borrow().explored {
-                        // node exists but we haven't explored it yet, can simply add it to the queue
-                        ChessQueue::new(key)
-                    } else {
-                        // add all children nodes that aren't explored
-                        let queue = ChessQueue::empty();
-                        let entries = Manager::unexplored_child_nodes(rnode.borrow(), 1, &mut HashSet::new());
-                        queue.append(entries);
-                        queue
-                    }
-                },
-                None => {
-                    let mut node = Node::new(board);
-                    node.is_valid = true;  // current board is assumed to be valid??
-                    let rnode = Rc::new(RefCell::new(node));
-                    bm.insert(key, rnode);
-
-                    ChessQueue::new(key)
-                }
+    /// Drops every node that can't be reached from the board with this key, keeping the graph
+    /// underneath it for the next search. If we've never seen the board, everything goes.
+    pub fn prune_to(&mut self, key: u64) {
+        let mut reachable: HashSet<u64> = HashSet::new();
+        let mut stack: Vec<Rc<RefCell<Node>>> = self.nodes.get(&key).cloned().into_iter().collect();
+        while let Some(rnode) = stack.pop() {
+            let node = rnode.borrow();
+            if reachable.insert(node.board.id()) {
+                stack.extend(node.edges.iter().map(|edge| edge.child.clone()));
             }
-        };
-
-        println!("Starting queue size: {}", queue.len());
-        self.clone().work(key, queue.clone(), iters);
+        }
 
-        // query current best move
-        self.lookup_highest_move(key)
+        self.nodes.retain(|key, rnode| {
+            if reachable.contains(key) {
+                true
+            } else {
+                rnode.borrow_mut().edges.clear();
+                false
+            }
+        });
     }
 
-    pub fn work(self, baseline_key: u64, mut queue: ChessQueue, iters: usize) -> () {
-        let mut count = 0;
+    fn node(&mut self, board: Board) -> Rc<RefCell<Node>> {
+        self.nodes.entry(board.id())
+            .or_insert_with(|| Rc::new(RefCell::new(Node::new(board))))
+            .clone()
+    }
 
-        // resolve baseline score so that we can do some basic filtering
-        let base_node = self.boards.borrow().get(&baseline_key).unwrap().clone();
-        let baseline_score = base_node.borrow().board.score();
-        let whites_turn = base_node.borrow().board.is_whites_move();
-        let f = |score: f32| -> bool {
-            if whites_turn {
-                score >= baseline_score
-            } else {
-                score <= baseline_score
-            }
-        };
+    /// Links in the node's children and gives back its value. With the graph full the node is
+    /// only valued, and stays a leaf.
+    fn expand(&mut self, node: &mut Node) -> f64 {
+        if let Some((result, _)) = node.board.game_result() {
+            let value = result_value(result, node.board.is_whites_move());
+            node.expanded = true;
+            node.terminal = Some(value);
+            return value
+        }
+        if self.nodes.len() < self.max_nodes {
+            node.expanded = true;
+            self.link_children(node);
+        }
 
-        for _ in 0..iters {
+        match self.leaf {
+            LeafValue::Evaluation => win_chance(self.evaluator.evaluate_relative(&node.board)),
+            LeafValue::Rollout => self.rollout(&node.board),
+        }
+    }
 
-            let items = queue.grab(100);
-            println!("grabbed {} items, queue size = {}", items.len(), queue.len());
+    fn link_children(&mut self, node: &mut Node) {
+        let moves = node.board.clone().get_legal_moves();
+        let priors = policy(&node.board, &moves);
+        for (chess_move, prior) in moves.into_iter().zip(priors) {
+            let child = self.node(node.board.apply_move(&chess_move));
+            node.edges.push(Edge { chess_move, child, prior, visits: 0 });
+        }
+    }
 
-            for (depth, key) in items.into_iter() {
-                count += 1;
-                if count % 500 == 0 {
-                    log(format!("ITER={}, depth={}", count, depth).as_str());
+    /// Plays the game on from `board` with moves drawn from the policy
+    fn rollout(&mut self, board: &Board) -> f64 {
+        let mut current = board.clone();
+        for _ in 0..ROLLOUT_PLIES {
+            if let Some((result, _)) = current.game_result() {
+                return result_value(result, board.is_whites_move())
+            }
+            let moves = current.clone().get_legal_moves();
+            let mut roll = self.rng.gen::<f64>();
+            let mut chosen = moves.len() - 1;
+            for (i, prior) in policy(&current, &moves).into_iter().enumerate() {
+                roll -= prior;
+                if roll <= 0.0 {
+                    chosen = i;
+                    break
                 }
+            }
+            current = current.apply_move(&moves[chosen]);
+        }
 
-                // get the node from the hashmap
-                let rnode_base = match self.boards.borrow_mut().get(&key) {
-                    None => {
-                        log("board not found in hashmap!");
-                        return;
-                    },
-                    Some(rnode) => rnode.clone(),
-                };
-
-                // filter it :D
-                let s = rnode_base.borrow().board.score();
-                if !f(s) {
-                    continue
-                }
+        let score = self.evaluator.evaluate(&current);
+        let score = if board.is_whites_move() { score } else { -score };
+        win_chance(score)
+    }
 
-                // list of final nodes to update scores
-                let mut final_nodes: Vec<Rc<RefCell<Node>>> = Vec::with_capacity(1500);
-
-                // rip all legal moves
-                rnode_base.borrow_mut().explored = true;
-                let moves = rnode_base.borrow_mut().board.get_legal_moves();
-                for cm in moves.iter() {
-                    let nboard = rnode_base.borrow().board.apply_move(cm);
-                    let rnode_one = Manager::grow(&mut self.boards.borrow_mut(), &rnode_base, cm, nboard);
-
-                    // rip all opponent legal moves as well
-                    rnode_one.borrow_mut().explored = true;
-                    let opp_moves = rnode_one.borrow_mut().board.get_legal_moves();
-                    for opp_cm in opp_moves.iter() {
-                        let nnboard = rnode_one.borrow().board.apply_move(opp_cm);
-                        let rnode_two = Manager::grow(&mut self.boards.borrow_mut(), &rnode_one, opp_cm, nnboard);
-
-                        final_nodes.push(rnode_two);
-                    }
-                }
+    /// One playout from the root, giving back how deep it went. `searchmoves` limits the moves
+    /// tried at the root.
+    fn playout(&mut self, root: &Rc<RefCell<Node>>, searchmoves: &[ChessMove]) -> usize {
+        let mut path = vec![root.clone()];
+        let mut edges: Vec<usize> = Vec::new();
+        let mut on_path: HashSet<u64> = HashSet::new();
+        on_path.insert(root.borrow().board.id());
+
+        // the value for the side to move at the end of the path
+        let mut value = loop {
+            let rnode = path.last().unwrap().clone();
+            let mut node = rnode.borrow_mut();
+            if let Some(value) = node.terminal {
+                break value
+            }
+            if !node.expanded {
+                break self.expand(&mut node)
+            }
+
+            let i = node.select(if path.len() == 1 { searchmoves } else { &[] });
+            edges.push(i);
+            let child = node.edges[i].child.clone();
+            if !on_path.insert(child.borrow().board.id()) {
+                // a repetition, a draw for the child and so for us too
+                break 0.5
+            }
+            path.push(child);
+        };
 
-                // backprop scores and add new nodes to the queue
-                queue.append(
-                    final_nodes.into_iter()
-                        .map(|rnode| {
-                            let node = rnode.borrow();
-
-                            // propagate scores through the parents
-                            for (cm, parent) in node.parents.iter() {
-                                parent.try_borrow_mut().map(|mut n| n.update_score(node.best_score.1, cm, 0));
-                            }
-
-                            (depth + 2, node.board.id())
-                        })
-                        .collect()
-                    );
+        for (i, rnode) in path.iter().enumerate().rev() {
+            let mut node = rnode.borrow_mut();
+            if let Some(&edge) = edges.get(i) {
+                node.edges[edge].visits += 1;
             }
+            node.visits += 1;
+            node.value += value;
+            value = 1.0 - value;
         }
+        edges.len()
     }
 
-    fn unexplored_child_nodes(n: Ref<Node>, depth: u32, visited: &mut HashSet<u64>) -> Vec<(u32, u64)> {
-        if visited.contains(&n.board.id()) {
-            return Vec::new();
-        } else {
-            visited.insert(n.board.id());
+    fn info(&self, root: &Node, searchmoves: &[ChessMove], playouts: u64, seldepth: usize, start: Instant) -> SearchInfo {
+        let mut pv = Vec::new();
+        let mut current = root.most_visited(searchmoves).map(|edge| (edge.chess_move.clone(), edge.child.clone()));
+        while let Some((chess_move, rnode)) = current {
+            pv.push(chess_move);
+            let node = rnode.borrow();
+            current = node.most_visited(&[])
+                .filter(|edge| edge.visits > 0 && pv.len() < seldepth)
+                .map(|edge| (edge.chess_move.clone(), edge.child.clone()));
         }
-
-        let mut res: Vec<(u32, u64)> = Vec::new();
-        for (_, r_child) in n.children[0..cmp::min(40, n.children.len())].to_vec().iter() {
-            r_child.try_borrow().map(|child| {
-                // println!("{}, {}", depth, child.board.id());
-                if child.explored {
-                    res.extend(Manager::unexplored_child_nodes(child, depth + 1, visited));
-                } else {
-                    res.push((depth, child.board.id()));
-                }
-            });
+        let value = root.most_visited(searchmoves).map(|edge| 1.0 - edge.child.borrow().average()).unwrap_or(0.5);
+
+        SearchInfo {
+            depth: pv.len() as u32,
+            seldepth: seldepth as u32,
+            score: centipawns(value),
+            bound: Bound::Exact,
+            nodes: playouts,
+            tbhits: 0,
+            hashfull: (self.nodes.len() * 1000 / self.max_nodes).min(1000) as u32,
+            time: start.elapsed(),
+            pv,
+            multipv: None,
         }
-        res
     }
 
-    fn grow(hm: &mut RefMut<HashMap<u64, Rc<RefCell<Node>>>>, from: &Rc<RefCell<Node>>, cm: &ChessMove, board: Board) -> Rc<RefCell<Node>> {
-        match hm.get(&board.id()) {
-            None => {
-                // create node
-                let bs = board.score();
-                let key = board.id();
-                let mut node = Node::new(board);
-                node.is_valid = true; // technically for now we're only considering legal moves
-                node.best_score.1 = bs;
-                let rnode = Rc::new(RefCell::new(node));
-
-                // insert into hashmap
-                hm.insert(key, rnode.clone());
-
-                // update the parents & children
-                rnode.borrow_mut().parents.push((cm.clone(), from.clone()));
-                from.borrow_mut().children.push((cm.clone(), rnode.clone()));
-
-                return rnode;
-            },
-            Some(rnode) => {
-                // update the parents & children
-                rnode.borrow_mut().parents.push((cm.clone(), from.clone()));
-                from.borrow_mut().children.push((cm.clone(), rnode.clone()));
-
-                return rnode.clone();
+    /// Runs playouts from `board` until the limits are hit: `nodes` counts playouts, the clock
+    /// is used like the alpha-beta search uses it, and with neither there are DEFAULT_PLAYOUTS
+    /// (or no limit at all for go infinite and go ponder, which go on until the stop signal).
+    /// Only searchmoves are tried at the root, if there are any. Depth means nothing here and is
+    /// ignored.
+    pub fn find_best_move<F>(&mut self, board: Board, limits: &SearchLimits, mut report: F) -> std::result::Result<ChessMove, String>
+        where F: FnMut(&SearchInfo) {
+        let start = Instant::now();
+        let budget = limits.time_budget(board.is_whites_move());
+        let playouts = limits.nodes.unwrap_or(if budget.is_some() || limits.infinite || limits.ponder { u64::MAX } else { DEFAULT_PLAYOUTS });
+
+        // whatever was already worked out below this board is still good, only the rest goes
+        self.prune_to(board.id());
+        let root = self.node(board);
+        if !root.borrow().expanded {
+            self.playout(&root, &limits.searchmoves);
+            // a full graph leaves new nodes as leaves, but the root has to have its moves
+            let mut node = root.borrow_mut();
+            if !node.expanded {
+                node.expanded = true;
+                self.link_children(&mut node);
             }
         }
+        if root.borrow().edges.is_empty() {
+            return Err(String::from("no legal moves"))
+        }
 
-        // purposely _don't_ propogate score; caller should do that when necessary
-    }
-}
+        let mut done = 0;
+        let mut seldepth = 0;
+        let mut last_report = start;
+        while done < playouts {
+            seldepth = seldepth.max(self.playout(&root, &limits.searchmoves));
+            done += 1;
+            if budget.is_some_and(|budget| start.elapsed() >= budget) || self.stop_signal.load(Ordering::Relaxed) {
+                break
+            }
+            if last_report.elapsed() >= REPORT_INTERVAL {
+                report(&self.info(&root.borrow(), &limits.searchmoves, done, seldepth, start));
+                last_report = Instant::now();
+            }
+        }
+        report(&self.info(&root.borrow(), &limits.searchmoves, done, seldepth, start));
 
-pub fn find_best_move(board: Board, manager: &mut Manager) -> std::result::Result<ChessMove, String> {
-    manager.clear();
-    manager.find_best_move(board, 50)
+        let root = root.borrow();
+        root.most_visited(&limits.searchmoves)
+            .map(|edge| edge.chess_move.clone())
+            .ok_or_else(|| String::from("none of the searchmoves are legal"))
+    }
 }
 
-#[derive(Clone, Debug)]
-pub struct ChessQueue {
-    queue: Arc<Mutex<VecDeque<(u32, u64)>>>  // (depth, board_id)
-}
 
-impl ChessQueue {
-    pub fn new(initial_board: u64) -> ChessQueue {
-        let mut queue: VecDeque<(u32, u64)> = VecDeque::new();
-        queue.push_back((0, initial_board));
-        ChessQueue{queue: Arc::new(Mutex::new(queue))}
+#[cfg(test)]
+mod tests {
+    use crate::board::ChessMove;
+    use crate::search::SearchLimits;
+    use crate::uci;
+    use crate::v2::{node_bytes, LeafValue, Manager};
+
+    #[test]
+    fn test_finds_mate_and_keeps_the_graph() {
+        let board = uci::position_board(Some("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1"), &[]).unwrap();
+        let limits = SearchLimits { nodes: Some(400), ..Default::default() };
+
+        let mut manager = Manager::new();
+        let mut infos = Vec::new();
+        let best = manager.find_best_move(board.clone(), &limits, |info| infos.push(info.clone())).unwrap();
+        assert_eq!(best, ChessMove::from_long_algebraic_notation("a1a8"));
+        assert_eq!(infos.last().unwrap().nodes, 400);
+        assert!(infos.last().unwrap().score > 1000);
+
+        // going on from a position that was searched keeps what's underneath it
+        let before = manager.len();
+        let child = board.apply_move(&best);
+        manager.prune_to(child.id());
+        assert!(manager.len() < before && !manager.is_empty());
+        manager.prune_to(0);
+        assert!(manager.is_empty());
     }
 
-    pub fn empty() -> ChessQueue {
-        ChessQueue{queue: Arc::new(Mutex::new(VecDeque::new()))}
+    #[test]
+    fn test_searchmoves() {
+        let board = uci::position_board(Some("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1"), &[]).unwrap();
+        let only = vec![ChessMove::from_long_algebraic_notation("g1f1"), ChessMove::from_long_algebraic_notation("a1a2")];
+        let limits = SearchLimits { nodes: Some(200), searchmoves: only.clone(), ..Default::default() };
+
+        let mut manager = Manager::new();
+        let mut infos = Vec::new();
+        let best = manager.find_best_move(board, &limits, |info| infos.push(info.clone())).unwrap();
+        assert!(only.contains(&best), "{}", best.to_long_algebraic_notation());
+        assert!(only.contains(&infos.last().unwrap().pv[0]));
     }
 
-    fn append(&self, boards: Vec<(u32, u64)>) {
-        let mut queue = self.queue.lock().unwrap();
-
-        // let the queue know we're gonna add a bunch of elements
-        queue.reserve(boards.len());
-
-        // add in the elements
-        for elem in boards.into_iter() {
-            queue.push_back(elem);
-        };
+    #[test]
+    fn test_graph_stays_within_hash() {
+        let board = uci::position_board(None, &[]).unwrap();
+        let limits = SearchLimits { nodes: Some(3000), ..Default::default() };
+        let cap = 1024 * 1024 / node_bytes();
+
+        let mut manager = Manager::new();
+        manager.set_hash_size(1);
+        let mut infos = Vec::new();
+        let best = manager.find_best_move(board.clone(), &limits, |info| infos.push(info.clone())).unwrap();
+        assert!(board.clone().get_legal_moves().contains(&best));
+        // the last expansion can go over by a node's worth of children
+        assert!(manager.len() >= cap && manager.len() < cap + 64, "{} nodes for a cap of {}", manager.len(), cap);
+        assert_eq!(infos.last().unwrap().nodes, 3000);
+        assert_eq!(infos.last().unwrap().hashfull, 1000);
     }
 
-    fn grab(&mut self, size: u32) -> Vec<(u32, u64)> {
-        let mut queue = self.queue.lock().unwrap();
-
-        let amount = {
-            if size >= queue.len() as u32 {
-                queue.len()
-            } else {
-                size as usize
-            }
-        };
+    #[test]
+    fn test_rollouts_take_the_queen() {
+        let board = uci::position_board(Some("4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1"), &[]).unwrap();
+        let limits = SearchLimits { nodes: Some(300), ..Default::default() };
 
-        let mut res: Vec<(u32, u64)> = Vec::with_capacity(amount);
-        for elem in queue.drain(0..amount).into_iter() {
-           res.push(elem);
-        }
-
-        res
+        let mut manager = Manager::new();
+        manager.set_leaf_value(LeafValue::Rollout);
+        let best = manager.find_best_move(board, &limits, |_| ()).unwrap();
+        assert_eq!(best, ChessMove::from_long_algebraic_notation("d2d5"));
     }
-
-    fn len(&self) -> usize {
-        self.queue.lock().unwrap().len()
-    }
-}
\ No newline at end of file
+}